@@ -1,11 +1,137 @@
-use std::path::PathBuf;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Directory packed into the initrd, unpacked at `/` by the kernel.
+const INITRD_DIR: &str = "initrd";
+/// Size of the blank disk attached next to the boot disk, for drivers to play with.
+const DATA_DISK_SIZE: u64 = 16 * 1024 * 1024;
 
 fn main() {
     let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
+
+    println!("cargo:rerun-if-changed={INITRD_DIR}");
+    let initrd_path = out_dir.join("initrd.tar");
+    pack_initrd(Path::new(INITRD_DIR), &initrd_path).unwrap();
+
     let bios_path = out_dir.join("bios.img");
     bootloader::BiosBoot::new(&kernel)
+        .set_ramdisk(&initrd_path)
         .create_disk_image(&bios_path)
         .unwrap();
     println!("cargo:rustc-env=BIOS_PATH={}", bios_path.display());
+
+    // kept across builds, as the kernel may have written to it
+    let data_path = out_dir.join("data.img");
+    if !data_path.exists() {
+        fs::File::create(&data_path)
+            .and_then(|file| file.set_len(DATA_DISK_SIZE))
+            .unwrap();
+    }
+    println!("cargo:rustc-env=DATA_PATH={}", data_path.display());
+}
+
+/// Writes every file, directory and symbolic link below `dir` as an ustar archive.
+fn pack_initrd(dir: &Path, out: &Path) -> io::Result<()> {
+    let mut archive = Vec::new();
+    if dir.is_dir() {
+        pack_dir(dir, "", &mut archive)?;
+    }
+    // two zero blocks end the archive
+    archive.extend_from_slice(&[0; 1024]);
+    fs::File::create(out)?.write_all(&archive)
+}
+
+fn pack_dir(dir: &Path, prefix: &str, archive: &mut Vec<u8>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    // sorted so the image doesn't change with the directory order
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        println!("cargo:rerun-if-changed={}", path.display());
+        let metadata = fs::symlink_metadata(&path)?;
+        let mode = permissions(&metadata);
+        if metadata.is_symlink() {
+            let target = fs::read_link(&path)?;
+            write_header(archive, &name, b'2', mode, 0, &target.to_string_lossy())?;
+        } else if metadata.is_dir() {
+            write_header(archive, &format!("{name}/"), b'5', mode, 0, "")?;
+            pack_dir(&path, &format!("{name}/"), archive)?;
+        } else {
+            let data = fs::read(&path)?;
+            write_header(archive, &name, b'0', mode, data.len() as u64, "")?;
+            archive.extend_from_slice(&data);
+            archive.resize(archive.len().next_multiple_of(512), 0);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+fn write_header(
+    archive: &mut Vec<u8>,
+    name: &str,
+    kind: u8,
+    mode: u32,
+    size: u64,
+    link: &str,
+) -> io::Result<()> {
+    let too_long = |what| io::Error::new(io::ErrorKind::InvalidInput, format!("{what} too long"));
+    let mut header = [0u8; 512];
+    // names that don't fit are split between the prefix and name fields
+    let (prefix, name) = match name.len() {
+        0..=100 => ("", name),
+        _ => {
+            let split = name[..name.len() - 1]
+                .rfind('/')
+                .filter(|&i| i <= 155 && name.len() - i - 1 <= 100)
+                .ok_or_else(|| too_long(name.to_string()))?;
+            (&name[..split], &name[split + 1..])
+        }
+    };
+    if link.len() > 100 {
+        return Err(too_long(link.to_string()));
+    }
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], mode as u64);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], 0);
+    header[156] = kind;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // computed with the checksum field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    write_octal(&mut header[148..155], checksum as u64);
+    archive.extend_from_slice(&header);
+    Ok(())
+}
+
+/// Writes `value` as zero padded octal digits followed by a NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}\0", width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
 }
@@ -1,16 +1,56 @@
 use x86_64::{
+    instructions::interrupts,
     structures::paging::{
         mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
 
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 use linked_list_allocator::LockedHeap;
 #[global_allocator]
-pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static ALLOCATOR: KernelHeap = KernelHeap(LockedHeap::empty());
+
+/// Takes the heap lock with interrupts disabled, so neither an interrupt handler nor a preempting
+/// thread can find it held and spin forever.
+pub struct KernelHeap(pub LockedHeap);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
 
 pub const HEAP_START: usize = 0x0_4444_4444_0000;
-pub const HEAP_SIZE: usize = 1024 * 1024;
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+/// Set once the heap can be allocated from, for code that runs before it too.
+pub static HEAP_READY: AtomicBool = AtomicBool::new(false);
+
+/// Bytes of the kernel heap.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.0.lock();
+        HeapStats {
+            size: heap.size(),
+            used: heap.used(),
+        }
+    })
+}
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -32,7 +72,8 @@ pub fn init_heap(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+        ALLOCATOR.0.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
     }
+    HEAP_READY.store(true, Ordering::Release);
     Ok(())
 }
@@ -0,0 +1,385 @@
+use alloc::{format, string::String, sync::Arc};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use x86_64::instructions::port::Port;
+
+use crate::{
+    block::{self, BlockDevice, BlockError, SECTOR_SIZE},
+    info,
+    ints::{self, IntIndex, PICS},
+    okay,
+    task::WaitQueue,
+};
+
+// registers, as offsets from the I/O base of a channel
+const DATA: u16 = 0;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE: u16 = 6;
+const STATUS: u16 = 7;
+const COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+/// Bit of the control register masking the interrupts of the channel.
+const CONTROL_NIEN: u8 = 1 << 1;
+
+const CMD_READ: u8 = 0x20;
+const CMD_READ_EXT: u8 = 0x24;
+const CMD_WRITE: u8 = 0x30;
+const CMD_WRITE_EXT: u8 = 0x34;
+const CMD_FLUSH: u8 = 0xe7;
+const CMD_FLUSH_EXT: u8 = 0xea;
+const CMD_IDENTIFY: u8 = 0xec;
+
+/// Sectors reachable with 28 bit addresses.
+const LBA28_LIMIT: u64 = 1 << 28;
+/// Time the drive gets to raise its interrupt.
+const TIMEOUT_MS: u64 = 5000;
+/// Status polls before giving up, during identification only.
+const POLL_LIMIT: usize = 1_000_000;
+
+/// One of the two legacy IDE channels, each with a master and a slave drive.
+struct Channel {
+    base: u16,
+    control: u16,
+    /// Serializes commands, as the drives of a channel share its registers.
+    lock: spin::Mutex<()>,
+    /// Set by the interrupt handler, along with the status it read.
+    irq_fired: AtomicBool,
+    irq_status: AtomicU8,
+    irq_done: WaitQueue,
+}
+
+static PRIMARY: Channel = Channel::new(0x1f0, 0x3f6);
+static SECONDARY: Channel = Channel::new(0x170, 0x376);
+
+impl Channel {
+    const fn new(base: u16, control: u16) -> Self {
+        Self {
+            base,
+            control,
+            lock: spin::Mutex::new(()),
+            irq_fired: AtomicBool::new(false),
+            irq_status: AtomicU8::new(0),
+            irq_done: WaitQueue::new(),
+        }
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    /// The status without acknowledging an interrupt.
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::new(self.control).read() }
+    }
+
+    fn set_interrupts(&self, enabled: bool) {
+        let value = if enabled { 0 } else { CONTROL_NIEN };
+        unsafe { Port::new(self.control).write(value) }
+    }
+
+    /// Gives the drive the 400ns it needs to update its status after a command or selection.
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    fn select(&self, slave: bool, lba_bits: u8) {
+        self.write(DRIVE, 0xe0 | (slave as u8) << 4 | lba_bits);
+        self.delay();
+    }
+
+    /// Busy waits for the drive to finish, then for data if `data` is set.
+    fn poll(&self, data: bool) -> Result<(), BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.alt_status();
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(BlockError::Io);
+            }
+            if !data || status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Timeout)
+    }
+
+    /// Called before a command, so that only the interrupt it raises counts.
+    fn arm(&self) {
+        self.irq_fired.store(false, Ordering::Release);
+    }
+
+    /// Sleeps until the interrupt announcing the next sector or the end of the command.
+    fn wait_irq(&self) -> Result<(), BlockError> {
+        let status = self
+            .irq_done
+            .wait_until_timeout(ints::ms_to_ticks(TIMEOUT_MS), || {
+                self.irq_fired
+                    .swap(false, Ordering::AcqRel)
+                    .then(|| self.irq_status.load(Ordering::Acquire))
+            })
+            .ok_or(BlockError::Timeout)?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    fn handle_interrupt(&self) {
+        // reading the status acknowledges the interrupt
+        self.irq_status.store(self.read(STATUS), Ordering::Release);
+        self.irq_fired.store(true, Ordering::Release);
+        self.irq_done.notify_all();
+    }
+
+    /// Loads the address registers and issues `command` for `count` sectors from `lba`.
+    fn command(&self, slave: bool, lba48: bool, lba: u64, count: u32, command: u8) {
+        if lba48 {
+            self.select(slave, 0);
+            // high bytes first, as each register is a two byte FIFO
+            self.write(SECTOR_COUNT, (count >> 8) as u8);
+            self.write(LBA_LOW, (lba >> 24) as u8);
+            self.write(LBA_MID, (lba >> 32) as u8);
+            self.write(LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            self.select(slave, (lba >> 24) as u8 & 0xf);
+        }
+        self.write(SECTOR_COUNT, count as u8);
+        self.write(LBA_LOW, lba as u8);
+        self.write(LBA_MID, (lba >> 8) as u8);
+        self.write(LBA_HIGH, (lba >> 16) as u8);
+        self.arm();
+        self.write(COMMAND, command);
+    }
+
+    fn read_words(&self, buf: &mut [u8]) {
+        let mut port = Port::<u16>::new(self.base + DATA);
+        for chunk in buf.chunks_exact_mut(2) {
+            chunk.copy_from_slice(&unsafe { port.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_words(&self, buf: &[u8]) {
+        let mut port = Port::<u16>::new(self.base + DATA);
+        for chunk in buf.chunks_exact(2) {
+            unsafe { port.write(u16::from_le_bytes([chunk[0], chunk[1]])) };
+        }
+    }
+}
+
+/// A hard disk on one of the IDE channels, driven with programmed I/O.
+pub struct AtaDrive {
+    name: String,
+    channel: &'static Channel,
+    slave: bool,
+    lba48: bool,
+    sectors: u64,
+    model: String,
+    serial: String,
+}
+
+impl AtaDrive {
+    /// Asks the drive for its parameters, returning `None` if there is no ATA disk there.
+    fn identify(name: String, channel: &'static Channel, slave: bool) -> Option<Self> {
+        let _guard = channel.lock.lock();
+        // polled with the interrupt of the channel masked
+        channel.set_interrupts(false);
+        let data = Self::read_identify(channel, slave);
+        channel.set_interrupts(true);
+        let data = data?;
+
+        let word = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+        let lba48 = word(83) & (1 << 10) != 0;
+        let sectors = if lba48 {
+            (0..4).map(|i| (word(100 + i) as u64) << (16 * i)).sum()
+        } else {
+            word(60) as u64 | (word(61) as u64) << 16
+        };
+        Some(Self {
+            name,
+            channel,
+            slave,
+            lba48,
+            sectors,
+            model: identify_string(&data[54..94]),
+            serial: identify_string(&data[20..40]),
+        })
+    }
+
+    fn read_identify(channel: &Channel, slave: bool) -> Option<[u8; SECTOR_SIZE]> {
+        channel.select(slave, 0);
+        // a floating bus reads as all ones
+        if channel.alt_status() == 0xff {
+            return None;
+        }
+        channel.write(SECTOR_COUNT, 0);
+        channel.write(LBA_LOW, 0);
+        channel.write(LBA_MID, 0);
+        channel.write(LBA_HIGH, 0);
+        channel.write(COMMAND, CMD_IDENTIFY);
+        channel.delay();
+        if channel.read(STATUS) == 0 {
+            return None;
+        }
+        channel.poll(false).ok()?;
+        // ATAPI and SATA devices abort and leave their signature here
+        if channel.read(LBA_MID) != 0 || channel.read(LBA_HIGH) != 0 {
+            return None;
+        }
+        channel.poll(true).ok()?;
+        let mut data = [0; SECTOR_SIZE];
+        channel.read_words(&mut data);
+        Some(data)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// Largest number of sectors a single command can move.
+    fn max_sectors(&self) -> usize {
+        if self.lba48 {
+            65536
+        } else {
+            256
+        }
+    }
+
+    /// Whether a command needs the 48 bit form, which is avoided as it takes twice the writes.
+    fn use_lba48(&self, lba: u64, count: usize) -> bool {
+        self.lba48 && (lba + count as u64 > LBA28_LIMIT || count > 256)
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        let channel = self.channel;
+        let _guard = channel.lock.lock();
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(self.max_sectors() * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            let lba48 = self.use_lba48(lba, count);
+            let command = if lba48 { CMD_READ_EXT } else { CMD_READ };
+            // a count of zero means the largest one
+            channel.command(self.slave, lba48, lba, count as u32, command);
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                channel.wait_irq()?;
+                channel.read_words(sector);
+            }
+            lba += count as u64;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        let channel = self.channel;
+        let _guard = channel.lock.lock();
+        let mut lba = lba;
+        for chunk in buf.chunks(self.max_sectors() * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            let lba48 = self.use_lba48(lba, count);
+            let command = if lba48 { CMD_WRITE_EXT } else { CMD_WRITE };
+            channel.command(self.slave, lba48, lba, count as u32, command);
+            // the first sector is expected without an interrupt
+            channel.poll(true)?;
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                channel.write_words(sector);
+                channel.wait_irq()?;
+            }
+            lba += count as u64;
+        }
+        Ok(())
+    }
+
+    /// Makes the drive write its own cache to the disk.
+    fn flush(&self) -> Result<(), BlockError> {
+        let channel = self.channel;
+        let _guard = channel.lock.lock();
+        channel.select(self.slave, 0);
+        channel.arm();
+        let command = if self.lba48 { CMD_FLUSH_EXT } else { CMD_FLUSH };
+        channel.write(COMMAND, command);
+        channel.wait_irq()
+    }
+}
+
+/// Text of the identify data, stored with the bytes of each word swapped.
+fn identify_string(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for pair in bytes.chunks_exact(2) {
+        text.push(pair[1] as char);
+        text.push(pair[0] as char);
+    }
+    text.trim().into()
+}
+
+/// Acknowledges the interrupt of the channel on `irq`, called from its interrupt handler.
+pub fn handle_interrupt(irq: IntIndex) {
+    match irq {
+        IntIndex::PrimaryAta => PRIMARY.handle_interrupt(),
+        IntIndex::SecondaryAta => SECONDARY.handle_interrupt(),
+        _ => {}
+    }
+}
+
+/// Finds the disks of both channels and registers them as `hda` to `hdd`.
+pub fn init() {
+    info!("probing ata drives");
+    // the slave PIC is cascaded on line 2, and the channels use lines 14 and 15
+    unsafe {
+        let mut pics = PICS.lock();
+        let [master, slave] = pics.read_masks();
+        pics.write_masks(master & !(1 << 2), slave & !(1 << 6 | 1 << 7));
+    }
+    let drives = [
+        (&PRIMARY, false),
+        (&PRIMARY, true),
+        (&SECONDARY, false),
+        (&SECONDARY, true),
+    ];
+    let mut found = 0;
+    for (i, (channel, slave)) in drives.into_iter().enumerate() {
+        let name = format!("hd{}", (b'a' + i as u8) as char);
+        let Some(drive) = AtaDrive::identify(name, channel, slave) else {
+            continue;
+        };
+        info!(
+            "{}: {} ({} sectors{})",
+            drive.name,
+            drive.model,
+            drive.sectors,
+            if drive.lba48 { ", lba48" } else { "" }
+        );
+        block::register(Arc::new(drive));
+        found += 1;
+    }
+    okay!("found {found} ata drives");
+}
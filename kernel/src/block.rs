@@ -0,0 +1,123 @@
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use crate::syscall::Errno;
+
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request goes past the last sector.
+    OutOfRange,
+    /// The buffer isn't a whole number of sectors.
+    BadBuffer,
+    /// The device reported an error.
+    Io,
+    /// The device didn't answer in time.
+    Timeout,
+}
+
+impl From<BlockError> for Errno {
+    fn from(value: BlockError) -> Self {
+        match value {
+            BlockError::OutOfRange | BlockError::BadBuffer => Errno::Inval,
+            BlockError::Io | BlockError::Timeout => Errno::Io,
+        }
+    }
+}
+
+/// Storage addressed by fixed size sectors.
+pub trait BlockDevice: Send + Sync {
+    /// Name under which the device is registered, as in `hda`.
+    fn name(&self) -> &str;
+
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64;
+
+    /// Reads the sectors starting at `lba` into `buf`, a multiple of the sector size.
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Waits until everything written is stored by the device itself.
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.sector_count() * self.sector_size() as u64
+    }
+
+    /// Reads bytes at any `offset`, going through whole sectors.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let sector_size = self.sector_size();
+        let mut sector = alloc::vec![0; sector_size];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let lba = pos / sector_size as u64;
+            let start = (pos % sector_size as u64) as usize;
+            let len = (sector_size - start).min(buf.len() - done);
+            self.read_sectors(lba, &mut sector)?;
+            buf[done..done + len].copy_from_slice(&sector[start..start + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes bytes at any `offset`, reading the sectors only partly overwritten first.
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let sector_size = self.sector_size();
+        let mut sector = alloc::vec![0; sector_size];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let lba = pos / sector_size as u64;
+            let start = (pos % sector_size as u64) as usize;
+            let len = (sector_size - start).min(buf.len() - done);
+            if len < sector_size {
+                self.read_sectors(lba, &mut sector)?;
+            }
+            sector[start..start + len].copy_from_slice(&buf[done..done + len]);
+            self.write_sectors(lba, &sector)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Checks that `len` bytes from `lba` are whole sectors on the device, returning how many.
+    fn check_range(&self, lba: u64, len: usize) -> Result<u64, BlockError> {
+        if len % self.sector_size() != 0 {
+            return Err(BlockError::BadBuffer);
+        }
+        let count = (len / self.sector_size()) as u64;
+        match lba.checked_add(count) {
+            Some(end) if end <= self.sector_count() => Ok(count),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+}
+
+pub type BlockDeviceRef = Arc<dyn BlockDevice>;
+
+static DEVICES: spin::Mutex<BTreeMap<String, BlockDeviceRef>> = spin::Mutex::new(BTreeMap::new());
+
+/// Makes `device` reachable by its name, replacing any device of the same name.
+pub fn register(device: BlockDeviceRef) {
+    DEVICES.lock().insert(device.name().into(), device);
+}
+
+/// Makes the device `name` unreachable, returning it. Users holding it can keep using it.
+pub fn unregister(name: &str) -> Option<BlockDeviceRef> {
+    DEVICES.lock().remove(name)
+}
+
+pub fn get(name: &str) -> Option<BlockDeviceRef> {
+    DEVICES.lock().get(name).cloned()
+}
+
+pub fn list() -> Vec<BlockDeviceRef> {
+    DEVICES.lock().values().cloned().collect()
+}
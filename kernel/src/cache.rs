@@ -0,0 +1,200 @@
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+
+use crate::block::{BlockDevice, BlockDeviceRef, BlockError};
+
+/// Sectors kept by a cache unless asked otherwise.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+struct Buffer {
+    data: Vec<u8>,
+    /// Written to since read from the device.
+    dirty: bool,
+    /// Value of the cache clock at the last access, for LRU eviction.
+    last_used: u64,
+}
+
+struct State {
+    buffers: BTreeMap<u64, Buffer>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub cached: usize,
+    pub dirty: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Keeps the recently used sectors of a device in memory. Writes stay in the cache until their
+/// sector is evicted or the cache synced.
+pub struct BufferCache {
+    name: String,
+    device: BlockDeviceRef,
+    capacity: usize,
+    state: spin::Mutex<State>,
+}
+
+/// Every cache alive, for [`sync_all`].
+static CACHES: spin::Mutex<Vec<Weak<BufferCache>>> = spin::Mutex::new(Vec::new());
+
+impl BufferCache {
+    /// Caches up to `capacity` sectors of `device`.
+    pub fn new(device: BlockDeviceRef, capacity: usize) -> Arc<Self> {
+        assert!(capacity > 0, "caches need room for a sector");
+        let cache = Arc::new(Self {
+            name: device.name().into(),
+            device,
+            capacity,
+            state: spin::Mutex::new(State {
+                buffers: BTreeMap::new(),
+                clock: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        });
+        let mut caches = CACHES.lock();
+        caches.retain(|cache| cache.strong_count() > 0);
+        caches.push(Arc::downgrade(&cache));
+        cache
+    }
+
+    pub fn device(&self) -> &BlockDeviceRef {
+        &self.device
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock();
+        CacheStats {
+            cached: state.buffers.len(),
+            dirty: state.buffers.values().filter(|b| b.dirty).count(),
+            hits: state.hits,
+            misses: state.misses,
+        }
+    }
+
+    /// Runs `f` on the cached copy of sector `lba`, reading it first if needed.
+    fn with_buffer<T>(
+        &self,
+        lba: u64,
+        load: bool,
+        f: impl FnOnce(&mut Buffer) -> T,
+    ) -> Result<T, BlockError> {
+        let mut state = self.state.lock();
+        state.clock += 1;
+        let clock = state.clock;
+        if state.buffers.contains_key(&lba) {
+            state.hits += 1;
+        } else {
+            state.misses += 1;
+            if state.buffers.len() >= self.capacity {
+                self.evict(&mut state)?;
+            }
+            let mut data = vec![0; self.device.sector_size()];
+            // sectors about to be overwritten whole aren't worth reading
+            if load {
+                self.device.read_sectors(lba, &mut data)?;
+            }
+            let buffer = Buffer {
+                data,
+                dirty: false,
+                last_used: clock,
+            };
+            state.buffers.insert(lba, buffer);
+        }
+        let buffer = state.buffers.get_mut(&lba).unwrap();
+        buffer.last_used = clock;
+        Ok(f(buffer))
+    }
+
+    /// Drops the least recently used sector, writing it back if needed.
+    fn evict(&self, state: &mut State) -> Result<(), BlockError> {
+        let Some((&lba, _)) = state.buffers.iter().min_by_key(|(_, b)| b.last_used) else {
+            return Ok(());
+        };
+        let buffer = &state.buffers[&lba];
+        if buffer.dirty {
+            self.device.write_sectors(lba, &buffer.data)?;
+        }
+        state.buffers.remove(&lba);
+        Ok(())
+    }
+
+    /// Writes every dirty sector back, in order, then flushes the device.
+    pub fn sync(&self) -> Result<(), BlockError> {
+        let mut state = self.state.lock();
+        for (&lba, buffer) in state.buffers.iter_mut().filter(|(_, b)| b.dirty) {
+            self.device.write_sectors(lba, &buffer.data)?;
+            buffer.dirty = false;
+        }
+        drop(state);
+        self.device.flush()
+    }
+
+    /// Syncs, then forgets every sector, as when the device changed behind the cache.
+    pub fn invalidate(&self) -> Result<(), BlockError> {
+        self.sync()?;
+        self.state.lock().buffers.clear();
+        Ok(())
+    }
+}
+
+impl BlockDevice for BufferCache {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.device.sector_count()
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        for (i, sector) in buf.chunks_exact_mut(self.sector_size()).enumerate() {
+            self.with_buffer(lba + i as u64, true, |b| sector.copy_from_slice(&b.data))?;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        for (i, sector) in buf.chunks_exact(self.sector_size()).enumerate() {
+            self.with_buffer(lba + i as u64, false, |b| {
+                b.data.copy_from_slice(sector);
+                b.dirty = true;
+            })?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.sync()
+    }
+}
+
+impl Drop for BufferCache {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+/// Writes back the dirty sectors of every cache, as before shutting down.
+pub fn sync_all() -> Result<(), BlockError> {
+    let caches: Vec<_> = CACHES.lock().iter().filter_map(Weak::upgrade).collect();
+    for cache in caches {
+        cache.sync()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,293 @@
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+
+use x86_64::instructions::interrupts;
+
+use crate::{signal, syscall::Errno, task::WaitQueue};
+
+/// Largest message user programs can send.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+/// Largest capacity of the channels created by user programs.
+pub const MAX_USER_CAPACITY: usize = 256;
+/// Highest number of handles a process can have open.
+pub const MAX_HANDLES: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelError {
+    /// Every endpoint on the other side was dropped.
+    Disconnected,
+    /// A non blocking send found no room.
+    Full,
+    /// A non blocking receive found no message.
+    Empty,
+    Timeout,
+    /// The running process got a signal while waiting.
+    Interrupted,
+}
+
+impl From<ChannelError> for Errno {
+    fn from(value: ChannelError) -> Self {
+        match value {
+            ChannelError::Disconnected => Errno::Pipe,
+            ChannelError::Full | ChannelError::Empty => Errno::Again,
+            ChannelError::Timeout => Errno::TimedOut,
+            ChannelError::Interrupted => Errno::Intr,
+        }
+    }
+}
+
+/// A failed send, giving the message back.
+#[derive(Debug)]
+pub struct SendError<T> {
+    pub error: ChannelError,
+    pub message: T,
+}
+
+struct State<T> {
+    messages: VecDeque<T>,
+    senders: usize,
+    receivers: usize,
+}
+
+struct Channel<T> {
+    /// Only locked with interrupts disabled, as interrupt handlers send too.
+    state: spin::Mutex<State<T>>,
+    capacity: usize,
+    not_empty: WaitQueue,
+    not_full: WaitQueue,
+}
+
+/// How long an operation may block.
+#[derive(Clone, Copy)]
+enum Wait {
+    Never,
+    Ticks(u128),
+    Forever,
+}
+
+impl<T> Channel<T> {
+    /// Runs `attempt` until it returns something, as long as `wait` allows.
+    fn wait<R>(
+        &self,
+        queue: &WaitQueue,
+        wait: Wait,
+        mut attempt: impl FnMut(&mut State<T>) -> Option<R>,
+    ) -> Result<Option<R>, ChannelError> {
+        let cond = || attempt(&mut self.state.lock());
+        match wait {
+            Wait::Never => Ok(interrupts::without_interrupts(cond)),
+            Wait::Ticks(ticks) => signal::wait_timeout(queue, ticks, cond),
+            Wait::Forever => signal::wait(queue, cond).map(Some),
+        }
+        .map_err(|_| ChannelError::Interrupted)
+    }
+}
+
+/// The sending side of a channel, cloned for every producer.
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+/// The receiving side of a channel, cloned for every consumer.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+/// Creates a channel holding up to `capacity` messages before senders block.
+pub fn channel<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channels need room for a message");
+    let channel = Arc::new(Channel {
+        state: spin::Mutex::new(State {
+            messages: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receivers: 1,
+        }),
+        capacity,
+        not_empty: WaitQueue::new(),
+        not_full: WaitQueue::new(),
+    });
+    let sender = Sender {
+        channel: channel.clone(),
+    };
+    (sender, Receiver { channel })
+}
+
+impl<T> Sender<T> {
+    /// Blocks until there is room for `message`.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.send_with(message, Wait::Forever)
+    }
+
+    /// Like [`Sender::send`], but gives up after `timeout` ticks.
+    pub fn send_timeout(&self, message: T, timeout: u128) -> Result<(), SendError<T>> {
+        self.send_with(message, Wait::Ticks(timeout))
+    }
+
+    /// Sends `message` only if there is room right away. Can be called from interrupt handlers.
+    pub fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        self.send_with(message, Wait::Never)
+    }
+
+    fn send_with(&self, message: T, wait: Wait) -> Result<(), SendError<T>> {
+        let channel = &self.channel;
+        let mut message = Some(message);
+        let result = channel.wait(&channel.not_full, wait, |state| {
+            if state.receivers == 0 {
+                return Some(Err(ChannelError::Disconnected));
+            }
+            if state.messages.len() >= channel.capacity {
+                return None;
+            }
+            state.messages.push_back(message.take().unwrap());
+            Some(Ok(()))
+        });
+        let error = match result {
+            Ok(Some(Ok(()))) => {
+                channel.not_empty.notify_one();
+                return Ok(());
+            }
+            Ok(Some(Err(error))) | Err(error) => error,
+            Ok(None) if matches!(wait, Wait::Never) => ChannelError::Full,
+            Ok(None) => ChannelError::Timeout,
+        };
+        Err(SendError {
+            error,
+            message: message.unwrap(),
+        })
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Blocks until a message arrives, failing once the channel is empty and every sender gone.
+    pub fn receive(&self) -> Result<T, ChannelError> {
+        self.receive_with(Wait::Forever)
+    }
+
+    /// Like [`Receiver::receive`], but gives up after `timeout` ticks.
+    pub fn receive_timeout(&self, timeout: u128) -> Result<T, ChannelError> {
+        self.receive_with(Wait::Ticks(timeout))
+    }
+
+    pub fn try_receive(&self) -> Result<T, ChannelError> {
+        self.receive_with(Wait::Never)
+    }
+
+    fn receive_with(&self, wait: Wait) -> Result<T, ChannelError> {
+        let channel = &self.channel;
+        let result = channel.wait(&channel.not_empty, wait, |state| {
+            match state.messages.pop_front() {
+                Some(message) => Some(Ok(message)),
+                None if state.senders == 0 => Some(Err(ChannelError::Disconnected)),
+                None => None,
+            }
+        })?;
+        match result {
+            Some(Ok(message)) => {
+                channel.not_full.notify_one();
+                Ok(message)
+            }
+            Some(Err(error)) => Err(error),
+            None if matches!(wait, Wait::Never) => Err(ChannelError::Empty),
+            None => Err(ChannelError::Timeout),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        interrupts::without_interrupts(|| self.channel.state.lock().senders += 1);
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        interrupts::without_interrupts(|| self.channel.state.lock().receivers += 1);
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let last = interrupts::without_interrupts(|| {
+            let mut state = self.channel.state.lock();
+            state.senders -= 1;
+            state.senders == 0
+        });
+        if last {
+            self.channel.not_empty.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let last = interrupts::without_interrupts(|| {
+            let mut state = self.channel.state.lock();
+            state.receivers -= 1;
+            state.receivers == 0
+        });
+        if last {
+            self.channel.not_full.notify_all();
+        }
+    }
+}
+
+/// Messages exchanged by user programs.
+pub type Message = Vec<u8>;
+
+/// A channel endpoint owned by a process.
+#[derive(Clone)]
+pub enum Endpoint {
+    Sender(Sender<Message>),
+    Receiver(Receiver<Message>),
+}
+
+/// The channel endpoints of a process, indexed by handle.
+#[derive(Clone, Default)]
+pub struct HandleTable {
+    handles: Vec<Option<Endpoint>>,
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self {
+            handles: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, handle: usize) -> Result<Endpoint, Errno> {
+        self.handles
+            .get(handle)
+            .and_then(|h| h.clone())
+            .ok_or(Errno::BadFd)
+    }
+
+    /// Stores `endpoint` in the lowest free handle.
+    pub fn insert(&mut self, endpoint: Endpoint) -> Result<usize, Errno> {
+        match self.handles.iter().position(|h| h.is_none()) {
+            Some(handle) => {
+                self.handles[handle] = Some(endpoint);
+                Ok(handle)
+            }
+            None if self.handles.len() < MAX_HANDLES => {
+                self.handles.push(Some(endpoint));
+                Ok(self.handles.len() - 1)
+            }
+            None => Err(Errno::MFile),
+        }
+    }
+
+    pub fn close(&mut self, handle: usize) -> Result<(), Errno> {
+        let slot = self.handles.get_mut(handle).ok_or(Errno::BadFd)?;
+        slot.take().ok_or(Errno::BadFd)?;
+        Ok(())
+    }
+
+    pub fn close_all(&mut self) {
+        self.handles.clear();
+    }
+}
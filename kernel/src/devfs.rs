@@ -0,0 +1,201 @@
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use crate::{
+    block::{self, BlockDeviceRef},
+    device::{self, CharDeviceRef},
+    info, ints, okay,
+    vfs::{self, DirEntry, FileSystem, FileType, FsError, Inode, InodeRef, Metadata},
+    warn,
+};
+
+const ROOT_INO: u64 = 1;
+
+/// The registered devices as nodes of a single directory, always listing the current ones.
+pub struct DevFs {
+    root: Arc<DevDir>,
+}
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: Arc::new(DevDir {
+                inos: spin::Mutex::new(BTreeMap::new()),
+                created: ints::uptime_secs(),
+            }),
+        })
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+}
+
+struct DevDir {
+    /// Inode numbers given to device names, kept when a device is registered again.
+    inos: spin::Mutex<BTreeMap<String, u64>>,
+    created: u64,
+}
+
+impl DevDir {
+    fn ino(&self, name: &str) -> u64 {
+        let mut inos = self.inos.lock();
+        let next = ROOT_INO + 1 + inos.len() as u64;
+        *inos.entry(name.into()).or_insert(next)
+    }
+
+    fn node(&self, device: Device) -> Arc<DevNode> {
+        Arc::new(DevNode {
+            ino: self.ino(device.name()),
+            device,
+            created: self.created,
+        })
+    }
+
+    /// Every device, char devices first.
+    fn devices(&self) -> Vec<Device> {
+        let chars = device::list().into_iter().map(Device::Char);
+        let blocks = block::list().into_iter().map(Device::Block);
+        chars.chain(blocks).collect()
+    }
+}
+
+impl Inode for DevDir {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: ROOT_INO,
+            kind: FileType::Directory,
+            size: self.devices().len() as u64,
+            mode: 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            atime: self.created,
+            mtime: self.created,
+            ctime: self.created,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef, FsError> {
+        let device = device::get(name)
+            .map(Device::Char)
+            .or_else(|| block::get(name).map(Device::Block))
+            .ok_or(FsError::NotFound)?;
+        Ok(self.node(device))
+    }
+
+    fn create(&self, _name: &str, _kind: FileType, _mode: u16) -> Result<InodeRef, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<InodeRef, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .devices()
+            .into_iter()
+            .map(|device| DirEntry {
+                name: device.name().into(),
+                ino: self.ino(device.name()),
+                kind: device.kind(),
+            })
+            .collect())
+    }
+}
+
+enum Device {
+    Char(CharDeviceRef),
+    Block(BlockDeviceRef),
+}
+
+impl Device {
+    fn name(&self) -> &str {
+        match self {
+            Device::Char(device) => device.name(),
+            Device::Block(device) => device.name(),
+        }
+    }
+
+    fn kind(&self) -> FileType {
+        match self {
+            Device::Char(_) => FileType::CharDevice,
+            Device::Block(_) => FileType::BlockDevice,
+        }
+    }
+}
+
+struct DevNode {
+    ino: u64,
+    device: Device,
+    created: u64,
+}
+
+impl Inode for DevNode {
+    fn metadata(&self) -> Metadata {
+        let (size, mode) = match &self.device {
+            Device::Char(device) => (device.size(), device.mode()),
+            Device::Block(device) => (device.size(), 0o660),
+        };
+        Metadata {
+            ino: self.ino,
+            kind: self.device.kind(),
+            size,
+            mode,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: self.created,
+            mtime: self.created,
+            ctime: self.created,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match &self.device {
+            Device::Char(device) => device.read_at(offset, buf),
+            Device::Block(device) => {
+                let len = device.size().saturating_sub(offset).min(buf.len() as u64) as usize;
+                device.read_at(offset, &mut buf[..len])?;
+                Ok(len)
+            }
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        match &self.device {
+            Device::Char(device) => device.write_at(offset, buf),
+            Device::Block(device) => {
+                let len = device.size().saturating_sub(offset).min(buf.len() as u64) as usize;
+                if len == 0 && !buf.is_empty() {
+                    return Err(FsError::NoSpace);
+                }
+                device.write_at(offset, &buf[..len])?;
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// Mounts a devfs on `/dev`, creating the directory when missing.
+pub fn init() {
+    info!("mounting devfs");
+    match vfs::mount_at("/dev", 0o755, DevFs::new()) {
+        Ok(()) => okay!("mounted devfs"),
+        Err(err) => warn!("failed to mount devfs: {err:?}"),
+    }
+}
@@ -0,0 +1,186 @@
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use x86_64::instructions::interrupts;
+
+use crate::{info, okay, vfs::FsError};
+
+/// Most sinks kernel messages can go to at once.
+const MAX_CONSOLES: usize = 4;
+
+/// A device read and written as a stream of bytes rather than by sectors.
+pub trait CharDevice: Send + Sync {
+    /// Name under which the device is registered, as in `ttyS0`.
+    fn name(&self) -> &str;
+
+    /// Reads at `offset`, which devices that aren't seekable ignore.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Bytes in the device, 0 for streams.
+    fn size(&self) -> u64 {
+        0
+    }
+
+    /// Permission bits of the device node.
+    fn mode(&self) -> u16 {
+        0o666
+    }
+}
+
+pub type CharDeviceRef = Arc<dyn CharDevice>;
+
+/// Shows kernel messages, colored by escape sequences, without using the heap as it can run
+/// before it exists.
+pub type ConsoleWriter = fn(fmt::Arguments);
+
+static DEVICES: spin::Mutex<BTreeMap<String, CharDeviceRef>> = spin::Mutex::new(BTreeMap::new());
+static CONSOLES: spin::Mutex<[Option<ConsoleWriter>; MAX_CONSOLES]> =
+    spin::Mutex::new([None; MAX_CONSOLES]);
+
+/// Makes `device` reachable by its name, replacing any device of the same name.
+pub fn register(device: CharDeviceRef) {
+    interrupts::without_interrupts(|| DEVICES.lock().insert(device.name().into(), device));
+}
+
+pub fn get(name: &str) -> Option<CharDeviceRef> {
+    interrupts::without_interrupts(|| DEVICES.lock().get(name).cloned())
+}
+
+pub fn list() -> Vec<CharDeviceRef> {
+    interrupts::without_interrupts(|| DEVICES.lock().values().cloned().collect())
+}
+
+/// Sends kernel messages to `writer` too, ignored once every slot is taken.
+pub fn add_console(writer: ConsoleWriter) {
+    interrupts::without_interrupts(|| {
+        let mut consoles = CONSOLES.lock();
+        if let Some(slot) = consoles.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(writer);
+        }
+    })
+}
+
+/// Writes a kernel message to every console.
+pub fn log(text: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        let consoles = *CONSOLES.lock();
+        for writer in consoles.into_iter().flatten() {
+            writer(text);
+        }
+    })
+}
+
+/// Discards writes and reads nothing.
+pub struct Null;
+
+impl CharDevice for Null {
+    fn name(&self) -> &str {
+        "null"
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(0)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(buf.len())
+    }
+}
+
+/// Discards writes and reads zeroes.
+pub struct Zero;
+
+impl CharDevice for Zero {
+    fn name(&self) -> &str {
+        "zero"
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(buf.len())
+    }
+}
+
+/// Reads pseudo random bytes, from a xorshift generator seeded with the timestamp counter. Not
+/// fit for cryptography. Writes are mixed into the state.
+pub struct Random {
+    state: AtomicU64,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        // the state of xorshift must not be 0
+        let seed = unsafe { core::arch::x86_64::_rdtsc() } | 1;
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let mut x = self.state.load(Ordering::Relaxed);
+        loop {
+            let mut next = x;
+            next ^= next << 13;
+            next ^= next >> 7;
+            next ^= next << 17;
+            match self
+                .state
+                .compare_exchange_weak(x, next, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return next,
+                Err(current) => x = current,
+            }
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CharDevice for Random {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        for chunk in buf.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes()[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        for chunk in buf.chunks(8) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let mixed = self.next() ^ u64::from_le_bytes(bytes);
+            self.state.store(mixed | 1, Ordering::Relaxed);
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Registers the devices which aren't backed by hardware.
+pub fn init() {
+    info!("registering memory devices");
+    register(Arc::new(Null));
+    register(Arc::new(Zero));
+    register(Arc::new(Random::new()));
+    okay!("registered memory devices");
+}
@@ -0,0 +1,293 @@
+use alloc::vec::Vec;
+use x86_64::{
+    structures::paging::{Mapper, Page, PageTableFlags},
+    VirtAddr,
+};
+
+use crate::{
+    mem::{AddressSpace, PAGE_SIZE},
+    user,
+};
+
+/// Top of the user stack, leaving a guard page below [`user::USER_END`].
+pub const USER_STACK_TOP: u64 = user::USER_END - PAGE_SIZE as u64;
+pub const USER_STACK_SIZE: u64 = 16 * PAGE_SIZE as u64;
+
+pub const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3e;
+
+const PT_LOAD: u32 = 1;
+const PT_PHDR: u32 = 6;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const HEADER_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    Truncated,
+    BadMagic,
+    Unsupported,
+    BadSegment,
+    /// The entry point is outside of every executable segment.
+    BadEntry,
+    OutOfMemory,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+}
+
+/// A parsed static ELF64 executable for x86_64.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: u64,
+    phoff: u64,
+    phnum: u16,
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16, ElfError> {
+    let bytes = data.get(at..at + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, ElfError> {
+    let bytes = data.get(at..at + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Result<u64, ElfError> {
+    let bytes = data.get(at..at + 8).ok_or(ElfError::Truncated)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if &data[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELFCLASS64
+            || data[5] != ELFDATA2LSB
+            || read_u16(data, 16)? != ET_EXEC
+            || read_u16(data, 18)? != EM_X86_64
+            || read_u16(data, 54)? as usize != PHDR_SIZE
+        {
+            return Err(ElfError::Unsupported);
+        }
+        let elf = Self {
+            data,
+            entry: read_u64(data, 24)?,
+            phoff: read_u64(data, 32)?,
+            phnum: read_u16(data, 56)?,
+        };
+        // checks every header is in bounds, so `program_headers` can't fail later
+        for i in 0..elf.phnum {
+            elf.program_header(i)?;
+        }
+        let executable = elf.program_headers().any(|ph| {
+            ph.kind == PT_LOAD
+                && ph.flags & PF_X != 0
+                && ph.vaddr <= elf.entry
+                && elf.entry - ph.vaddr < ph.mem_size
+        });
+        if elf.entry >= user::USER_END || !executable {
+            return Err(ElfError::BadEntry);
+        }
+        Ok(elf)
+    }
+
+    fn program_header(&self, index: u16) -> Result<ProgramHeader, ElfError> {
+        let at = (self.phoff as usize)
+            .checked_add(index as usize * PHDR_SIZE)
+            .ok_or(ElfError::Truncated)?;
+        Ok(ProgramHeader {
+            kind: read_u32(self.data, at)?,
+            flags: read_u32(self.data, at + 4)?,
+            offset: read_u64(self.data, at + 8)?,
+            vaddr: read_u64(self.data, at + 16)?,
+            file_size: read_u64(self.data, at + 32)?,
+            mem_size: read_u64(self.data, at + 40)?,
+        })
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.phnum).map(|i| self.program_header(i).unwrap())
+    }
+
+    /// Where the program headers end up in memory, for `AT_PHDR`.
+    fn phdr_addr(&self) -> Option<u64> {
+        if let Some(phdr) = self.program_headers().find(|ph| ph.kind == PT_PHDR) {
+            return Some(phdr.vaddr);
+        }
+        self.program_headers()
+            .filter(|ph| ph.kind == PT_LOAD)
+            .find(|ph| (ph.offset..ph.offset.saturating_add(ph.file_size)).contains(&self.phoff))
+            .map(|ph| ph.vaddr.wrapping_add(self.phoff - ph.offset))
+    }
+
+    /// Maps every `PT_LOAD` segment into `space`.
+    pub fn load(&self, space: &mut AddressSpace) -> Result<(), ElfError> {
+        for ph in self.program_headers().filter(|ph| ph.kind == PT_LOAD) {
+            self.load_segment(space, &ph)?;
+        }
+        Ok(())
+    }
+
+    fn load_segment(&self, space: &mut AddressSpace, ph: &ProgramHeader) -> Result<(), ElfError> {
+        if ph.mem_size == 0 {
+            return Ok(());
+        }
+        let end = ph
+            .vaddr
+            .checked_add(ph.mem_size)
+            .ok_or(ElfError::BadSegment)?;
+        let file_end = ph
+            .offset
+            .checked_add(ph.file_size)
+            .ok_or(ElfError::BadSegment)?;
+        if end > user::USER_END || ph.file_size > ph.mem_size || file_end > self.data.len() as u64 {
+            return Err(ElfError::BadSegment);
+        }
+
+        let mut flags = PageTableFlags::empty();
+        if ph.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if ph.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        let first = Page::containing_address(VirtAddr::new(ph.vaddr));
+        let last = Page::containing_address(VirtAddr::new(end - 1));
+        for page in Page::range_inclusive(first, last) {
+            match space.translate(page.start_address()) {
+                // shared with the previous segment, so the permissions are merged
+                Some((_, old)) => {
+                    let mut merged = old | (flags & PageTableFlags::WRITABLE);
+                    if !flags.contains(PageTableFlags::NO_EXECUTE) {
+                        merged.remove(PageTableFlags::NO_EXECUTE);
+                    }
+                    unsafe { space.mapper().update_flags(page, merged) }
+                        .map_err(|_| ElfError::BadSegment)?
+                        .flush();
+                }
+                None => {
+                    space
+                        .map_user(page, flags)
+                        .map_err(|_| ElfError::OutOfMemory)?;
+                }
+            }
+        }
+        // the rest of the pages, including the bss, are already zeroed
+        let file = &self.data[ph.offset as usize..file_end as usize];
+        space.write(VirtAddr::new(ph.vaddr), file).unwrap();
+        Ok(())
+    }
+}
+
+/// A program ready to be started in ring 3.
+pub struct UserImage {
+    pub space: AddressSpace,
+    pub entry: VirtAddr,
+    pub stack: VirtAddr,
+}
+
+/// Creates a new address space with the executable `data` loaded and a stack holding `argv`,
+/// `envp` and the auxiliary vector, laid out as the System V ABI expects at process entry.
+pub fn load(data: &[u8], argv: &[&str], envp: &[&str]) -> Result<UserImage, ElfError> {
+    let elf = Elf::parse(data)?;
+    let mut space = AddressSpace::new().ok_or(ElfError::OutOfMemory)?;
+    elf.load(&mut space)?;
+
+    let stack_bottom = Page::containing_address(VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE));
+    let stack_top = Page::containing_address(VirtAddr::new(USER_STACK_TOP - 1));
+    for page in Page::range_inclusive(stack_bottom, stack_top) {
+        space
+            .map_user(page, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)
+            .map_err(|_| ElfError::OutOfMemory)?;
+    }
+
+    let strings_size: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    if strings_size as u64 > USER_STACK_SIZE / 2 {
+        return Err(ElfError::OutOfMemory);
+    }
+    let mut sp = USER_STACK_TOP;
+    let mut push_str = |s: &str| {
+        sp -= s.len() as u64 + 1;
+        space.write(VirtAddr::new(sp), s.as_bytes()).unwrap();
+        space
+            .write(VirtAddr::new(sp + s.len() as u64), &[0])
+            .unwrap();
+        sp
+    };
+    let argv_ptrs: Vec<u64> = argv.iter().map(|s| push_str(s)).collect();
+    let envp_ptrs: Vec<u64> = envp.iter().map(|s| push_str(s)).collect();
+
+    let auxv = [
+        (AT_PHDR, elf.phdr_addr().unwrap_or(0)),
+        (AT_PHENT, PHDR_SIZE as u64),
+        (AT_PHNUM, elf.phnum as u64),
+        (AT_PAGESZ, PAGE_SIZE as u64),
+        (AT_ENTRY, elf.entry),
+        (AT_NULL, 0),
+    ];
+    let mut words = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend(&argv_ptrs);
+    words.push(0);
+    words.extend(&envp_ptrs);
+    words.push(0);
+    for (key, value) in auxv {
+        words.extend([key, value]);
+    }
+
+    // rsp must be 16 bytes aligned when pointing to argc
+    let size = (words.len() * 8) as u64;
+    let sp = (sp - size) & !0xf;
+    if USER_STACK_TOP - sp > USER_STACK_SIZE {
+        return Err(ElfError::OutOfMemory);
+    }
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    space.write(VirtAddr::new(sp), &bytes).unwrap();
+
+    Ok(UserImage {
+        space,
+        entry: VirtAddr::new(elf.entry),
+        stack: VirtAddr::new(sp),
+    })
+}
+
+/// Loads and runs the executable `data` until it exits, returning its exit code.
+pub fn run(data: &[u8], argv: &[&str], envp: &[&str]) -> Result<u64, ElfError> {
+    use x86_64::registers::control::Cr3;
+
+    let image = load(data, argv, envp)?;
+    let (previous, flags) = Cr3::read();
+    let code = unsafe {
+        image.space.activate();
+        let code = user::run(image.entry, image.stack);
+        Cr3::write(previous, flags);
+        code
+    };
+    Ok(code)
+}
@@ -0,0 +1,1288 @@
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::any::Any;
+
+use crate::{
+    block::{BlockDevice, BlockDeviceRef},
+    cache::{self, BufferCache},
+    ints,
+    vfs::{DirEntry, FileSystem, FileType, FsError, Inode, InodeRef, Metadata, NAME_MAX},
+    warn,
+};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const MAGIC: u16 = 0xef53;
+const ROOT_INO: u32 = 2;
+
+/// First usable inode and inode size of revision 0 filesystems.
+const GOOD_OLD_FIRST_INO: u32 = 11;
+const GOOD_OLD_INODE_SIZE: u64 = 128;
+/// Bytes of an inode understood here, whatever the inode size.
+const INODE_BASE_SIZE: usize = 128;
+const GROUP_DESC_SIZE: u64 = 32;
+
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+
+const DIRECT_BLOCKS: usize = 12;
+const BLOCK_POINTERS: usize = 15;
+/// Symbolic links shorter than this live in the block pointers instead of a block.
+const FAST_SYMLINK_MAX: usize = 60;
+
+/// Directories indexed by hash, an index only kept up to date by drivers that know about it.
+const INDEX_FL: u32 = 0x1000;
+
+const S_IFMT: u16 = 0o170000;
+const DIR_ENTRY_HEADER: usize = 8;
+
+/// Where the metadata of a block group is.
+struct Group {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+}
+
+/// The counters of a block group descriptor.
+struct GroupCounts {
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+/// Allocation state, locked by every operation so that they happen one at a time.
+struct Meta {
+    groups: Vec<GroupCounts>,
+    free_blocks: u32,
+    free_inodes: u32,
+}
+
+/// An inode as stored on disk, with the fields not understood kept as they are.
+#[derive(Clone)]
+struct DiskInode {
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    dtime: u32,
+    links: u16,
+    /// Space used, in 512 byte units.
+    sectors: u32,
+    flags: u32,
+    block: [u32; BLOCK_POINTERS],
+    raw: [u8; INODE_BASE_SIZE],
+}
+
+impl DiskInode {
+    fn new(mode: u16, uid: u32, gid: u32, now: u32) -> Self {
+        Self {
+            mode,
+            uid,
+            gid,
+            size: 0,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+            links: 1,
+            sectors: 0,
+            flags: 0,
+            block: [0; BLOCK_POINTERS],
+            raw: [0; INODE_BASE_SIZE],
+        }
+    }
+
+    fn parse(raw: [u8; INODE_BASE_SIZE]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([raw[i], raw[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(raw[i..i + 4].try_into().unwrap());
+        let mode = u16_at(0);
+        // the high half of the size is the directory ACL for anything but files
+        let size_high = match mode & S_IFMT {
+            0o100000 => u32_at(108) as u64,
+            _ => 0,
+        };
+        Self {
+            mode,
+            uid: u16_at(2) as u32 | (u16_at(120) as u32) << 16,
+            gid: u16_at(24) as u32 | (u16_at(122) as u32) << 16,
+            size: u32_at(4) as u64 | size_high << 32,
+            atime: u32_at(8),
+            ctime: u32_at(12),
+            mtime: u32_at(16),
+            dtime: u32_at(20),
+            links: u16_at(26),
+            sectors: u32_at(28),
+            flags: u32_at(32),
+            block: core::array::from_fn(|i| u32_at(40 + i * 4)),
+            raw,
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; INODE_BASE_SIZE] {
+        let mut raw = self.raw;
+        let mut put = |i: usize, bytes: &[u8]| raw[i..i + bytes.len()].copy_from_slice(bytes);
+        put(0, &self.mode.to_le_bytes());
+        put(2, &(self.uid as u16).to_le_bytes());
+        put(4, &(self.size as u32).to_le_bytes());
+        put(8, &self.atime.to_le_bytes());
+        put(12, &self.ctime.to_le_bytes());
+        put(16, &self.mtime.to_le_bytes());
+        put(20, &self.dtime.to_le_bytes());
+        put(24, &(self.gid as u16).to_le_bytes());
+        put(26, &self.links.to_le_bytes());
+        put(28, &self.sectors.to_le_bytes());
+        put(32, &self.flags.to_le_bytes());
+        for (i, block) in self.block.iter().enumerate() {
+            put(40 + i * 4, &block.to_le_bytes());
+        }
+        if self.kind() == FileType::File {
+            put(108, &((self.size >> 32) as u32).to_le_bytes());
+        }
+        put(120, &((self.uid >> 16) as u16).to_le_bytes());
+        put(122, &((self.gid >> 16) as u16).to_le_bytes());
+        raw
+    }
+
+    fn kind(&self) -> FileType {
+        match self.mode & S_IFMT {
+            0o040000 => FileType::Directory,
+            0o120000 => FileType::Symlink,
+            0o020000 => FileType::CharDevice,
+            0o060000 => FileType::BlockDevice,
+            0o010000 => FileType::Pipe,
+            _ => FileType::File,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.kind() == FileType::Directory
+    }
+
+    /// Whether this is a symbolic link with its target in the block pointers.
+    fn is_fast_symlink(&self) -> bool {
+        self.kind() == FileType::Symlink && self.sectors == 0
+    }
+
+    fn touch(&mut self, now: u32) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+}
+
+/// A directory entry, located in the directory.
+struct Slot {
+    /// Logical block of the directory holding the entry.
+    block: u64,
+    offset: usize,
+    ino: u32,
+    name: Vec<u8>,
+    file_type: u8,
+}
+
+struct Ext2 {
+    cache: Arc<BufferCache>,
+    block_size: u64,
+    blocks_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    first_ino: u32,
+    /// Whether directory entries hold the type of their inode.
+    filetype: bool,
+    max_file_size: u64,
+    /// Last time the filesystem was written, from which time goes on as there is no real time
+    /// clock.
+    epoch: u32,
+    /// Block of the group descriptor table.
+    group_table: u64,
+    groups: Vec<Group>,
+    meta: spin::Mutex<Meta>,
+    inodes: spin::Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+    /// Inodes without links, freed once their last reference was dropped.
+    orphans: spin::Mutex<Vec<u32>>,
+}
+
+/// The second extended filesystem, with permissions and symbolic links.
+pub struct Ext2Fs {
+    fs: Arc<Ext2>,
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2Fs {
+    /// Reads the superblock of `device`, which is accessed through a buffer cache from now on.
+    pub fn new(device: BlockDeviceRef) -> Result<Arc<Self>, FsError> {
+        let cache = BufferCache::new(device, cache::DEFAULT_CAPACITY);
+        let mut sb = [0u8; 1024];
+        cache.read_at(SUPERBLOCK_OFFSET, &mut sb)?;
+        let u16_at = |i: usize| u16::from_le_bytes([sb[i], sb[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(sb[i..i + 4].try_into().unwrap());
+        if u16_at(56) != MAGIC || u32_at(24) > 6 {
+            return Err(FsError::Corrupt);
+        }
+        let block_size = 1024u64 << u32_at(24);
+        let (first_ino, inode_size, incompat, ro_compat) = match u32_at(76) {
+            0 => (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE, 0, 0),
+            _ => (u32_at(84), u16_at(88) as u64, u32_at(96), u32_at(100)),
+        };
+        // anything else changes the layout, or has to be kept up to date on writes
+        if incompat & !INCOMPAT_FILETYPE != 0
+            || ro_compat & !(RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE) != 0
+        {
+            return Err(FsError::Unsupported);
+        }
+        let blocks_count = u32_at(4);
+        let first_data_block = u32_at(20);
+        let blocks_per_group = u32_at(32);
+        let inodes_per_group = u32_at(40);
+        // a group's bitmaps each take a single block
+        let bits_per_block = 8 * block_size;
+        if !(1..=bits_per_block).contains(&(blocks_per_group as u64))
+            || !(1..=bits_per_block).contains(&(inodes_per_group as u64))
+            || first_data_block >= blocks_count
+            || !(INODE_BASE_SIZE as u64..=block_size).contains(&inode_size)
+            || blocks_count as u64 * block_size > cache.size()
+        {
+            return Err(FsError::Corrupt);
+        }
+
+        let pointers = block_size / 4;
+        let max_blocks = DIRECT_BLOCKS as u64 + pointers + pointers.pow(2) + pointers.pow(3);
+        let max_file_size = match ro_compat & RO_COMPAT_LARGE_FILE {
+            0 => i32::MAX as u64,
+            _ => max_blocks * block_size,
+        };
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group);
+        let group_table = first_data_block as u64 + 1;
+        let mut table = vec![0u8; (group_count as u64 * GROUP_DESC_SIZE) as usize];
+        cache.read_at(group_table * block_size, &mut table)?;
+        let (groups, counts) = table
+            .chunks_exact(GROUP_DESC_SIZE as usize)
+            .map(|desc| {
+                let u16_at = |i: usize| u16::from_le_bytes([desc[i], desc[i + 1]]);
+                let u32_at = |i: usize| u32::from_le_bytes(desc[i..i + 4].try_into().unwrap());
+                let group = Group {
+                    block_bitmap: u32_at(0),
+                    inode_bitmap: u32_at(4),
+                    inode_table: u32_at(8),
+                };
+                let counts = GroupCounts {
+                    free_blocks: u16_at(12),
+                    free_inodes: u16_at(14),
+                    used_dirs: u16_at(16),
+                };
+                (group, counts)
+            })
+            .unzip();
+
+        let fs = Arc::new(Ext2 {
+            cache,
+            block_size,
+            blocks_count,
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            inode_size,
+            first_ino,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            max_file_size,
+            epoch: u32_at(48).max(u32_at(44)),
+            group_table,
+            groups,
+            meta: spin::Mutex::new(Meta {
+                groups: counts,
+                free_blocks: u32_at(12),
+                free_inodes: u32_at(16),
+            }),
+            inodes: spin::Mutex::new(BTreeMap::new()),
+            orphans: spin::Mutex::new(Vec::new()),
+        });
+        let root = fs.inode(ROOT_INO)?;
+        if !root.state.lock().is_dir() {
+            return Err(FsError::Corrupt);
+        }
+        Ok(Arc::new(Self { fs, root }))
+    }
+
+    pub fn free_blocks(&self) -> u32 {
+        self.fs.lock().free_blocks
+    }
+
+    pub fn free_inodes(&self) -> u32 {
+        self.fs.lock().free_inodes
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        // taking the lock frees the orphans closed since the last operation
+        let _meta = self.fs.lock();
+        self.fs.write_u32(SUPERBLOCK_OFFSET + 48, self.fs.now())?;
+        Ok(self.fs.cache.sync()?)
+    }
+}
+
+/// The `file_type` of directory entries.
+fn entry_type(kind: FileType) -> u8 {
+    match kind {
+        FileType::File => 1,
+        FileType::Directory => 2,
+        FileType::CharDevice => 3,
+        FileType::BlockDevice => 4,
+        FileType::Pipe => 5,
+        FileType::Symlink => 7,
+    }
+}
+
+/// Space taken by an entry named `len` bytes, rounded to four bytes.
+fn entry_len(len: usize) -> usize {
+    (DIR_ENTRY_HEADER + len).next_multiple_of(4)
+}
+
+impl Ext2 {
+    fn now(&self) -> u32 {
+        self.epoch + ints::uptime_secs() as u32
+    }
+
+    /// Takes the lock of every operation, freeing the inodes orphaned since the last one.
+    fn lock(&self) -> spin::MutexGuard<'_, Meta> {
+        let mut meta = self.meta.lock();
+        loop {
+            let Some(ino) = self.orphans.lock().pop() else {
+                break;
+            };
+            if let Err(err) = self.free_inode(&mut meta, ino) {
+                warn!("failed to free ext2 inode {ino}: {err:?}");
+            }
+        }
+        meta
+    }
+
+    fn read_u32(&self, offset: u64) -> Result<u32, FsError> {
+        let mut bytes = [0; 4];
+        self.cache.read_at(offset, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_u32(&self, offset: u64, value: u32) -> Result<(), FsError> {
+        Ok(self.cache.write_at(offset, &value.to_le_bytes())?)
+    }
+
+    fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.block_size
+    }
+
+    fn inode_offset(&self, ino: u32) -> Result<u64, FsError> {
+        let count = self.inodes_per_group as u64 * self.groups.len() as u64;
+        if ino == 0 || ino as u64 > count {
+            return Err(FsError::Io);
+        }
+        let table = self.groups[self.group_of(ino)].inode_table;
+        let index = ((ino - 1) % self.inodes_per_group) as u64;
+        Ok(self.block_offset(table) + index * self.inode_size)
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<DiskInode, FsError> {
+        let mut raw = [0; INODE_BASE_SIZE];
+        self.cache.read_at(self.inode_offset(ino)?, &mut raw)?;
+        Ok(DiskInode::parse(raw))
+    }
+
+    fn write_inode(&self, ino: u32, inode: &DiskInode) -> Result<(), FsError> {
+        Ok(self
+            .cache
+            .write_at(self.inode_offset(ino)?, &inode.to_bytes())?)
+    }
+
+    /// The in-memory inode `ino`, shared by everyone using it.
+    fn inode(self: &Arc<Self>, ino: u32) -> Result<Arc<Ext2Inode>, FsError> {
+        if let Some(inode) = self.inodes.lock().get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let disk = self.read_inode(ino)?;
+        Ok(self.cache_inode(ino, disk))
+    }
+
+    fn cache_inode(self: &Arc<Self>, ino: u32, disk: DiskInode) -> Arc<Ext2Inode> {
+        let mut inodes = self.inodes.lock();
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(Ext2Inode {
+            fs: self.clone(),
+            ino,
+            state: spin::Mutex::new(disk),
+        });
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Writes the counters of a group and of the superblock.
+    fn write_counts(&self, meta: &Meta, index: usize) -> Result<(), FsError> {
+        let counts = &meta.groups[index];
+        let mut desc = [0u8; 6];
+        desc[0..2].copy_from_slice(&counts.free_blocks.to_le_bytes());
+        desc[2..4].copy_from_slice(&counts.free_inodes.to_le_bytes());
+        desc[4..6].copy_from_slice(&counts.used_dirs.to_le_bytes());
+        let offset = self.group_table * self.block_size + index as u64 * GROUP_DESC_SIZE + 12;
+        self.cache.write_at(offset, &desc)?;
+        self.write_u32(SUPERBLOCK_OFFSET + 12, meta.free_blocks)?;
+        self.write_u32(SUPERBLOCK_OFFSET + 16, meta.free_inodes)
+    }
+
+    /// Sets the first clear bit from `start` to `limit` in the bitmap at `block`, returning its
+    /// index.
+    fn take_bit(&self, block: u32, start: u32, limit: u32) -> Result<Option<u32>, FsError> {
+        let mut bitmap = vec![0u8; self.block_size as usize];
+        self.cache.read_at(self.block_offset(block), &mut bitmap)?;
+        let found = (start..limit).find(|&bit| bitmap[bit as usize / 8] & 1 << (bit % 8) == 0);
+        if let Some(bit) = found {
+            let byte = bitmap[bit as usize / 8] | 1 << (bit % 8);
+            let at = self.block_offset(block) + bit as u64 / 8;
+            self.cache.write_at(at, &[byte])?;
+        }
+        Ok(found)
+    }
+
+    fn clear_bit(&self, block: u32, bit: u32) -> Result<(), FsError> {
+        let at = self.block_offset(block) + bit as u64 / 8;
+        let mut byte = [0];
+        self.cache.read_at(at, &mut byte)?;
+        if byte[0] & 1 << (bit % 8) == 0 {
+            // freeing twice means the filesystem is damaged
+            return Err(FsError::Io);
+        }
+        byte[0] &= !(1 << (bit % 8));
+        Ok(self.cache.write_at(at, &byte)?)
+    }
+
+    /// Takes a free block, zeroed, preferring the group `goal`.
+    fn alloc_block(&self, meta: &mut Meta, goal: usize) -> Result<u32, FsError> {
+        let count = meta.groups.len();
+        for index in (0..count).map(|i| (goal + i) % count) {
+            if meta.groups[index].free_blocks == 0 {
+                continue;
+            }
+            // a group with free blocks when there are none in all means damage
+            let free_blocks = meta.free_blocks.checked_sub(1).ok_or(FsError::Corrupt)?;
+            let start = self.first_data_block + index as u32 * self.blocks_per_group;
+            let limit = self.blocks_per_group.min(self.blocks_count - start);
+            let Some(bit) = self.take_bit(self.groups[index].block_bitmap, 0, limit)? else {
+                continue;
+            };
+            meta.groups[index].free_blocks -= 1;
+            meta.free_blocks = free_blocks;
+            self.write_counts(meta, index)?;
+            let block = start + bit;
+            let zeroes = vec![0; self.block_size as usize];
+            self.cache.write_at(self.block_offset(block), &zeroes)?;
+            return Ok(block);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_block(&self, meta: &mut Meta, block: u32) -> Result<(), FsError> {
+        if block < self.first_data_block || block >= self.blocks_count {
+            return Err(FsError::Io);
+        }
+        let index = ((block - self.first_data_block) / self.blocks_per_group) as usize;
+        let bit = (block - self.first_data_block) % self.blocks_per_group;
+        self.clear_bit(self.groups[index].block_bitmap, bit)?;
+        meta.groups[index].free_blocks += 1;
+        meta.free_blocks += 1;
+        self.write_counts(meta, index)
+    }
+
+    /// Takes a free inode number, preferring the group `goal`.
+    fn alloc_inode(&self, meta: &mut Meta, goal: usize, is_dir: bool) -> Result<u32, FsError> {
+        let count = meta.groups.len();
+        for index in (0..count).map(|i| (goal + i) % count) {
+            if meta.groups[index].free_inodes == 0 {
+                continue;
+            }
+            let free_inodes = meta.free_inodes.checked_sub(1).ok_or(FsError::Corrupt)?;
+            // reserved inodes are normally marked as used already, but not always
+            let start = match index {
+                0 => self.first_ino - 1,
+                _ => 0,
+            };
+            let bitmap = self.groups[index].inode_bitmap;
+            let Some(bit) = self.take_bit(bitmap, start, self.inodes_per_group)? else {
+                continue;
+            };
+            let ino = index as u32 * self.inodes_per_group + bit + 1;
+            let group = &mut meta.groups[index];
+            group.free_inodes -= 1;
+            if is_dir {
+                group.used_dirs += 1;
+            }
+            meta.free_inodes = free_inodes;
+            self.write_counts(meta, index)?;
+            // whatever the inode size, the rest of it starts out zeroed
+            let offset = self.inode_offset(ino)?;
+            self.cache
+                .write_at(offset, &vec![0; self.inode_size as usize])?;
+            return Ok(ino);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Frees the blocks and number of an inode without links.
+    fn free_inode(&self, meta: &mut Meta, ino: u32) -> Result<(), FsError> {
+        let mut inode = self.read_inode(ino)?;
+        if !inode.is_fast_symlink() {
+            self.free_blocks_from(meta, &mut inode, 0)?;
+        }
+        let is_dir = inode.is_dir();
+        inode.dtime = self.now();
+        inode.size = 0;
+        self.write_inode(ino, &inode)?;
+
+        let index = ((ino - 1) / self.inodes_per_group) as usize;
+        let bit = (ino - 1) % self.inodes_per_group;
+        self.clear_bit(self.groups[index].inode_bitmap, bit)?;
+        let group = &mut meta.groups[index];
+        group.free_inodes += 1;
+        if is_dir {
+            group.used_dirs = group.used_dirs.saturating_sub(1);
+        }
+        meta.free_inodes += 1;
+        self.write_counts(meta, index)?;
+        self.inodes.lock().remove(&ino);
+        Ok(())
+    }
+
+    fn group_of(&self, ino: u32) -> usize {
+        ((ino - 1) / self.inodes_per_group) as usize
+    }
+
+    /// The block holding logical block `index` of an inode, `0` for a hole unless `alloc` is
+    /// given to fill it, along with the indirect blocks leading to it.
+    fn bmap(
+        &self,
+        mut alloc: Option<(&mut Meta, usize)>,
+        inode: &mut DiskInode,
+        index: u64,
+    ) -> Result<u32, FsError> {
+        let pointers = self.block_size / 4;
+        let mut rest = index;
+        let mut path = [0u64; 3];
+        let (slot, depth) = if rest < DIRECT_BLOCKS as u64 {
+            (rest as usize, 0)
+        } else {
+            rest -= DIRECT_BLOCKS as u64;
+            let mut depth = 1;
+            while rest >= pointers.pow(depth) {
+                rest -= pointers.pow(depth);
+                depth += 1;
+                if depth > 3 {
+                    return Err(FsError::NoSpace);
+                }
+            }
+            for level in 0..depth {
+                path[level as usize] = rest / pointers.pow(depth - 1 - level) % pointers;
+            }
+            (DIRECT_BLOCKS + depth as usize - 1, depth as usize)
+        };
+
+        let sectors_per_block = (self.block_size / 512) as u32;
+        let mut block = inode.block[slot];
+        if block == 0 {
+            let Some((meta, goal)) = alloc.as_mut() else {
+                return Ok(0);
+            };
+            block = self.alloc_block(meta, *goal)?;
+            inode.block[slot] = block;
+            inode.sectors += sectors_per_block;
+        }
+        for &entry in &path[..depth] {
+            let at = self.block_offset(block) + entry * 4;
+            let mut next = self.read_u32(at)?;
+            if next == 0 {
+                let Some((meta, goal)) = alloc.as_mut() else {
+                    return Ok(0);
+                };
+                next = self.alloc_block(meta, *goal)?;
+                self.write_u32(at, next)?;
+                inode.sectors += sectors_per_block;
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Frees the blocks of an inode from logical block `keep` on, with the indirect blocks left
+    /// empty.
+    fn free_blocks_from(
+        &self,
+        meta: &mut Meta,
+        inode: &mut DiskInode,
+        keep: u64,
+    ) -> Result<(), FsError> {
+        let pointers = self.block_size / 4;
+        let DiskInode { block, sectors, .. } = inode;
+        let mut base = 0;
+        for (slot, pointer) in block.iter_mut().enumerate() {
+            let level = slot.saturating_sub(DIRECT_BLOCKS - 1) as u32;
+            self.free_tree(meta, sectors, pointer, level, keep.saturating_sub(base))?;
+            base += pointers.pow(level);
+        }
+        Ok(())
+    }
+
+    /// Frees the blocks below `*pointer`, a tree `level` indirect blocks deep, from its logical
+    /// block `keep` on. `*pointer` is cleared once the whole tree is freed.
+    fn free_tree(
+        &self,
+        meta: &mut Meta,
+        sectors: &mut u32,
+        pointer: &mut u32,
+        level: u32,
+        keep: u64,
+    ) -> Result<(), FsError> {
+        let pointers = self.block_size / 4;
+        if *pointer == 0 || keep >= pointers.pow(level) {
+            return Ok(());
+        }
+        if level > 0 {
+            let span = pointers.pow(level - 1);
+            let mut data = vec![0u8; self.block_size as usize];
+            self.cache.read_at(self.block_offset(*pointer), &mut data)?;
+            for (i, entry) in data.chunks_exact_mut(4).enumerate() {
+                let mut child = u32::from_le_bytes(entry.try_into().unwrap());
+                let child_keep = keep.saturating_sub(i as u64 * span);
+                self.free_tree(meta, sectors, &mut child, level - 1, child_keep)?;
+                entry.copy_from_slice(&child.to_le_bytes());
+            }
+            if keep > 0 {
+                return Ok(self.cache.write_at(self.block_offset(*pointer), &data)?);
+            }
+        }
+        self.free_block(meta, *pointer)?;
+        *sectors = sectors.saturating_sub((self.block_size / 512) as u32);
+        *pointer = 0;
+        Ok(())
+    }
+
+    fn read_data(&self, inode: &mut DiskInode, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = (pos % self.block_size) as usize;
+            let len = (self.block_size as usize - within).min(buf.len() - done);
+            match self.bmap(None, inode, pos / self.block_size)? {
+                0 => buf[done..done + len].fill(0),
+                block => {
+                    let at = self.block_offset(block) + within as u64;
+                    self.cache.read_at(at, &mut buf[done..done + len])?;
+                }
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` at `offset` of an inode, allocating its blocks. The size is left alone.
+    fn write_data(
+        &self,
+        meta: &mut Meta,
+        ino: u32,
+        inode: &mut DiskInode,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<(), FsError> {
+        let goal = self.group_of(ino);
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = pos % self.block_size;
+            let len = ((self.block_size - within) as usize).min(buf.len() - done);
+            let block = self.bmap(Some((meta, goal)), inode, pos / self.block_size)?;
+            let at = self.block_offset(block) + within;
+            self.cache.write_at(at, &buf[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Zeroes the last block of an inode past `size`, so that growing it shows zeroes.
+    fn zero_tail(&self, inode: &mut DiskInode, size: u64) -> Result<(), FsError> {
+        let within = size % self.block_size;
+        if within == 0 {
+            return Ok(());
+        }
+        match self.bmap(None, inode, size / self.block_size)? {
+            0 => Ok(()),
+            block => {
+                let zeroes = vec![0; (self.block_size - within) as usize];
+                Ok(self
+                    .cache
+                    .write_at(self.block_offset(block) + within, &zeroes)?)
+            }
+        }
+    }
+
+    fn dir_entries(&self, dir: &mut DiskInode) -> Result<Vec<Slot>, FsError> {
+        let mut slots = Vec::new();
+        let mut data = vec![0u8; self.block_size as usize];
+        for block in 0..dir.size / self.block_size {
+            self.read_data(dir, block * self.block_size, &mut data)?;
+            let mut offset = 0;
+            while offset < data.len() {
+                let entry = &data[offset..];
+                let ino = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                let rec_len = u16::from_le_bytes([entry[4], entry[5]]) as usize;
+                let name_len = match self.filetype {
+                    true => entry[6] as usize,
+                    false => u16::from_le_bytes([entry[6], entry[7]]) as usize,
+                };
+                if rec_len < DIR_ENTRY_HEADER
+                    || rec_len % 4 != 0
+                    || rec_len > entry.len()
+                    || DIR_ENTRY_HEADER + name_len > rec_len
+                {
+                    return Err(FsError::Io);
+                }
+                if ino != 0 {
+                    slots.push(Slot {
+                        block,
+                        offset,
+                        ino,
+                        name: entry[DIR_ENTRY_HEADER..DIR_ENTRY_HEADER + name_len].to_vec(),
+                        file_type: if self.filetype { entry[7] } else { 0 },
+                    });
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(slots)
+    }
+
+    fn find_entry(&self, dir: &mut DiskInode, name: &str) -> Result<Slot, FsError> {
+        if !dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        self.dir_entries(dir)?
+            .into_iter()
+            .find(|slot| slot.name == name.as_bytes())
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Whether a directory holds nothing but `.` and `..`.
+    fn is_empty_dir(&self, dir: &mut DiskInode) -> Result<bool, FsError> {
+        Ok(self
+            .dir_entries(dir)?
+            .iter()
+            .all(|slot| slot.name == b"." || slot.name == b".."))
+    }
+
+    fn write_entry(
+        &self,
+        at: u64,
+        ino: u32,
+        rec_len: usize,
+        name: &[u8],
+        kind: FileType,
+    ) -> Result<(), FsError> {
+        let mut entry = vec![0u8; DIR_ENTRY_HEADER + name.len()];
+        entry[..4].copy_from_slice(&ino.to_le_bytes());
+        entry[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+        entry[6] = name.len() as u8;
+        if self.filetype {
+            entry[7] = entry_type(kind);
+        }
+        entry[DIR_ENTRY_HEADER..].copy_from_slice(name);
+        Ok(self.cache.write_at(at, &entry)?)
+    }
+
+    /// Adds the entry `name` for `ino` in a directory, in the first gap large enough or in a new
+    /// block.
+    fn add_entry(
+        &self,
+        meta: &mut Meta,
+        dir_ino: u32,
+        dir: &mut DiskInode,
+        name: &str,
+        ino: u32,
+        kind: FileType,
+    ) -> Result<(), FsError> {
+        let needed = entry_len(name.len());
+        // the hash index would no longer list every entry
+        dir.flags &= !INDEX_FL;
+        let mut data = vec![0u8; self.block_size as usize];
+        for index in 0..dir.size / self.block_size {
+            self.read_data(dir, index * self.block_size, &mut data)?;
+            let block = self.bmap(None, dir, index)?;
+            let mut offset = 0;
+            while offset < data.len() {
+                let entry = &data[offset..];
+                let used_by = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                let rec_len = u16::from_le_bytes([entry[4], entry[5]]) as usize;
+                if rec_len < DIR_ENTRY_HEADER {
+                    return Err(FsError::Io);
+                }
+                let used = match used_by {
+                    0 => 0,
+                    _ => entry_len(entry[6] as usize),
+                };
+                if rec_len >= used + needed {
+                    let at = self.block_offset(block) + offset as u64;
+                    if used > 0 {
+                        // the entry keeps what it needs, the new one gets the rest
+                        self.cache.write_at(at + 4, &(used as u16).to_le_bytes())?;
+                    }
+                    let at = at + used as u64;
+                    return self.write_entry(at, ino, rec_len - used, name.as_bytes(), kind);
+                }
+                offset += rec_len;
+            }
+        }
+
+        let index = dir.size / self.block_size;
+        let block = self.bmap(Some((meta, self.group_of(dir_ino))), dir, index)?;
+        let rec_len = self.block_size as usize;
+        self.write_entry(
+            self.block_offset(block),
+            ino,
+            rec_len,
+            name.as_bytes(),
+            kind,
+        )?;
+        dir.size += self.block_size;
+        Ok(())
+    }
+
+    /// Removes an entry found by [`Ext2::find_entry`], merging its space into the entry before.
+    fn remove_entry(&self, dir: &mut DiskInode, slot: &Slot) -> Result<(), FsError> {
+        let block = self.bmap(None, dir, slot.block)?;
+        let start = self.block_offset(block);
+        let mut data = vec![0u8; self.block_size as usize];
+        self.cache.read_at(start, &mut data)?;
+        let rec_len_at = |offset: usize| u16::from_le_bytes([data[offset + 4], data[offset + 5]]);
+        if slot.offset == 0 {
+            // the first entry of a block has nothing before it, so it only loses its inode
+            return self.write_u32(start, 0);
+        }
+        let mut previous = 0;
+        loop {
+            let next = previous + rec_len_at(previous) as usize;
+            if next == slot.offset {
+                break;
+            }
+            if next <= previous || next > slot.offset {
+                return Err(FsError::Io);
+            }
+            previous = next;
+        }
+        let merged = rec_len_at(previous) + rec_len_at(slot.offset);
+        Ok(self
+            .cache
+            .write_at(start + previous as u64 + 4, &merged.to_le_bytes())?)
+    }
+
+    /// Points the `..` entry of a directory to `parent`.
+    fn set_parent(&self, dir: &mut DiskInode, parent: u32) -> Result<(), FsError> {
+        let slot = self
+            .dir_entries(dir)?
+            .into_iter()
+            .find(|slot| slot.name == b"..")
+            .ok_or(FsError::Io)?;
+        let block = self.bmap(None, dir, slot.block)?;
+        self.write_u32(self.block_offset(block) + slot.offset as u64, parent)
+    }
+}
+
+// Files still open when unmounting keep the filesystem alive, and the last of them to be closed
+// may be an orphan. The cache writes everything back once dropped right after.
+impl Drop for Ext2 {
+    fn drop(&mut self) {
+        drop(self.lock());
+    }
+}
+
+pub struct Ext2Inode {
+    fs: Arc<Ext2>,
+    ino: u32,
+    state: spin::Mutex<DiskInode>,
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        if self.state.get_mut().links == 0 {
+            self.fs.orphans.lock().push(self.ino);
+        }
+    }
+}
+
+impl Ext2Inode {
+    fn save(&self, state: &DiskInode) -> Result<(), FsError> {
+        self.fs.write_inode(self.ino, state)
+    }
+
+    /// Checks that this is a directory still linked, locking it.
+    fn dir_state(&self) -> Result<spin::MutexGuard<'_, DiskInode>, FsError> {
+        let state = self.state.lock();
+        if !state.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        if state.links == 0 {
+            return Err(FsError::NotFound);
+        }
+        Ok(state)
+    }
+
+    /// Creates the inode of a new entry `name` with `init` filling it, then links it here.
+    fn create_with(
+        &self,
+        meta: &mut Meta,
+        name: &str,
+        mode: u16,
+        init: impl FnOnce(&mut Meta, u32, &mut DiskInode) -> Result<(), FsError>,
+    ) -> Result<Arc<Ext2Inode>, FsError> {
+        let fs = &self.fs;
+        validate_name(name)?;
+        let mut dir = self.dir_state()?;
+        match fs.find_entry(&mut dir, name) {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+        let mut disk = DiskInode::new(mode, dir.uid, dir.gid, fs.now());
+        let ino = fs.alloc_inode(meta, fs.group_of(self.ino), disk.is_dir())?;
+        let result = init(meta, ino, &mut disk).and_then(|()| {
+            fs.write_inode(ino, &disk)?;
+            fs.add_entry(meta, self.ino, &mut dir, name, ino, disk.kind())
+        });
+        if let Err(err) = result {
+            // whatever was allocated goes back with the inode
+            disk.links = 0;
+            fs.write_inode(ino, &disk)?;
+            fs.free_inode(meta, ino)?;
+            return Err(err);
+        }
+        if disk.is_dir() {
+            dir.links += 1;
+        }
+        dir.touch(self.fs.now());
+        self.save(&dir)?;
+        Ok(fs.cache_inode(ino, disk))
+    }
+
+    /// Drops a link to `inode`, the entry of which was removed from this directory.
+    fn release(&self, dir: &mut DiskInode, inode: &Ext2Inode) -> Result<(), FsError> {
+        let mut state = inode.state.lock();
+        if state.is_dir() {
+            // its `.` goes along with the entry, and its `..` no longer links here
+            state.links = 0;
+            dir.links = dir.links.checked_sub(1).ok_or(FsError::Corrupt)?;
+        } else {
+            state.links = state.links.checked_sub(1).ok_or(FsError::Corrupt)?;
+        }
+        state.ctime = self.fs.now();
+        inode.save(&state)
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), FsError> {
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        Metadata {
+            ino: self.ino as u64,
+            kind: state.kind(),
+            size: state.size,
+            mode: state.mode & 0o7777,
+            nlink: state.links as u32,
+            uid: state.uid,
+            gid: state.gid,
+            atime: state.atime as u64,
+            mtime: state.mtime as u64,
+            ctime: state.ctime as u64,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let _meta = self.fs.lock();
+        let mut state = self.state.lock();
+        if state.kind() != FileType::File {
+            return Err(self.not_file_of(&state));
+        }
+        if offset >= state.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((state.size - offset) as usize);
+        self.fs.read_data(&mut state, offset, &mut buf[..len])?;
+        // not written back on its own, to keep reads from dirtying the cache
+        state.atime = self.fs.now();
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= self.fs.max_file_size)
+            .ok_or(FsError::NoSpace)?;
+        let mut meta = self.fs.lock();
+        let mut state = self.state.lock();
+        if state.kind() != FileType::File {
+            return Err(self.not_file_of(&state));
+        }
+        if offset > state.size {
+            let size = state.size;
+            self.fs.zero_tail(&mut state, size)?;
+        }
+        let result = self
+            .fs
+            .write_data(&mut meta, self.ino, &mut state, offset, buf);
+        // the blocks taken before running out of space stay with the file
+        if result.is_ok() {
+            state.size = state.size.max(end);
+        }
+        state.touch(self.fs.now());
+        self.save(&state)?;
+        result.map(|()| buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if size > self.fs.max_file_size {
+            return Err(FsError::NoSpace);
+        }
+        let mut meta = self.fs.lock();
+        let mut state = self.state.lock();
+        if state.kind() != FileType::File {
+            return Err(self.not_file_of(&state));
+        }
+        let old = state.size;
+        if size < old {
+            let keep = size.div_ceil(self.fs.block_size);
+            self.fs.free_blocks_from(&mut meta, &mut state, keep)?;
+        }
+        self.fs.zero_tail(&mut state, size.min(old))?;
+        state.size = size;
+        state.touch(self.fs.now());
+        self.save(&state)
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef, FsError> {
+        let _meta = self.fs.lock();
+        let slot = self.fs.find_entry(&mut *self.dir_state()?, name)?;
+        Ok(self.fs.inode(slot.ino)?)
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> Result<InodeRef, FsError> {
+        let mut meta = self.fs.lock();
+        let mode = kind.mode_bits() as u16 | mode & 0o7777;
+        let block_size = self.fs.block_size;
+        let parent = self.ino;
+        let inode = match kind {
+            FileType::File => self.create_with(&mut meta, name, mode, |_, _, _| Ok(()))?,
+            FileType::Directory => self.create_with(&mut meta, name, mode, |meta, ino, disk| {
+                let fs = &self.fs;
+                disk.links = 2;
+                let block = fs.bmap(Some((meta, fs.group_of(ino))), disk, 0)?;
+                let at = fs.block_offset(block);
+                fs.write_entry(at, ino, entry_len(1), b".", FileType::Directory)?;
+                let rest = block_size as usize - entry_len(1);
+                let at = at + entry_len(1) as u64;
+                fs.write_entry(at, parent, rest, b"..", FileType::Directory)?;
+                disk.size = block_size;
+                Ok(())
+            })?,
+            _ => return Err(FsError::Unsupported),
+        };
+        Ok(inode)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<InodeRef, FsError> {
+        if target.is_empty() || target.len() as u64 > self.fs.block_size {
+            return Err(FsError::NameTooLong);
+        }
+        let mut meta = self.fs.lock();
+        let mode = FileType::Symlink.mode_bits() as u16 | 0o777;
+        let inode = self.create_with(&mut meta, name, mode, |meta, ino, disk| {
+            if target.len() < FAST_SYMLINK_MAX {
+                let mut bytes = [0u8; FAST_SYMLINK_MAX];
+                bytes[..target.len()].copy_from_slice(target.as_bytes());
+                for (pointer, chunk) in disk.block.iter_mut().zip(bytes.chunks_exact(4)) {
+                    *pointer = u32::from_le_bytes(chunk.try_into().unwrap());
+                }
+            } else {
+                self.fs.write_data(meta, ino, disk, 0, target.as_bytes())?;
+            }
+            disk.size = target.len() as u64;
+            Ok(())
+        })?;
+        Ok(inode)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let fs = &self.fs;
+        let _meta = fs.lock();
+        let mut dir = self.dir_state()?;
+        let slot = fs.find_entry(&mut dir, name)?;
+        let inode = fs.inode(slot.ino)?;
+        let mut state = inode.state.lock();
+        if state.is_dir() && !fs.is_empty_dir(&mut state)? {
+            return Err(FsError::NotEmpty);
+        }
+        drop(state);
+        fs.remove_entry(&mut dir, &slot)?;
+        self.release(&mut dir, &inode)?;
+        dir.touch(self.fs.now());
+        self.save(&dir)
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &InodeRef, new_name: &str) -> Result<(), FsError> {
+        let fs = &self.fs;
+        let target = (&**new_dir as &dyn Any)
+            .downcast_ref::<Ext2Inode>()
+            .filter(|target| Arc::ptr_eq(&target.fs, fs))
+            .ok_or(FsError::CrossDevice)?;
+        validate_name(new_name)?;
+        let mut meta = fs.lock();
+        let slot = fs.find_entry(&mut *self.dir_state()?, old_name)?;
+        let moved = fs.inode(slot.ino)?;
+        let is_dir = moved.state.lock().is_dir();
+
+        // the entry replaced, if any, goes away first
+        let existing = match fs.find_entry(&mut *target.dir_state()?, new_name) {
+            Ok(existing) => Some(existing),
+            Err(FsError::NotFound) => None,
+            Err(err) => return Err(err),
+        };
+        if let Some(existing) = existing {
+            if existing.ino == slot.ino {
+                return Ok(());
+            }
+            let replaced = fs.inode(existing.ino)?;
+            let mut replaced_state = replaced.state.lock();
+            match (is_dir, replaced_state.is_dir()) {
+                (true, false) => return Err(FsError::NotDirectory),
+                (false, true) => return Err(FsError::IsDirectory),
+                (true, true) if !fs.is_empty_dir(&mut replaced_state)? => {
+                    return Err(FsError::NotEmpty)
+                }
+                _ => {}
+            }
+            drop(replaced_state);
+            let mut dir = target.dir_state()?;
+            fs.remove_entry(&mut dir, &existing)?;
+            target.release(&mut dir, &replaced)?;
+            target.save(&dir)?;
+        }
+
+        let kind = moved.state.lock().kind();
+        let mut dir = target.dir_state()?;
+        fs.add_entry(&mut meta, target.ino, &mut dir, new_name, slot.ino, kind)?;
+        if is_dir && target.ino != self.ino {
+            dir.links += 1;
+        }
+        dir.touch(self.fs.now());
+        target.save(&dir)?;
+        drop(dir);
+
+        // the entry may have moved within the same directory, so it is looked up again
+        let mut dir = self.dir_state()?;
+        let slot = fs.find_entry(&mut dir, old_name)?;
+        fs.remove_entry(&mut dir, &slot)?;
+        if is_dir && target.ino != self.ino {
+            dir.links -= 1;
+        }
+        dir.touch(self.fs.now());
+        self.save(&dir)?;
+        drop(dir);
+
+        let mut state = moved.state.lock();
+        if is_dir && target.ino != self.ino {
+            fs.set_parent(&mut state, target.ino)?;
+        }
+        state.ctime = self.fs.now();
+        moved.save(&state)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let fs = &self.fs;
+        let _meta = fs.lock();
+        let slots = fs.dir_entries(&mut *self.dir_state()?)?;
+        let mut entries = Vec::new();
+        for slot in slots {
+            if slot.name == b"." || slot.name == b".." {
+                continue;
+            }
+            let kind = match slot.file_type {
+                1 => FileType::File,
+                2 => FileType::Directory,
+                3 => FileType::CharDevice,
+                4 => FileType::BlockDevice,
+                5 => FileType::Pipe,
+                7 => FileType::Symlink,
+                _ => fs.inode(slot.ino)?.state.lock().kind(),
+            };
+            entries.push(DirEntry {
+                name: String::from_utf8_lossy(&slot.name).into_owned(),
+                ino: slot.ino as u64,
+                kind,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        let _meta = self.fs.lock();
+        let mut state = self.state.lock();
+        if state.kind() != FileType::Symlink {
+            return Err(FsError::InvalidPath);
+        }
+        let len = (state.size as usize).min(self.fs.block_size as usize);
+        let mut target = vec![0u8; len];
+        if state.is_fast_symlink() {
+            let bytes = state.block.iter().flat_map(|p| p.to_le_bytes());
+            for (byte, value) in target.iter_mut().zip(bytes) {
+                *byte = value;
+            }
+        } else {
+            self.fs.read_data(&mut state, 0, &mut target)?;
+        }
+        String::from_utf8(target).map_err(|_| FsError::InvalidPath)
+    }
+
+    fn set_mode(&self, mode: u16) -> Result<(), FsError> {
+        let _meta = self.fs.lock();
+        let mut state = self.state.lock();
+        state.mode = state.mode & S_IFMT | mode & 0o7777;
+        state.ctime = self.fs.now();
+        self.save(&state)
+    }
+}
+
+impl Ext2Inode {
+    fn not_file_of(&self, state: &DiskInode) -> FsError {
+        match state.kind() {
+            FileType::Directory => FsError::IsDirectory,
+            _ => FsError::Unsupported,
+        }
+    }
+}
@@ -0,0 +1,1094 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    block::{BlockDevice, BlockDeviceRef, BlockError},
+    cache::{self, BufferCache},
+    vfs::{DirEntry, FileSystem, FileType, FsError, Inode, InodeRef, Metadata},
+};
+
+const ENTRY_SIZE: u64 = 32;
+const ROOT_INO: u64 = 1;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// Read only, hidden, system and volume id together mark a long name entry.
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+const ENTRY_END: u8 = 0x00;
+const ENTRY_FREE: u8 = 0xe5;
+/// A first name byte of 0xe5 is stored as 0x05, as 0xe5 marks free entries.
+const ENTRY_KANJI: u8 = 0x05;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// Bits of the reserved byte telling that the short base name or extension is lower case.
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// There is no real time clock, so everything gets the epoch of FAT, 1980-01-01.
+const FAT_EPOCH_DATE: u16 = 1 << 5 | 1;
+
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+const MAX_NAME_LEN: usize = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatKind {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatKind {
+    /// Smallest value marking the end of a cluster chain.
+    fn end_of_chain(self) -> u32 {
+        match self {
+            FatKind::Fat12 => 0xff8,
+            FatKind::Fat16 => 0xfff8,
+            FatKind::Fat32 => 0x0fff_fff8,
+        }
+    }
+
+    /// Bits taken by each entry of the table.
+    fn entry_bits(self) -> u64 {
+        match self {
+            FatKind::Fat12 => 12,
+            FatKind::Fat16 => 16,
+            FatKind::Fat32 => 32,
+        }
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(_: BlockError) -> Self {
+        FsError::Io
+    }
+}
+
+/// Where the entries of a directory are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DirLoc {
+    /// The fixed size root directory of FAT12 and FAT16.
+    FixedRoot,
+    Chain(u32),
+}
+
+/// The short entry of a file, by its directory and byte offset in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct EntryPos {
+    dir: DirLoc,
+    offset: u64,
+}
+
+impl EntryPos {
+    fn ino(&self) -> u64 {
+        let cluster = match self.dir {
+            DirLoc::FixedRoot => 0,
+            DirLoc::Chain(cluster) => cluster as u64,
+        };
+        (cluster << 32 | self.offset / ENTRY_SIZE) + ROOT_INO + 1
+    }
+}
+
+/// A directory entry with its long name resolved.
+struct RawEntry {
+    name: String,
+    short_name: [u8; 11],
+    attr: u8,
+    cluster: u32,
+    size: u32,
+    pos: EntryPos,
+    /// Offset of the first long name entry, or of the short entry without a long name.
+    first_offset: u64,
+}
+
+impl RawEntry {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+}
+
+struct Fat {
+    cache: Arc<BufferCache>,
+    kind: FatKind,
+    sector_size: u64,
+    cluster_size: u64,
+    fat_start: u64,
+    fat_size: u64,
+    fat_count: u64,
+    root_start: u64,
+    root_entries: u64,
+    data_start: u64,
+    /// Data clusters, numbered from 2.
+    clusters: u32,
+    fsinfo: Option<u64>,
+    /// Taken by every operation, so that the FAT and directories are changed one at a time.
+    lock: spin::Mutex<AllocState>,
+    inodes: spin::Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+struct AllocState {
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    /// Whether the free count of FSInfo was already marked unknown.
+    fsinfo_stale: bool,
+}
+
+/// A FAT12, FAT16 or FAT32 filesystem with long names.
+pub struct FatFs {
+    fat: Arc<Fat>,
+    root: Arc<FatInode>,
+}
+
+impl FatFs {
+    /// Reads the boot sector of `device`, which is accessed through a buffer cache from now on.
+    pub fn new(device: BlockDeviceRef) -> Result<Arc<Self>, FsError> {
+        let cache = BufferCache::new(device, cache::DEFAULT_CAPACITY);
+        let mut boot = [0u8; 512];
+        cache.read_at(0, &mut boot)?;
+        if boot[510..] != [0x55, 0xaa] {
+            return Err(FsError::Corrupt);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([boot[i], boot[i + 1]]) as u64;
+        let u32_at = |i: usize| u32::from_le_bytes(boot[i..i + 4].try_into().unwrap()) as u64;
+
+        let sector_size = u16_at(11);
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = u16_at(14);
+        let fat_count = boot[16] as u64;
+        let root_entries = u16_at(17);
+        let total = match u16_at(19) {
+            0 => u32_at(32),
+            total => total,
+        };
+        let fat_size = match u16_at(22) {
+            0 => u32_at(36),
+            size => size,
+        };
+        if !sector_size.is_power_of_two()
+            || !(512..=4096).contains(&sector_size)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || fat_count == 0
+            || fat_size == 0
+        {
+            return Err(FsError::Corrupt);
+        }
+        let root_sectors = (root_entries * ENTRY_SIZE).div_ceil(sector_size);
+        let root_start = reserved + fat_count * fat_size;
+        let data_start = root_start + root_sectors;
+        let data_sectors = total.checked_sub(data_start).ok_or(FsError::Corrupt)?;
+        let clusters = (data_sectors / sectors_per_cluster) as u32;
+        let kind = match clusters {
+            0..4085 => FatKind::Fat12,
+            4085..65525 => FatKind::Fat16,
+            _ => FatKind::Fat32,
+        };
+        if total * sector_size > cache.size() || (kind == FatKind::Fat32) != (root_entries == 0) {
+            return Err(FsError::Corrupt);
+        }
+        // the table needs an entry for each cluster, after the two reserved ones
+        let fat_entries = fat_size * sector_size * 8 / kind.entry_bits();
+        if clusters == 0 || fat_entries < clusters as u64 + 2 {
+            return Err(FsError::Corrupt);
+        }
+
+        let (root_cluster, fsinfo) = match kind {
+            FatKind::Fat32 => (u32_at(44) as u32, Some(u16_at(48)).filter(|&s| s != 0)),
+            _ => (0, None),
+        };
+        let fat = Arc::new(Fat {
+            cache,
+            kind,
+            sector_size,
+            cluster_size: sectors_per_cluster * sector_size,
+            fat_start: reserved,
+            fat_size,
+            fat_count,
+            root_start,
+            root_entries,
+            data_start,
+            clusters,
+            fsinfo,
+            lock: spin::Mutex::new(AllocState {
+                next_free: 2,
+                fsinfo_stale: false,
+            }),
+            inodes: spin::Mutex::new(BTreeMap::new()),
+        });
+        if let Some(hint) = fat.read_fsinfo_hint()? {
+            fat.lock.lock().next_free = hint;
+        }
+        let root_dir = match kind {
+            FatKind::Fat32 => DirLoc::Chain(root_cluster),
+            _ => DirLoc::FixedRoot,
+        };
+        let root = Arc::new(FatInode {
+            fat: fat.clone(),
+            ino: ROOT_INO,
+            pos: None,
+            state: spin::Mutex::new(NodeState {
+                cluster: root_cluster,
+                size: 0,
+                attr: ATTR_DIRECTORY,
+                deleted: false,
+            }),
+            root_dir: Some(root_dir),
+        });
+        Ok(Arc::new(Self { fat, root }))
+    }
+
+    pub fn kind(&self) -> FatKind {
+        self.fat.kind
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &str {
+        match self.fat.kind {
+            FatKind::Fat12 => "fat12",
+            FatKind::Fat16 => "fat16",
+            FatKind::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(self.fat.cache.sync()?)
+    }
+}
+
+impl Fat {
+    fn read_fsinfo_hint(&self) -> Result<Option<u32>, FsError> {
+        let Some(sector) = self.fsinfo else {
+            return Ok(None);
+        };
+        let mut info = [0u8; 512];
+        self.cache.read_at(sector * self.sector_size, &mut info)?;
+        let u32_at = |i: usize| u32::from_le_bytes(info[i..i + 4].try_into().unwrap());
+        if u32_at(0) != FSINFO_LEAD || u32_at(484) != FSINFO_STRUCT {
+            return Ok(None);
+        }
+        let hint = u32_at(492);
+        Ok((2..self.clusters + 2).contains(&hint).then_some(hint))
+    }
+
+    /// Tells other systems to recount the free clusters, as they aren't tracked here.
+    fn invalidate_fsinfo(&self, state: &mut AllocState) -> Result<(), FsError> {
+        if let (Some(sector), false) = (self.fsinfo, state.fsinfo_stale) {
+            let at = sector * self.sector_size + 488;
+            self.cache.write_at(at, &FSINFO_UNKNOWN.to_le_bytes())?;
+            state.fsinfo_stale = true;
+        }
+        Ok(())
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        (self.data_start + (cluster as u64 - 2) * (self.cluster_size / self.sector_size))
+            * self.sector_size
+    }
+
+    fn valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.clusters + 2).contains(&cluster)
+    }
+
+    /// Byte offset of the entry of `cluster` in the first FAT.
+    fn fat_entry_offset(&self, cluster: u32) -> u64 {
+        let cluster = cluster as u64;
+        self.fat_start * self.sector_size
+            + match self.kind {
+                FatKind::Fat12 => cluster + cluster / 2,
+                FatKind::Fat16 => cluster * 2,
+                FatKind::Fat32 => cluster * 4,
+            }
+    }
+
+    fn read_fat(&self, cluster: u32) -> Result<u32, FsError> {
+        let offset = self.fat_entry_offset(cluster);
+        let mut bytes = [0u8; 4];
+        let len = if self.kind == FatKind::Fat32 { 4 } else { 2 };
+        self.cache.read_at(offset, &mut bytes[..len])?;
+        let value = u32::from_le_bytes(bytes);
+        Ok(match self.kind {
+            FatKind::Fat12 if cluster % 2 == 1 => value >> 4,
+            FatKind::Fat12 => value & 0xfff,
+            FatKind::Fat16 => value,
+            FatKind::Fat32 => value & 0x0fff_ffff,
+        })
+    }
+
+    /// Sets the entry of `cluster` in every copy of the FAT.
+    fn write_fat(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        for copy in 0..self.fat_count {
+            let offset = self.fat_entry_offset(cluster) + copy * self.fat_size * self.sector_size;
+            let mut bytes = [0u8; 4];
+            let len = if self.kind == FatKind::Fat32 { 4 } else { 2 };
+            self.cache.read_at(offset, &mut bytes[..len])?;
+            let old = u32::from_le_bytes(bytes);
+            let new = match self.kind {
+                // entries share the middle byte with their neighbour
+                FatKind::Fat12 if cluster % 2 == 1 => old & 0x000f | value << 4,
+                FatKind::Fat12 => old & 0xf000 | value & 0xfff,
+                FatKind::Fat16 => value & 0xffff,
+                // the top four bits are reserved
+                FatKind::Fat32 => old & 0xf000_0000 | value & 0x0fff_ffff,
+            };
+            self.cache.write_at(offset, &new.to_le_bytes()[..len])?;
+        }
+        Ok(())
+    }
+
+    /// The clusters of the chain starting at `first`, empty for `0`.
+    fn chain(&self, first: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 && cluster < self.kind.end_of_chain() {
+            // a chain longer than the disk loops
+            if !self.valid_cluster(cluster) || chain.len() > self.clusters as usize {
+                return Err(FsError::Io);
+            }
+            chain.push(cluster);
+            cluster = self.read_fat(cluster)?;
+        }
+        Ok(chain)
+    }
+
+    /// Takes a free cluster, zeroed, and appends it to the chain ending with `last`, if any.
+    fn alloc_cluster(&self, state: &mut AllocState, last: Option<u32>) -> Result<u32, FsError> {
+        let start = state.next_free.clamp(2, self.clusters + 1);
+        let mut cluster = start;
+        loop {
+            if self.read_fat(cluster)? == 0 {
+                break;
+            }
+            cluster = if cluster + 1 >= self.clusters + 2 {
+                2
+            } else {
+                cluster + 1
+            };
+            if cluster == start {
+                return Err(FsError::NoSpace);
+            }
+        }
+        self.invalidate_fsinfo(state)?;
+        self.write_fat(cluster, self.kind.end_of_chain() | 0x7)?;
+        if let Some(last) = last {
+            self.write_fat(last, cluster)?;
+        }
+        let zeroes = vec![0; self.cluster_size as usize];
+        self.cache.write_at(self.cluster_offset(cluster), &zeroes)?;
+        state.next_free = cluster + 1;
+        Ok(cluster)
+    }
+
+    fn free_chain(&self, state: &mut AllocState, first: u32) -> Result<(), FsError> {
+        self.invalidate_fsinfo(state)?;
+        for cluster in self.chain(first)? {
+            self.write_fat(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the chain starting at `*first` hold `count` clusters, allocating or freeing the
+    /// ones past that. `*first` becomes `0` for an empty chain.
+    fn resize_chain(
+        &self,
+        state: &mut AllocState,
+        first: &mut u32,
+        count: usize,
+    ) -> Result<Vec<u32>, FsError> {
+        let mut chain = self.chain(*first)?;
+        if count < chain.len() {
+            match count {
+                0 => *first = 0,
+                _ => self.write_fat(chain[count - 1], self.kind.end_of_chain() | 0x7)?,
+            }
+            self.free_chain(state, chain[count])?;
+            chain.truncate(count);
+        }
+        while chain.len() < count {
+            let cluster = self.alloc_cluster(state, chain.last().copied())?;
+            if chain.is_empty() {
+                *first = cluster;
+            }
+            chain.push(cluster);
+        }
+        Ok(chain)
+    }
+
+    /// Reads bytes at `offset` of a chain, which must be long enough.
+    fn read_chain(&self, chain: &[u32], offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let cluster = chain[(pos / self.cluster_size) as usize];
+            let within = pos % self.cluster_size;
+            let len = ((self.cluster_size - within) as usize).min(buf.len() - done);
+            let at = self.cluster_offset(cluster) + within;
+            self.cache.read_at(at, &mut buf[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_chain(&self, chain: &[u32], offset: u64, buf: &[u8]) -> Result<(), FsError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let cluster = chain[(pos / self.cluster_size) as usize];
+            let within = pos % self.cluster_size;
+            let len = ((self.cluster_size - within) as usize).min(buf.len() - done);
+            let at = self.cluster_offset(cluster) + within;
+            self.cache.write_at(at, &buf[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// The whole content of a directory.
+    fn read_dir_data(&self, dir: DirLoc) -> Result<Vec<u8>, FsError> {
+        match dir {
+            DirLoc::FixedRoot => {
+                let mut data = vec![0; (self.root_entries * ENTRY_SIZE) as usize];
+                self.cache
+                    .read_at(self.root_start * self.sector_size, &mut data)?;
+                Ok(data)
+            }
+            DirLoc::Chain(first) => {
+                let chain = self.chain(first)?;
+                let mut data = vec![0; chain.len() * self.cluster_size as usize];
+                self.read_chain(&chain, 0, &mut data)?;
+                Ok(data)
+            }
+        }
+    }
+
+    fn write_dir_data(&self, dir: DirLoc, offset: u64, data: &[u8]) -> Result<(), FsError> {
+        match dir {
+            DirLoc::FixedRoot => {
+                let at = self.root_start * self.sector_size + offset;
+                Ok(self.cache.write_at(at, data)?)
+            }
+            DirLoc::Chain(first) => self.write_chain(&self.chain(first)?, offset, data),
+        }
+    }
+
+    fn read_entries(&self, dir: DirLoc) -> Result<Vec<RawEntry>, FsError> {
+        let data = self.read_dir_data(dir)?;
+        let mut entries = Vec::new();
+        // long name parts seen so far, with their checksum and first offset
+        let mut long: Option<(Vec<u16>, u8, u64)> = None;
+        for (i, slot) in data.chunks_exact(ENTRY_SIZE as usize).enumerate() {
+            let offset = i as u64 * ENTRY_SIZE;
+            match slot[0] {
+                ENTRY_END => break,
+                ENTRY_FREE => {
+                    long = None;
+                    continue;
+                }
+                _ => {}
+            }
+            let attr = slot[11];
+            if attr & 0x3f == ATTR_LONG_NAME {
+                let seq = slot[0] & 0x1f;
+                if slot[0] & LFN_LAST != 0 {
+                    long = Some((vec![0xffff; seq as usize * LFN_CHARS], slot[13], offset));
+                }
+                if let Some((units, checksum, _)) = &mut long {
+                    let index = seq as usize;
+                    if index == 0 || index * LFN_CHARS > units.len() || *checksum != slot[13] {
+                        long = None;
+                        continue;
+                    }
+                    let start = (index - 1) * LFN_CHARS;
+                    for (j, unit) in lfn_units(slot).enumerate() {
+                        units[start + j] = unit;
+                    }
+                }
+                continue;
+            }
+            let long_name = long.take();
+            if attr & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+            let short_name: [u8; 11] = slot[..11].try_into().unwrap();
+            if short_name == *b".          " || short_name == *b"..         " {
+                continue;
+            }
+            let (name, first_offset) = match long_name {
+                Some((units, checksum, first)) if checksum == short_checksum(&short_name) => {
+                    let units = units.into_iter().take_while(|&u| u != 0 && u != 0xffff);
+                    let name = char::decode_utf16(units)
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    (name, first)
+                }
+                _ => (short_display_name(&short_name, slot[12]), offset),
+            };
+            let u16_at = |i: usize| u16::from_le_bytes([slot[i], slot[i + 1]]) as u32;
+            entries.push(RawEntry {
+                name,
+                short_name,
+                attr,
+                cluster: u16_at(20) << 16 | u16_at(26),
+                size: u32::from_le_bytes(slot[28..32].try_into().unwrap()),
+                pos: EntryPos { dir, offset },
+                first_offset,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn find_entry(&self, dir: DirLoc, name: &str) -> Result<RawEntry, FsError> {
+        self.read_entries(dir)?
+            .into_iter()
+            .find(|entry| names_equal(&entry.name, name))
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Adds an entry `name` to `dir`, with a long name if it needs one.
+    fn add_entry(
+        &self,
+        state: &mut AllocState,
+        dir: DirLoc,
+        name: &str,
+        attr: u8,
+        cluster: u32,
+    ) -> Result<EntryPos, FsError> {
+        validate_name(name)?;
+        let entries = self.read_entries(dir)?;
+        if entries.iter().any(|entry| names_equal(&entry.name, name)) {
+            return Err(FsError::AlreadyExists);
+        }
+        let (short_name, case, needs_long) = match exact_short_name(name) {
+            Some((short, case)) => (short, case, false),
+            None => {
+                let taken = |short: &[u8; 11]| entries.iter().any(|e| e.short_name == *short);
+                (alias_short_name(name, taken)?, 0, true)
+            }
+        };
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let long_slots = if needs_long {
+            units.len().div_ceil(LFN_CHARS)
+        } else {
+            0
+        };
+
+        let offset = self.free_slots(state, dir, long_slots as u64 + 1)?;
+        let mut slots = vec![0u8; (long_slots + 1) * ENTRY_SIZE as usize];
+        let checksum = short_checksum(&short_name);
+        for (i, slot) in slots.chunks_exact_mut(ENTRY_SIZE as usize).enumerate() {
+            if i == long_slots {
+                break;
+            }
+            // long name parts come last first
+            let seq = long_slots - i;
+            slot[0] = seq as u8 | if i == 0 { LFN_LAST } else { 0 };
+            slot[11] = ATTR_LONG_NAME;
+            slot[13] = checksum;
+            let part = (0..LFN_CHARS).map(|j| {
+                let k = (seq - 1) * LFN_CHARS + j;
+                match k.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[k],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                }
+            });
+            set_lfn_units(slot, part);
+        }
+        let short = &mut slots[long_slots * ENTRY_SIZE as usize..];
+        short[..11].copy_from_slice(&short_name);
+        short[11] = attr;
+        short[12] = case;
+        write_entry_fields(short, cluster, 0);
+        for date in [16, 18, 24] {
+            short[date..date + 2].copy_from_slice(&FAT_EPOCH_DATE.to_le_bytes());
+        }
+        self.write_dir_data(dir, offset, &slots)?;
+        Ok(EntryPos {
+            dir,
+            offset: offset + long_slots as u64 * ENTRY_SIZE,
+        })
+    }
+
+    /// Finds `count` consecutive free entries in `dir`, growing it if needed.
+    fn free_slots(&self, state: &mut AllocState, dir: DirLoc, count: u64) -> Result<u64, FsError> {
+        let data = self.read_dir_data(dir)?;
+        let mut run_start = 0;
+        let mut run = 0;
+        for (i, slot) in data.chunks_exact(ENTRY_SIZE as usize).enumerate() {
+            let offset = i as u64 * ENTRY_SIZE;
+            if slot[0] == ENTRY_END {
+                // everything after the end marker is free too
+                if run == 0 {
+                    run_start = offset;
+                }
+                run += (data.len() as u64 - offset) / ENTRY_SIZE;
+                break;
+            }
+            if slot[0] == ENTRY_FREE {
+                if run == 0 {
+                    run_start = offset;
+                }
+                run += 1;
+                if run >= count {
+                    return Ok(run_start);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        if run >= count {
+            return Ok(run_start);
+        }
+        let DirLoc::Chain(first) = dir else {
+            return Err(FsError::NoSpace);
+        };
+        if run == 0 {
+            run_start = data.len() as u64;
+        }
+        // new clusters are zeroed, which marks them as the end of the directory
+        let missing = (count - run) * ENTRY_SIZE;
+        let mut first = first;
+        let clusters = data.len() as u64 / self.cluster_size + missing.div_ceil(self.cluster_size);
+        self.resize_chain(state, &mut first, clusters as usize)?;
+        Ok(run_start)
+    }
+
+    fn remove_entry(&self, entry: &RawEntry) -> Result<(), FsError> {
+        let mut offset = entry.first_offset;
+        while offset <= entry.pos.offset {
+            self.write_dir_data(entry.pos.dir, offset, &[ENTRY_FREE])?;
+            offset += ENTRY_SIZE;
+        }
+        Ok(())
+    }
+
+    fn write_entry(&self, pos: EntryPos, state: &NodeState) -> Result<(), FsError> {
+        let mut slot = [0u8; ENTRY_SIZE as usize];
+        let at = pos.offset;
+        match pos.dir {
+            DirLoc::FixedRoot => self
+                .cache
+                .read_at(self.root_start * self.sector_size + at, &mut slot)?,
+            DirLoc::Chain(first) => self.read_chain(&self.chain(first)?, at, &mut slot)?,
+        }
+        slot[11] = state.attr;
+        write_entry_fields(&mut slot, state.cluster, state.size);
+        self.write_dir_data(pos.dir, at, &slot)
+    }
+
+    fn inode(self: &Arc<Self>, entry: &RawEntry) -> Arc<FatInode> {
+        let ino = entry.pos.ino();
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return inode;
+        }
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(FatInode {
+            fat: self.clone(),
+            ino,
+            pos: Some(entry.pos),
+            state: spin::Mutex::new(NodeState {
+                cluster: entry.cluster,
+                size: entry.size,
+                attr: entry.attr,
+                deleted: false,
+            }),
+            root_dir: None,
+        });
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+}
+
+struct NodeState {
+    cluster: u32,
+    size: u32,
+    attr: u8,
+    /// Set once unlinked, after which the clusters belong to someone else.
+    deleted: bool,
+}
+
+pub struct FatInode {
+    fat: Arc<Fat>,
+    ino: u64,
+    /// `None` for the root, which has no entry.
+    pos: Option<EntryPos>,
+    state: spin::Mutex<NodeState>,
+    /// Where the root directory is, as it may not be a cluster chain.
+    root_dir: Option<DirLoc>,
+}
+
+impl FatInode {
+    fn dir_loc(&self, state: &NodeState) -> Result<DirLoc, FsError> {
+        if state.attr & ATTR_DIRECTORY == 0 {
+            return Err(FsError::NotDirectory);
+        }
+        Ok(self.root_dir.unwrap_or(DirLoc::Chain(state.cluster)))
+    }
+
+    fn save(&self, state: &NodeState) -> Result<(), FsError> {
+        match self.pos {
+            Some(pos) => self.fat.write_entry(pos, state),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that this is a file still linked, returning its state.
+    fn file_state(&self) -> Result<spin::MutexGuard<'_, NodeState>, FsError> {
+        let state = self.state.lock();
+        if state.attr & ATTR_DIRECTORY != 0 {
+            return Err(FsError::IsDirectory);
+        }
+        if state.deleted {
+            return Err(FsError::NotFound);
+        }
+        Ok(state)
+    }
+
+    fn resize(
+        &self,
+        alloc: &mut AllocState,
+        state: &mut NodeState,
+        size: u64,
+    ) -> Result<(), FsError> {
+        let fat = &self.fat;
+        let old = state.size as u64;
+        let count = size.div_ceil(fat.cluster_size) as usize;
+        let chain = match fat.resize_chain(alloc, &mut state.cluster, count) {
+            Ok(chain) => chain,
+            Err(err) => {
+                // gives back what was taken before running out of space
+                let old_count = old.div_ceil(fat.cluster_size) as usize;
+                fat.resize_chain(alloc, &mut state.cluster, old_count)?;
+                return Err(err);
+            }
+        };
+        // new clusters are zeroed, but not the end of the last old one
+        if size > old && old % fat.cluster_size != 0 {
+            let end = (old.next_multiple_of(fat.cluster_size)).min(size);
+            fat.write_chain(&chain, old, &vec![0; (end - old) as usize])?;
+        }
+        state.size = size as u32;
+        self.save(state)
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        let is_dir = state.attr & ATTR_DIRECTORY != 0;
+        let mut mode = if is_dir { 0o755 } else { 0o644 };
+        if state.attr & ATTR_READ_ONLY != 0 {
+            mode &= !0o222;
+        }
+        Metadata {
+            ino: self.ino,
+            kind: if is_dir {
+                FileType::Directory
+            } else {
+                FileType::File
+            },
+            size: state.size as u64,
+            mode,
+            nlink: if is_dir { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let _alloc = self.fat.lock.lock();
+        let state = self.file_state()?;
+        let size = state.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let chain = self.fat.chain(state.cluster)?;
+        self.fat.read_chain(&chain, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or(FsError::NoSpace)?;
+        let mut alloc = self.fat.lock.lock();
+        let mut state = self.file_state()?;
+        if end > state.size as u64 {
+            self.resize(&mut alloc, &mut state, end)?;
+        }
+        let chain = self.fat.chain(state.cluster)?;
+        self.fat.write_chain(&chain, offset, buf)?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if size > MAX_FILE_SIZE {
+            return Err(FsError::NoSpace);
+        }
+        let mut alloc = self.fat.lock.lock();
+        let mut state = self.file_state()?;
+        self.resize(&mut alloc, &mut state, size)
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef, FsError> {
+        let _alloc = self.fat.lock.lock();
+        let dir = self.dir_loc(&self.state.lock())?;
+        let entry = self.fat.find_entry(dir, name)?;
+        Ok(self.fat.inode(&entry))
+    }
+
+    fn create(&self, name: &str, kind: FileType, _mode: u16) -> Result<InodeRef, FsError> {
+        let fat = &self.fat;
+        let mut alloc = fat.lock.lock();
+        let dir = self.dir_loc(&self.state.lock())?;
+        let pos = match kind {
+            FileType::File => fat.add_entry(&mut alloc, dir, name, ATTR_ARCHIVE, 0)?,
+            FileType::Directory => {
+                let cluster = fat.alloc_cluster(&mut alloc, None)?;
+                let pos = match fat.add_entry(&mut alloc, dir, name, ATTR_DIRECTORY, cluster) {
+                    Ok(pos) => pos,
+                    Err(err) => {
+                        fat.free_chain(&mut alloc, cluster)?;
+                        return Err(err);
+                    }
+                };
+                // `..` points to cluster 0 for the root, wherever it is
+                let parent = match dir {
+                    DirLoc::Chain(parent) if self.pos.is_some() => parent,
+                    _ => 0,
+                };
+                let mut dots = [0u8; 2 * ENTRY_SIZE as usize];
+                dots[..11].copy_from_slice(b".          ");
+                dots[32..43].copy_from_slice(b"..         ");
+                for (slot, cluster) in dots.chunks_exact_mut(32).zip([cluster, parent]) {
+                    slot[11] = ATTR_DIRECTORY;
+                    write_entry_fields(slot, cluster, 0);
+                }
+                fat.write_chain(&[cluster], 0, &dots)?;
+                pos
+            }
+            _ => return Err(FsError::Unsupported),
+        };
+        let entry = fat
+            .read_entries(dir)?
+            .into_iter()
+            .find(|entry| entry.pos == pos)
+            .ok_or(FsError::Io)?;
+        Ok(fat.inode(&entry))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let fat = &self.fat;
+        let mut alloc = fat.lock.lock();
+        let dir = self.dir_loc(&self.state.lock())?;
+        let entry = fat.find_entry(dir, name)?;
+        if entry.is_dir() && !fat.read_entries(DirLoc::Chain(entry.cluster))?.is_empty() {
+            return Err(FsError::NotEmpty);
+        }
+        if entry.attr & ATTR_READ_ONLY != 0 {
+            return Err(FsError::ReadOnly);
+        }
+        fat.remove_entry(&entry)?;
+        // inodes still open see the file as gone, as its clusters are reused
+        let inode = fat.inodes.lock().remove(&entry.pos.ino());
+        if let Some(inode) = inode.as_ref().and_then(Weak::upgrade) {
+            inode.state.lock().deleted = true;
+        }
+        if entry.cluster != 0 {
+            fat.free_chain(&mut alloc, entry.cluster)?;
+        }
+        Ok(())
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let _alloc = self.fat.lock.lock();
+        let dir = self.dir_loc(&self.state.lock())?;
+        Ok(self
+            .fat
+            .read_entries(dir)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                ino: entry.pos.ino(),
+                kind: if entry.is_dir() {
+                    FileType::Directory
+                } else {
+                    FileType::File
+                },
+                name: entry.name,
+            })
+            .collect())
+    }
+
+    /// Only the write bits count, through the read only attribute.
+    fn set_mode(&self, mode: u16) -> Result<(), FsError> {
+        let _alloc = self.fat.lock.lock();
+        let mut state = self.state.lock();
+        if self.pos.is_none() {
+            return Err(FsError::Unsupported);
+        }
+        if mode & 0o200 == 0 {
+            state.attr |= ATTR_READ_ONLY;
+        } else {
+            state.attr &= !ATTR_READ_ONLY;
+        }
+        self.save(&state)
+    }
+}
+
+fn write_entry_fields(slot: &mut [u8], cluster: u32, size: u32) {
+    slot[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    slot[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    slot[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// The 13 UTF-16 units of a long name entry.
+fn lfn_units(slot: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    [1..11, 14..26, 28..32]
+        .into_iter()
+        .flat_map(move |range| slot[range].chunks_exact(2))
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+}
+
+fn set_lfn_units(slot: &mut [u8], units: impl Iterator<Item = u16>) {
+    let offsets = [1..11, 14..26, 28..32]
+        .into_iter()
+        .flat_map(|range| range.step_by(2));
+    for (offset, unit) in offsets.zip(units) {
+        slot[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+    }
+}
+
+fn short_checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn short_display_name(short: &[u8; 11], case: u8) -> String {
+    let mut base = short[..8].to_vec();
+    if base[0] == ENTRY_KANJI {
+        base[0] = ENTRY_FREE;
+    }
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let text: String = bytes.iter().map(|&b| b as char).collect();
+        let text = text.trim_end_matches(' ');
+        if lower {
+            text.to_lowercase()
+        } else {
+            text.into()
+        }
+    };
+    let base = part(&base, case & CASE_LOWER_BASE != 0);
+    let ext = part(&short[8..], case & CASE_LOWER_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{base}.{ext}")
+    }
+}
+
+/// FAT compares names without regard to case.
+fn names_equal(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+fn validate_name(name: &str) -> Result<(), FsError> {
+    if name.encode_utf16().count() > MAX_NAME_LEN {
+        return Err(FsError::NameTooLong);
+    }
+    let bad_char = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name.ends_with(['.', ' ']) || name.contains(bad_char) {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+fn short_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// The 8.3 name storing `name` alone, with the case bits it needs, if there is one.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || ext.contains('.') {
+        return None;
+    }
+    if !base.chars().chain(ext.chars()).all(short_char) {
+        return None;
+    }
+    // a part must be all upper or all lower case to be stored without a long name
+    let case_bit = |part: &str, bit: u8| {
+        let lower = part.chars().any(|c| c.is_ascii_lowercase());
+        let upper = part.chars().any(|c| c.is_ascii_uppercase());
+        match (lower, upper) {
+            (true, true) => None,
+            (true, false) => Some(bit),
+            _ => Some(0),
+        }
+    };
+    let case = case_bit(base, CASE_LOWER_BASE)? | case_bit(ext, CASE_LOWER_EXT)?;
+    let mut short = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() {
+        short[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in ext.bytes().enumerate() {
+        short[8 + i] = c.to_ascii_uppercase();
+    }
+    Some((short, case))
+}
+
+/// A unique `BASE~N.EXT` alias for a name that needs a long entry.
+fn alias_short_name(name: &str, taken: impl Fn(&[u8; 11]) -> bool) -> Result<[u8; 11], FsError> {
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.to_ascii_uppercase() {
+                c if short_char(c) => c as u8,
+                _ => b'_',
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(i) if i > 0 => (&trimmed[..i], &trimmed[i + 1..]),
+        _ => (trimmed, ""),
+    };
+    let base = clean(base);
+    let ext = clean(ext);
+    for n in 1..1_000_000u32 {
+        let tail = format!("~{n}");
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        let ext_len = ext.len().min(3);
+        short[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+        if !taken(&short) {
+            return Ok(short);
+        }
+    }
+    Err(FsError::NoSpace)
+}
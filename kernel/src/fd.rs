@@ -0,0 +1,146 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    ints, print, signal,
+    syscall::Errno,
+    vfs::{self, Metadata, SeekFrom},
+};
+
+/// Something a file descriptor can point to.
+pub trait FileLike: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno>;
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno>;
+
+    /// Moves the offset of seekable files, returning the new one.
+    fn seek(&self, _pos: SeekFrom) -> Result<u64, Errno> {
+        Err(Errno::SPipe)
+    }
+
+    fn stat(&self) -> Result<Metadata, Errno> {
+        Err(Errno::Inval)
+    }
+
+    /// The file when this is a file opened through the filesystem.
+    fn as_vfs_file(&self) -> Option<&vfs::File> {
+        None
+    }
+}
+
+pub type FileRef = Arc<dyn FileLike>;
+
+/// Highest number of descriptors a process can have open.
+pub const MAX_FDS: usize = 256;
+
+/// The open files of a process, indexed by file descriptor.
+#[derive(Clone, Default)]
+pub struct FdTable {
+    files: Vec<Option<FileRef>>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// A table with the console as stdin, stdout and stderr.
+    pub fn with_console() -> Self {
+        let console: FileRef = Arc::new(Console);
+        Self {
+            files: alloc::vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        }
+    }
+
+    pub fn get(&self, fd: usize) -> Result<FileRef, Errno> {
+        self.files
+            .get(fd)
+            .and_then(|f| f.clone())
+            .ok_or(Errno::BadFd)
+    }
+
+    /// Stores `file` in the lowest free descriptor.
+    pub fn insert(&mut self, file: FileRef) -> Result<usize, Errno> {
+        match self.files.iter().position(|f| f.is_none()) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                Ok(fd)
+            }
+            None if self.files.len() < MAX_FDS => {
+                self.files.push(Some(file));
+                Ok(self.files.len() - 1)
+            }
+            None => Err(Errno::MFile),
+        }
+    }
+
+    /// Points `fd` to `file`, closing what it pointed to before.
+    pub fn set(&mut self, fd: usize, file: FileRef) -> Result<(), Errno> {
+        if fd >= MAX_FDS {
+            return Err(Errno::BadFd);
+        }
+        if fd >= self.files.len() {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd] = Some(file);
+        Ok(())
+    }
+
+    /// Copies `fd` to the lowest free descriptor.
+    pub fn dup(&mut self, fd: usize) -> Result<usize, Errno> {
+        let file = self.get(fd)?;
+        self.insert(file)
+    }
+
+    /// Makes `new` point to the same file as `old`.
+    pub fn dup2(&mut self, old: usize, new: usize) -> Result<usize, Errno> {
+        let file = self.get(old)?;
+        if old != new {
+            self.set(new, file)?;
+        }
+        Ok(new)
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<(), Errno> {
+        let slot = self.files.get_mut(fd).ok_or(Errno::BadFd)?;
+        slot.take().ok_or(Errno::BadFd)?;
+        Ok(())
+    }
+
+    pub fn close_all(&mut self) {
+        self.files.clear();
+    }
+}
+
+/// The keyboard for reading and the screen and serial port for writing.
+pub struct Console;
+
+impl FileLike for Console {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        signal::wait(&ints::INPUT_READY, || {
+            let read = ints::read_input(buf);
+            (read != 0).then_some(read)
+        })
+    }
+
+    /// Writes the longest valid UTF-8 prefix and reports only that as written. Bytes that can't
+    /// start a character are shown as a replacement character.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        match core::str::from_utf8(buf) {
+            Ok(text) => {
+                print!("{text}");
+                Ok(buf.len())
+            }
+            Err(err) if err.valid_up_to() > 0 => {
+                let valid = err.valid_up_to();
+                print!("{}", core::str::from_utf8(&buf[..valid]).unwrap());
+                Ok(valid)
+            }
+            Err(err) => {
+                print!("\u{fffd}");
+                Ok(err.error_len().unwrap_or(buf.len()))
+            }
+        }
+    }
+}
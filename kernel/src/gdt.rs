@@ -8,19 +8,50 @@ use x86_64::{
     },
 };
 
-use crate::{info, okay};
+use crate::{info, okay, percpu};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const KERNEL_STACK_INDEX: usize = 0;
 
 pub struct SegSelectors {
     kcode: SegmentSelector,
     kdata: SegmentSelector,
+    ucode: SegmentSelector,
+    udata: SegmentSelector,
     tss: SegmentSelector,
 }
 
 impl SegSelectors {
-    pub fn new(kcode: SegmentSelector, kdata: SegmentSelector, tss: SegmentSelector) -> Self {
-        Self { kcode, kdata, tss }
+    pub fn new(
+        kcode: SegmentSelector,
+        kdata: SegmentSelector,
+        ucode: SegmentSelector,
+        udata: SegmentSelector,
+        tss: SegmentSelector,
+    ) -> Self {
+        Self {
+            kcode,
+            kdata,
+            ucode,
+            udata,
+            tss,
+        }
+    }
+
+    pub fn kernel_code(&self) -> SegmentSelector {
+        self.kcode
+    }
+
+    pub fn kernel_data(&self) -> SegmentSelector {
+        self.kdata
+    }
+
+    pub fn user_code(&self) -> SegmentSelector {
+        self.ucode
+    }
+
+    pub fn user_data(&self) -> SegmentSelector {
+        self.udata
     }
 
     pub unsafe fn set_segmentations(&self) {
@@ -32,11 +63,16 @@ impl SegSelectors {
         segmentation::GS::set_reg(self.kdata);
         segmentation::SS::set_reg(self.kdata);
         tables::load_tss(self.tss);
+        percpu::load(0);
     }
 }
 
 pub fn init() {
     info!("initializing gdt");
+    info!("\tsetting up tss stacks");
+    unsafe { init_tss() }
+    okay!("\tsetted up tss stacks");
+
     info!("\tloading gdt table");
     GDT.0.load();
     okay!("\tloaded gdt table");
@@ -47,28 +83,57 @@ pub fn init() {
     okay!("gdt loaded");
 }
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
+pub fn selectors() -> &'static SegSelectors {
+    &GDT.1
+}
+
+/// Sets the stack used when an interrupt, a trap or a `syscall` comes from ring 3.
+pub unsafe fn set_kernel_stack(stack_end: VirtAddr) {
+    TSS.privilege_stack_table[KERNEL_STACK_INDEX] = stack_end;
+    percpu::set_kernel_rsp(stack_end);
+}
+
+pub fn kernel_stack() -> VirtAddr {
+    unsafe { TSS.privilege_stack_table[KERNEL_STACK_INDEX] }
+}
+
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+unsafe fn init_tss() {
+    TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        let stack_end = stack_start + STACK_SIZE;
+        stack_end
     };
+    TSS.privilege_stack_table[KERNEL_STACK_INDEX] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        let stack_end = stack_start + STACK_SIZE;
+        stack_end.align_down(16u64)
+    };
+}
+
+lazy_static! {
     static ref GDT: (GlobalDescriptorTable, SegSelectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let kcode_seg = gdt.add_entry(Descriptor::kernel_code_segment());
         let kdata_seg = gdt.add_entry(Descriptor::kernel_data_segment());
+        // `sysret` expects the user data segment right before the user code one
         gdt.add_entry(Descriptor::UserSegment(0));
-        gdt.add_entry(Descriptor::user_code_segment());
-        gdt.add_entry(Descriptor::user_data_segment());
+        let udata_seg = gdt.add_entry(Descriptor::user_data_segment());
+        let ucode_seg = gdt.add_entry(Descriptor::user_code_segment());
 
-        let tss_seg = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (gdt, SegSelectors::new(kcode_seg, kdata_seg, tss_seg))
+        let tss_seg = gdt.add_entry(Descriptor::tss_segment(unsafe {
+            &*core::ptr::addr_of!(TSS)
+        }));
+        (
+            gdt,
+            SegSelectors::new(kcode_seg, kdata_seg, ucode_seg, udata_seg, tss_seg),
+        )
     };
 }
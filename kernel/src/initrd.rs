@@ -0,0 +1,124 @@
+use alloc::{format, string::String};
+
+use crate::{
+    info, okay,
+    vfs::{self, FsError},
+    warn,
+};
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitrdError {
+    /// The archive ends in the middle of an entry.
+    Truncated,
+    BadChecksum,
+    /// A header field isn't what ustar allows.
+    BadHeader,
+    Fs(FsError),
+}
+
+impl From<FsError> for InitrdError {
+    fn from(value: FsError) -> Self {
+        InitrdError::Fs(value)
+    }
+}
+
+/// Unpacks the archive the bootloader loaded as ramdisk at `addr` into `/`, if any.
+pub fn init(addr: Option<u64>, len: u64) {
+    let Some(addr) = addr else {
+        warn!("no initrd was loaded");
+        return;
+    };
+    info!("unpacking initrd");
+    let data = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+    match unpack(data, "/") {
+        Ok(entries) => okay!("unpacked {entries} initrd entries"),
+        Err(err) => warn!("failed to unpack initrd: {err:?}"),
+    }
+}
+
+/// Creates the content of the ustar archive `data` below the directory `root`, returning how
+/// many entries it had. Existing directories are kept and existing files replaced.
+pub fn unpack(data: &[u8], root: &str) -> Result<usize, InitrdError> {
+    let mut offset = 0;
+    let mut entries = 0;
+    while offset + BLOCK_SIZE <= data.len() {
+        let header = &data[offset..offset + BLOCK_SIZE];
+        // the archive ends with zero blocks
+        if header.iter().all(|&b| b == 0) {
+            return Ok(entries);
+        }
+        let checksum = octal(&header[148..156])?;
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        if sum != checksum {
+            return Err(InitrdError::BadChecksum);
+        }
+
+        let name = entry_name(header)?;
+        let path = format!("{}/{}", root.trim_end_matches('/'), name.trim_matches('/'));
+        let mode = octal(&header[100..108])? as u16;
+        let size = octal(&header[124..136])? as usize;
+        let content = offset + BLOCK_SIZE;
+        let next = content + size.next_multiple_of(BLOCK_SIZE);
+        if content + size > data.len() {
+            return Err(InitrdError::Truncated);
+        }
+
+        match header[156] {
+            b'0' | 0 => {
+                vfs::write_file(&path, &data[content..content + size])?;
+                vfs::chmod(&path, mode)?;
+            }
+            b'5' => match vfs::mkdir(&path, mode) {
+                Ok(()) | Err(FsError::AlreadyExists) => {}
+                Err(err) => return Err(err.into()),
+            },
+            b'2' => {
+                let target = field_str(&header[157..257])?;
+                if vfs::lstat(&path).is_ok() {
+                    vfs::unlink(&path)?;
+                }
+                vfs::symlink(target, &path)?;
+            }
+            // links, devices and fifos have no use here
+            kind => warn!("skipped initrd entry {name} of type {}", kind as char),
+        }
+        entries += 1;
+        offset = next;
+    }
+    Err(InitrdError::Truncated)
+}
+
+fn entry_name(header: &[u8]) -> Result<String, InitrdError> {
+    let name = field_str(&header[..100])?;
+    let prefix = if &header[257..262] == b"ustar" {
+        field_str(&header[345..500])?
+    } else {
+        ""
+    };
+    Ok(if prefix.is_empty() {
+        name.into()
+    } else {
+        format!("{prefix}/{name}")
+    })
+}
+
+/// A NUL padded text field.
+fn field_str(field: &[u8]) -> Result<&str, InitrdError> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).map_err(|_| InitrdError::BadHeader)
+}
+
+/// A number field, in octal digits padded with spaces or NULs.
+fn octal(field: &[u8]) -> Result<u64, InitrdError> {
+    let digits = field_str(field)?.trim_matches(' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| InitrdError::BadHeader)
+}
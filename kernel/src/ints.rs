@@ -1,22 +1,78 @@
-use crate::{erro, gdt, info, okay, print, warn};
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::collections::VecDeque;
+
+use crate::{
+    ata, erro, gdt, info, keyboard, okay, percpu, serial, signal, syscall, task, task::WaitQueue,
+    warn,
+};
 use pic8259::ChainedPics;
 use spin;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{instructions::interrupts, PrivilegeLevel, VirtAddr};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
-pub const KEYBOARD_PORT: u16 = 0x60;
+/// The PIT is left with its default divisor, ticking at ~18.2Hz.
+pub const PIT_FREQUENCY_HZ: u128 = 1_193_182;
+pub const PIT_DIVISOR: u128 = 65536;
 
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
-pub static mut TIMER_TICKS: spin::RwLock<u128> = spin::RwLock::new(0);
+/// Interrupts handled on each line of the PICs.
+static COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+
+/// Typed bytes waiting to be read.
+pub static INPUT: spin::Mutex<VecDeque<u8>> = spin::Mutex::new(VecDeque::new());
+/// Notified when bytes are pushed to [`INPUT`].
+pub static INPUT_READY: WaitQueue = WaitQueue::new();
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
 pub enum IntIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial = PIC_1_OFFSET + 4,
+    PrimaryAta = PIC_2_OFFSET + 6,
+    SecondaryAta,
+}
+
+impl IntIndex {
+    pub const ALL: [IntIndex; 5] = [
+        IntIndex::Timer,
+        IntIndex::Keyboard,
+        IntIndex::Serial,
+        IntIndex::PrimaryAta,
+        IntIndex::SecondaryAta,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IntIndex::Timer => "timer",
+            IntIndex::Keyboard => "keyboard",
+            IntIndex::Serial => "serial",
+            IntIndex::PrimaryAta => "ata0",
+            IntIndex::SecondaryAta => "ata1",
+        }
+    }
+
+    /// Line of the chained PICs the interrupt arrives on.
+    pub fn line(self) -> usize {
+        (self as u8 - PIC_1_OFFSET) as usize
+    }
+
+    /// Times the interrupt was handled since boot.
+    pub fn count(self) -> u64 {
+        COUNTS[self.line()].load(Ordering::Relaxed)
+    }
+
+    fn record(self) {
+        COUNTS[self.line()].fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl From<IntIndex> for usize {
@@ -31,6 +87,122 @@ impl From<IntIndex> for u8 {
     }
 }
 
+/// Registers of the interrupted program, in the order pushed by the entry stubs.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// Pushed by the CPU for some exceptions, zero otherwise.
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    /// System call arguments, in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`.
+    pub fn args(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+
+    pub fn from_user(&self) -> bool {
+        self.cs & 3 == 3
+    }
+}
+
+/// Saves the general purpose registers in [`TrapFrame`] order.
+macro_rules! push_regs {
+    () => {
+        "push rax\npush rbx\npush rcx\npush rdx\npush rsi\npush rdi\npush rbp\npush r8
+         push r9\npush r10\npush r11\npush r12\npush r13\npush r14\npush r15"
+    };
+}
+
+macro_rules! pop_regs {
+    () => {
+        "pop r15\npop r14\npop r13\npop r12\npop r11\npop r10\npop r9\npop r8
+         pop rbp\npop rdi\npop rsi\npop rdx\npop rcx\npop rbx\npop rax"
+    };
+}
+
+/// Swaps in the other GS base when the frame on top of the stack, starting at the error code,
+/// was pushed by a trap from ring 3. Used on entry to load the kernel one, and on exit to give
+/// the user one back.
+macro_rules! swapgs_if_user {
+    () => {
+        "test qword ptr [rsp + 16], 3\njz 9f\nswapgs\n9:"
+    };
+}
+
+pub(crate) use {pop_regs, push_regs, swapgs_if_user};
+
+/// Defines an entry stub `$name` building a [`TrapFrame`] and passing it to `$handler`. Vectors
+/// for which the CPU doesn't push an error code get a zero in its place.
+macro_rules! trap_stub {
+    ($name:literal, $handler:path, $push_error:literal) => {
+        global_asm!(
+            concat!(".global ", $name),
+            concat!($name, ":"),
+            $push_error,
+            swapgs_if_user!(),
+            push_regs!(),
+            "mov rdi, rsp",
+            // rbp was saved above, and the stack is realigned for the call
+            "mov rbp, rsp",
+            "and rsp, -16",
+            "call {handler}",
+            "mov rsp, rbp",
+            pop_regs!(),
+            swapgs_if_user!(),
+            "add rsp, 8",
+            "iretq",
+            handler = sym $handler,
+        );
+    };
+}
+
+trap_stub!("timer_entry", timer_h, "push 0");
+trap_stub!("divide_error_entry", divide_error_h, "push 0");
+trap_stub!("invalid_opcode_entry", invalid_opcode_h, "push 0");
+trap_stub!("general_protection_entry", general_protection_h, "");
+trap_stub!("page_fault_entry", page_fault_h, "");
+trap_stub!("keyboard_entry", keyboard_h, "push 0");
+trap_stub!("serial_entry", serial_h, "push 0");
+trap_stub!("primary_ata_entry", primary_ata_h, "push 0");
+trap_stub!("secondary_ata_entry", secondary_ata_h, "push 0");
+
+extern "C" {
+    fn timer_entry();
+    fn divide_error_entry();
+    fn invalid_opcode_entry();
+    fn general_protection_entry();
+    fn page_fault_entry();
+    fn keyboard_entry();
+    fn serial_entry();
+    fn primary_ata_entry();
+    fn secondary_ata_entry();
+}
+
+fn stub_addr(stub: unsafe extern "C" fn()) -> VirtAddr {
+    VirtAddr::from_ptr(stub as *const ())
+}
+
 lazy_static::lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -38,9 +210,22 @@ lazy_static::lazy_static! {
         unsafe {
             idt.double_fault.set_handler_fn(double_fault_h).set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt.page_fault.set_handler_fn(page_fault_h);
-        idt[IntIndex::Timer.into()].set_handler_fn(timer_h);
-        idt[IntIndex::Keyboard.into()].set_handler_fn(keyboard_h);
+        unsafe {
+            idt.divide_error.set_handler_addr(stub_addr(divide_error_entry));
+            idt.invalid_opcode.set_handler_addr(stub_addr(invalid_opcode_entry));
+            idt.general_protection_fault.set_handler_addr(stub_addr(general_protection_entry));
+            idt.page_fault.set_handler_addr(stub_addr(page_fault_entry));
+            idt[IntIndex::Timer.into()].set_handler_addr(stub_addr(timer_entry));
+            idt[IntIndex::Keyboard.into()].set_handler_addr(stub_addr(keyboard_entry));
+            idt[IntIndex::Serial.into()].set_handler_addr(stub_addr(serial_entry));
+            idt[IntIndex::PrimaryAta.into()].set_handler_addr(stub_addr(primary_ata_entry));
+            idt[IntIndex::SecondaryAta.into()].set_handler_addr(stub_addr(secondary_ata_entry));
+        }
+        unsafe {
+            idt[syscall::INT_VECTOR.into()]
+                .set_handler_addr(syscall::int_entry_addr())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
         idt
     };
 }
@@ -73,55 +258,122 @@ extern "x86-interrupt" fn double_fault_h(stack_frame: InterruptStackFrame, error
     panic!("double fault exception");
 }
 
-extern "x86-interrupt" fn page_fault_h(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-    warn!("page fault exception");
-    info!("\terror code: {error_code:?}");
-    info!("\tacessed address: {:?}", Cr2::read());
-    info!("\tstack frame: {stack_frame:?}");
-    idle_mode(); // TODO: Recover from
+/// Faults raised by a user program become signals, while the kernel itself can't recover.
+fn user_fault(frame: &mut TrapFrame, name: &str, signal: u8) -> bool {
+    if !frame.from_user() {
+        return false;
+    }
+    warn!("{name} in user mode at {:#x}", frame.rip);
+    signal::force(signal);
+    signal::deliver(frame);
+    true
 }
 
-extern "x86-interrupt" fn timer_h(_stack_frame: InterruptStackFrame) {
-    let mut timer_w = unsafe { TIMER_TICKS.write() };
-    *timer_w += 1;
-    unsafe { PICS.lock().notify_end_of_interrupt(IntIndex::Timer.into()) }
+extern "C" fn divide_error_h(frame: &mut TrapFrame) {
+    if !user_fault(frame, "divide error", signal::SIGFPE) {
+        panic!("divide error exception\n{frame:#x?}");
+    }
 }
 
-extern "x86-interrupt" fn keyboard_h(_stack_frame: InterruptStackFrame) {
-    use x86_64::instructions::port::Port;
-
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
+extern "C" fn invalid_opcode_h(frame: &mut TrapFrame) {
+    if !user_fault(frame, "invalid opcode", signal::SIGILL) {
+        panic!("invalid opcode exception\n{frame:#x?}");
+    }
+}
 
-    lazy_static::lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-            Keyboard::new(ScancodeSet1::new(), layouts::Us104Key,HandleControl::Ignore)
-        );
+extern "C" fn general_protection_h(frame: &mut TrapFrame) {
+    if !user_fault(frame, "general protection fault", signal::SIGSEGV) {
+        panic!("general protection fault exception\n{frame:#x?}");
     }
+}
 
-    let mut kb = KEYBOARD.lock();
-    let mut port = Port::new(KEYBOARD_PORT);
+extern "C" fn page_fault_h(frame: &mut TrapFrame) {
+    use x86_64::registers::control::Cr2;
+    if user_fault(frame, "page fault", signal::SIGSEGV) {
+        return;
+    }
+    warn!("page fault exception");
+    info!(
+        "\terror code: {:?}",
+        PageFaultErrorCode::from_bits_truncate(frame.error_code)
+    );
+    info!("\tacessed address: {:?}", Cr2::read());
+    info!("\tstack frame: {frame:#x?}");
+    idle_mode();
+}
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = kb.add_byte(scancode) {
-        if let Some(key) = kb.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(ch) => print!("{ch}"),
-                DecodedKey::RawKey(rk) => print!("{rk:?}"),
-            }
-        }
+extern "C" fn timer_h(frame: &mut TrapFrame) {
+    let nesting = percpu::IntNesting::enter();
+    percpu::TICKS.get().fetch_add(1, Ordering::Relaxed);
+    IntIndex::Timer.record();
+    unsafe { PICS.lock().notify_end_of_interrupt(IntIndex::Timer.into()) }
+    // the next thread doesn't run inside this handler
+    drop(nesting);
+    task::tick();
+    if frame.from_user() {
+        signal::deliver(frame);
     }
+}
 
+extern "C" fn keyboard_h(_frame: &mut TrapFrame) {
+    let _nesting = percpu::IntNesting::enter();
+    IntIndex::Keyboard.record();
+    keyboard::handle_interrupt();
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(IntIndex::Keyboard.into())
     }
 }
 
+extern "C" fn serial_h(_frame: &mut TrapFrame) {
+    let _nesting = percpu::IntNesting::enter();
+    IntIndex::Serial.record();
+    serial::handle_interrupt();
+    unsafe { PICS.lock().notify_end_of_interrupt(IntIndex::Serial.into()) }
+}
+
+extern "C" fn primary_ata_h(_frame: &mut TrapFrame) {
+    ata_h(IntIndex::PrimaryAta);
+}
+
+extern "C" fn secondary_ata_h(_frame: &mut TrapFrame) {
+    ata_h(IntIndex::SecondaryAta);
+}
+
+fn ata_h(irq: IntIndex) {
+    let _nesting = percpu::IntNesting::enter();
+    irq.record();
+    ata::handle_interrupt(irq);
+    unsafe { PICS.lock().notify_end_of_interrupt(irq.into()) }
+}
+
 pub fn get_ticks() -> u128 {
-    unsafe { *TIMER_TICKS.read() }
+    percpu::TICKS.get().load(Ordering::Relaxed) as u128
+}
+
+/// Whole seconds since the timer started.
+pub fn uptime_secs() -> u64 {
+    (get_ticks() * PIT_DIVISOR / PIT_FREQUENCY_HZ) as u64
+}
+
+pub fn ms_to_ticks(ms: u64) -> u128 {
+    (ms as u128 * PIT_FREQUENCY_HZ).div_ceil(PIT_DIVISOR * 1000)
+}
+
+/// Queues typed bytes for [`read_input`].
+pub fn push_input(bytes: &[u8]) {
+    interrupts::without_interrupts(|| INPUT.lock().extend(bytes));
+    INPUT_READY.notify_all();
+}
+
+/// Moves typed bytes into `buf`, returning how many were available.
+pub fn read_input(buf: &mut [u8]) -> usize {
+    interrupts::without_interrupts(|| {
+        let mut input = INPUT.lock();
+        let len = buf.len().min(input.len());
+        for (dst, src) in buf.iter_mut().zip(input.drain(..len)) {
+            *dst = src;
+        }
+        len
+    })
 }
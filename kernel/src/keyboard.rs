@@ -0,0 +1,178 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard, ScancodeSet1,
+};
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::{
+    channel::{self, Receiver, Sender},
+    device::{self, CharDevice},
+    info, ints, monitor, okay, signal,
+    task::{self, WaitQueue},
+    vfs::FsError,
+};
+
+pub const KEYBOARD_PORT: u16 = 0x60;
+
+/// Scancodes buffered between the interrupt handler and the driver task.
+const SCANCODE_CAPACITY: usize = 128;
+const EVENT_CAPACITY: usize = 64;
+/// Bytes of a key event read from the device: the key code, then 0 for up, 1 for down or 2 for
+/// both at once.
+pub const RAW_EVENT_SIZE: usize = 2;
+
+/// Key events not read from the device yet, the oldest dropped when full.
+static RAW_EVENTS: spin::Mutex<VecDeque<[u8; RAW_EVENT_SIZE]>> = spin::Mutex::new(VecDeque::new());
+/// Notified when a key event is queued to [`RAW_EVENTS`].
+static RAW_EVENTS_READY: WaitQueue = WaitQueue::new();
+
+/// Set once the channels exist, as keys can be pressed before the heap is ready.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref SCANCODES: (Sender<u8>, Receiver<u8>) = channel::channel(SCANCODE_CAPACITY);
+    static ref EVENTS: (Sender<DecodedKey>, Receiver<DecodedKey>) = channel::channel(EVENT_CAPACITY);
+}
+
+/// Starts the driver task decoding scancodes, and the console task turning its key events into
+/// console input.
+pub fn init() {
+    info!("starting keyboard tasks");
+    lazy_static::initialize(&SCANCODES);
+    lazy_static::initialize(&EVENTS);
+    STARTED.store(true, Ordering::Release);
+    device::register(Arc::new(KeyboardEvents));
+    task::spawn(driver_main);
+    task::spawn(console_main);
+    okay!("started keyboard tasks");
+}
+
+/// Reads the pending scancode, called from the keyboard interrupt.
+pub fn handle_interrupt() {
+    let scancode: u8 = unsafe { Port::new(KEYBOARD_PORT).read() };
+    // keys are dropped when the driver can't keep up
+    if STARTED.load(Ordering::Acquire) {
+        let _ = SCANCODES.0.try_send(scancode);
+    }
+}
+
+/// A new consumer of the decoded keys. Each key goes to a single consumer, in typing order.
+pub fn events() -> Receiver<DecodedKey> {
+    EVENTS.1.clone()
+}
+
+fn driver_main() {
+    let mut keyboard = Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::MapLettersToUnicode,
+    );
+    // the left and right shift keys, held down
+    let mut shift = [false; 2];
+    while let Ok(scancode) = SCANCODES.1.receive() {
+        let Ok(Some(event)) = keyboard.add_byte(scancode) else {
+            continue;
+        };
+        push_raw_event(&event);
+        let down = event.state != KeyState::Up;
+        match event.code {
+            KeyCode::LShift => shift[0] = down,
+            KeyCode::RShift => shift[1] = down,
+            // shift with the page keys browses the scrollback instead of typing them
+            KeyCode::PageUp | KeyCode::PageDown if shift.contains(&true) => {
+                if down {
+                    monitor::scroll_view(event.code == KeyCode::PageUp);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(key) = keyboard.process_keyevent(event) {
+            let _ = EVENTS.0.try_send(key);
+        }
+    }
+}
+
+fn push_raw_event(event: &KeyEvent) {
+    let state = match event.state {
+        KeyState::Up => 0,
+        KeyState::Down => 1,
+        KeyState::SingleShot => 2,
+    };
+    interrupts::without_interrupts(|| {
+        let mut events = RAW_EVENTS.lock();
+        if events.len() == EVENT_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back([event.code as u8, state]);
+    });
+    RAW_EVENTS_READY.notify_all();
+}
+
+/// Presses and releases of keys as `keyboard`, each taking [`RAW_EVENT_SIZE`] bytes. Reads
+/// block until a key changes.
+pub struct KeyboardEvents;
+
+impl CharDevice for KeyboardEvents {
+    fn name(&self) -> &str {
+        "keyboard"
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let count = buf.len() / RAW_EVENT_SIZE;
+        if count == 0 {
+            return Err(FsError::Unsupported);
+        }
+        signal::wait(&RAW_EVENTS_READY, || {
+            let mut events = RAW_EVENTS.lock();
+            let len = count.min(events.len());
+            for (chunk, event) in buf
+                .chunks_exact_mut(RAW_EVENT_SIZE)
+                .zip(events.drain(..len))
+            {
+                chunk.copy_from_slice(&event);
+            }
+            (len > 0).then_some(len * RAW_EVENT_SIZE)
+        })
+        .map_err(|_| FsError::Interrupted)
+    }
+
+    fn mode(&self) -> u16 {
+        0o440
+    }
+}
+
+/// Queues typed keys as console input, the way a VT100 terminal sends them. Whoever reads the
+/// console echoes what it wants.
+fn console_main() {
+    let events = events();
+    while let Ok(key) = events.receive() {
+        let mut buf = [0; 4];
+        let bytes = match key {
+            DecodedKey::Unicode('\x7f') => "\x1b[3~".as_bytes(),
+            DecodedKey::Unicode(ch) => ch.encode_utf8(&mut buf).as_bytes(),
+            DecodedKey::RawKey(code) => match escape_sequence(code) {
+                Some(sequence) => sequence.as_bytes(),
+                None => continue,
+            },
+        };
+        ints::push_input(bytes);
+    }
+}
+
+fn escape_sequence(code: KeyCode) -> Option<&'static str> {
+    Some(match code {
+        KeyCode::ArrowUp => "\x1b[A",
+        KeyCode::ArrowDown => "\x1b[B",
+        KeyCode::ArrowRight => "\x1b[C",
+        KeyCode::ArrowLeft => "\x1b[D",
+        KeyCode::Home => "\x1b[H",
+        KeyCode::End => "\x1b[F",
+        KeyCode::Insert => "\x1b[2~",
+        KeyCode::PageUp => "\x1b[5~",
+        KeyCode::PageDown => "\x1b[6~",
+        _ => return None,
+    })
+}
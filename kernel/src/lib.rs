@@ -6,35 +6,60 @@ extern crate alloc;
 
 use core::fmt;
 
-use bootloader_api::{info::FrameBuffer, BootInfo};
+use bootloader_api::BootInfo;
 use mem::BootInfoFrameAllocator;
-use monitor::{FrameBufferWriter, RgbColor};
+use monitor::RgbColor;
 
 pub mod allocator;
+pub mod ata;
+pub mod block;
+pub mod cache;
+pub mod channel;
+pub mod devfs;
+pub mod device;
+pub mod elf;
+pub mod ext2;
+pub mod fat;
+pub mod fd;
 pub mod gdt;
+pub mod initrd;
 pub mod ints;
+pub mod keyboard;
 pub mod mem;
 pub mod monitor;
+pub mod partition;
+pub mod percpu;
+pub mod pipe;
+pub mod power;
+pub mod process;
+pub mod procfs;
+pub mod ramdisk;
+pub mod ramfs;
+pub mod script;
+pub mod serial;
+pub mod shell;
+pub mod shm;
+pub mod signal;
+pub mod syscall;
+pub mod task;
 #[cfg(debug_assertions)]
 pub mod test_runner;
+pub mod user;
+pub mod vfs;
 
-use spin::mutex::Mutex;
-use uart_16550::SerialPort;
 use x86_64::{structures::paging::OffsetPageTable, VirtAddr};
-pub static mut MONITOR_OUT: Mutex<Option<FrameBufferWriter>> = Mutex::new(None);
-pub static mut SERIAL_OUT: Mutex<Option<SerialPort>> = Mutex::new(None);
 
 pub static mut PAGE_MAPPER: Option<OffsetPageTable> = None;
 pub static mut FRAME_ALLOCATOR: Option<BootInfoFrameAllocator> = None;
 
-pub const SERIAL_IO_PORT: u16 = 0x3F8;
-
 pub fn init(info: &'static mut BootInfo) {
-    setup_monitor(info.framebuffer.as_mut().unwrap());
+    serial::init();
+    monitor::init(info.framebuffer.as_mut().unwrap());
     okay!("monitor started");
 
     gdt::init();
     ints::init();
+    syscall::init();
 
     info!("initializing pics");
     unsafe { ints::PICS.lock().initialize() };
@@ -64,20 +89,23 @@ pub fn init(info: &'static mut BootInfo) {
         .unwrap();
     }
     okay!("paged heap");
-}
 
-pub fn setup_monitor(fb: &'static mut FrameBuffer) {
-    let fb_info = fb.info();
-    let fb_buffer = fb.buffer_mut();
-    let monitor = monitor::FrameBufferWriter::new(fb_buffer, fb_info);
-    unsafe {
-        MONITOR_OUT = Some(monitor).into();
-    }
-    unsafe {
-        let mut sp = SerialPort::new(SERIAL_IO_PORT);
-        sp.init();
-        SERIAL_OUT = Some(sp).into();
-    }
+    info!("reserving kernel page tables");
+    mem::reserve_kernel_tables();
+    okay!("reserved kernel page tables");
+
+    process::init();
+    vfs::init();
+    initrd::init(info.ramdisk_addr.into_option(), info.ramdisk_len);
+    device::init();
+    serial::register();
+    monitor::register();
+    keyboard::init();
+    ata::init();
+    partition::init();
+    devfs::init();
+    procfs::init();
+    shell::init();
 }
 
 pub const INFO_COLOR: RgbColor = RgbColor::new(127, 127, 127);
@@ -145,18 +173,11 @@ macro_rules! println {
     }}
 }
 
+/// Prints in `color` with an SGR escape sequence, so every console shows it.
 pub fn internal_colored_print(fmt: fmt::Arguments, color: RgbColor) {
-    use x86_64::instructions::interrupts;
-
-    interrupts::without_interrupts(|| {
-        use core::fmt::Write;
-        let mut serial_lock = unsafe { crate::SERIAL_OUT.lock() };
-        let serial = serial_lock.as_mut().unwrap();
-        serial.write_fmt(fmt).unwrap();
-
-        let mut monitor_lock = unsafe { crate::MONITOR_OUT.lock() };
-        let monitor = monitor_lock.as_mut().unwrap();
-        monitor.color = color;
-        monitor.write_fmt(fmt).unwrap();
-    })
+    if color == WHITE_COLOR {
+        return device::log(fmt);
+    }
+    let RgbColor { r, g, b } = color;
+    device::log(format_args!("\x1b[38;2;{r};{g};{b}m{fmt}\x1b[39m"));
 }
@@ -6,7 +6,7 @@ use core::panic::PanicInfo;
 use bootloader_api::config::Mapping;
 use bootloader_api::BootloaderConfig;
 use kernel::monitor::RgbColor;
-use kernel::{okay, print, println};
+use kernel::{print, println};
 
 extern crate alloc;
 
@@ -23,6 +23,7 @@ fn panic_handler(_info: &PanicInfo) -> ! {
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
     config.mappings.physical_memory = Some(Mapping::Dynamic);
+    config.mappings.dynamic_range_start = Some(0xFFFF_8000_0000_0000);
     config
 };
 
@@ -38,13 +39,6 @@ fn kernel_main(info: &'static mut bootloader_api::BootInfo) -> ! {
     print!("yaay, welcome to ");
     println!(RgbColor::new(0, 255, 0) => "tchaiOS!");
 
-    print!("(root) [/]: ");
-
-    loop {
-        let ticks = kernel::ints::get_ticks();
-        while kernel::ints::get_ticks() - ticks < 20 {}
-        okay!("still running");
-        #[cfg(test)]
-        info!("test mode");
-    }
+    kernel::shell::start();
+    kernel::ints::idle_mode();
 }
@@ -1,9 +1,18 @@
+use alloc::vec::Vec;
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
 use x86_64::{
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    instructions::interrupts,
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::{
+        mapper::{MapToError, TranslateResult},
+        FrameAllocator, FrameDeallocator, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB, Translate,
+    },
     PhysAddr, VirtAddr,
 };
 
+use crate::user;
+
 pub unsafe fn active_level_4_table(phys_mem_offset: VirtAddr) -> &'static mut PageTable {
     use x86_64::registers::control::Cr3;
     let (level_4_table_frame, _) = Cr3::read();
@@ -16,14 +25,28 @@ pub unsafe fn active_level_4_table(phys_mem_offset: VirtAddr) -> &'static mut Pa
 
 pub const PAGE_SIZE: usize = 1024 * 4;
 
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    let mapper = unsafe { crate::PAGE_MAPPER.as_ref().unwrap() };
+    mapper.phys_offset() + addr.as_u64()
+}
+
 pub unsafe fn init(phys_mem_offset: VirtAddr) -> OffsetPageTable<'static> {
     let l4_table = active_level_4_table(phys_mem_offset);
     OffsetPageTable::new(l4_table, phys_mem_offset)
 }
 
+/// Counts of usable physical frames.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStats {
+    pub total: usize,
+    /// Frames handed out and not freed since.
+    pub used: usize,
+}
+
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryRegions,
     next: usize,
+    freed: Vec<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
@@ -31,6 +54,20 @@ impl BootInfoFrameAllocator {
         Self {
             memory_map,
             next: 0,
+            freed: Vec::new(),
+        }
+    }
+
+    /// Regions of physical memory as the bootloader described them.
+    pub fn memory_map(&self) -> &'static MemoryRegions {
+        self.memory_map
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let total = Self::usable_frames(self.memory_map).count();
+        FrameStats {
+            total,
+            used: self.next.min(total).saturating_sub(self.freed.len()),
         }
     }
 
@@ -45,8 +82,220 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.freed.pop() {
+            return Some(frame);
+        }
         let frame = Self::usable_frames(self.memory_map).nth(self.next);
         self.next += 1;
         frame
     }
 }
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.freed.push(frame);
+    }
+}
+
+/// Handle to the global frame allocator, usable from any thread.
+pub struct GlobalFrames;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // a thread preempted in the middle of an allocation would leave the allocator half updated
+        interrupts::without_interrupts(|| unsafe {
+            crate::FRAME_ALLOCATOR.as_mut().unwrap().allocate_frame()
+        })
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for GlobalFrames {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        interrupts::without_interrupts(|| unsafe {
+            crate::FRAME_ALLOCATOR
+                .as_mut()
+                .unwrap()
+                .deallocate_frame(frame)
+        })
+    }
+}
+
+pub fn frame_allocator() -> GlobalFrames {
+    GlobalFrames
+}
+
+pub fn frame_stats() -> FrameStats {
+    interrupts::without_interrupts(|| unsafe { crate::FRAME_ALLOCATOR.as_ref().unwrap().stats() })
+}
+
+pub fn memory_map() -> &'static MemoryRegions {
+    unsafe { crate::FRAME_ALLOCATOR.as_ref().unwrap().memory_map() }
+}
+
+/// Fills the frame with zeroes through the physical memory mapping.
+pub fn zero_frame(frame: PhysFrame) {
+    let virt = phys_to_virt(frame.start_address());
+    unsafe { virt.as_mut_ptr::<u8>().write_bytes(0, PAGE_SIZE) };
+}
+
+/// Gives every unused kernel entry of the boot level 4 table an empty level 3 table. Address
+/// spaces copy these entries when created, so mappings the kernel adds later still show up in
+/// all of them.
+pub fn reserve_kernel_tables() {
+    let kernel = unsafe { crate::PAGE_MAPPER.as_mut().unwrap() }.level_4_table();
+    let first_kernel_entry = (user::USER_END >> 39) as usize;
+    for entry in kernel.iter_mut().skip(first_kernel_entry) {
+        if entry.is_unused() {
+            let frame = GlobalFrames
+                .allocate_frame()
+                .expect("no frame for a kernel page table");
+            zero_frame(frame);
+            entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+    }
+}
+
+/// Marks user pages whose frame belongs to a shared memory region, so tearing down an address
+/// space leaves the frame to the region.
+pub const SHARED: PageTableFlags = PageTableFlags::BIT_9;
+
+/// A level 4 table with its own user half, sharing every kernel mapping with the boot table.
+pub struct AddressSpace {
+    l4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    pub fn new() -> Option<Self> {
+        let l4_frame = GlobalFrames.allocate_frame()?;
+        zero_frame(l4_frame);
+        let mut space = Self { l4_frame };
+
+        // every kernel entry already points to a level 3 table, see `reserve_kernel_tables`
+        let kernel = unsafe { crate::PAGE_MAPPER.as_mut().unwrap() }.level_4_table();
+        let first_kernel_entry = (user::USER_END >> 39) as usize;
+        let table = space.table();
+        for i in first_kernel_entry..512 {
+            table[i] = kernel[i].clone();
+        }
+        Some(space)
+    }
+
+    fn table(&mut self) -> &mut PageTable {
+        unsafe { &mut *phys_to_virt(self.l4_frame.start_address()).as_mut_ptr() }
+    }
+
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let offset = unsafe { crate::PAGE_MAPPER.as_ref().unwrap() }.phys_offset();
+        unsafe { OffsetPageTable::new(self.table(), offset) }
+    }
+
+    pub fn l4_frame(&self) -> PhysFrame {
+        self.l4_frame
+    }
+
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.l4_frame
+    }
+
+    /// Switches the running CPU to this address space.
+    pub unsafe fn activate(&self) {
+        Cr3::write(self.l4_frame, Cr3Flags::empty());
+    }
+
+    /// Maps a zeroed user page, returning its frame.
+    pub fn map_user(
+        &mut self,
+        page: Page,
+        flags: PageTableFlags,
+    ) -> Result<PhysFrame, MapToError<Size4KiB>> {
+        let frame = user::map_user_page(&mut self.mapper(), &mut GlobalFrames, page, flags)?;
+        zero_frame(frame);
+        Ok(frame)
+    }
+
+    pub fn translate(&mut self, addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+        match self.mapper().translate(addr) {
+            TranslateResult::Mapped {
+                frame,
+                offset,
+                flags,
+            } => Some((frame.start_address() + offset, flags)),
+            _ => None,
+        }
+    }
+
+    /// Copies `data` to `addr`, which must already be mapped, regardless of the page permissions.
+    pub fn write(&mut self, addr: VirtAddr, data: &[u8]) -> Option<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let at = addr + done;
+            let (phys, _) = self.translate(at)?;
+            let len = (PAGE_SIZE - usize::from(at.page_offset())).min(data.len() - done);
+            let dst = phys_to_virt(phys).as_mut_ptr::<u8>();
+            unsafe { core::ptr::copy_nonoverlapping(data[done..].as_ptr(), dst, len) };
+            done += len;
+        }
+        Some(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+        let first_kernel_entry = (user::USER_END >> 39) as usize;
+        let l4 = self.table();
+        for l4_entry in l4.iter().take(first_kernel_entry) {
+            if l4_entry.is_unused() {
+                continue;
+            }
+            free_table(l4_entry.frame().unwrap(), 3);
+        }
+        unsafe { GlobalFrames.deallocate_frame(self.l4_frame) };
+    }
+}
+
+/// Frees the frames mapped by a table of the given `level` and the table itself.
+fn free_table(frame: PhysFrame, level: usize) {
+    let table: &PageTable = unsafe { &*phys_to_virt(frame.start_address()).as_ptr() };
+    for entry in table.iter().filter(|e| !e.is_unused()) {
+        let frame = entry.frame().unwrap();
+        if level == 1 {
+            if !entry.flags().contains(SHARED) {
+                unsafe { GlobalFrames.deallocate_frame(frame) };
+            }
+        } else {
+            free_table(frame, level - 1);
+        }
+    }
+    unsafe { GlobalFrames.deallocate_frame(frame) };
+}
+
+pub fn kernel_l4_frame() -> PhysFrame {
+    let mapper = unsafe { crate::PAGE_MAPPER.as_mut().unwrap() };
+    let offset = mapper.phys_offset();
+    let virt = VirtAddr::from_ptr(mapper.level_4_table() as *const PageTable);
+    PhysFrame::containing_address(PhysAddr::new(virt - offset))
+}
+
+/// Switches back to the page tables set up by the bootloader, which only map the kernel.
+pub unsafe fn activate_kernel_space() {
+    let (_, flags) = Cr3::read();
+    Cr3::write(kernel_l4_frame(), flags);
+}
+
+/// Mapper for the page tables the running CPU is using.
+pub fn active_mapper() -> OffsetPageTable<'static> {
+    let offset = unsafe { crate::PAGE_MAPPER.as_ref().unwrap() }.phys_offset();
+    unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) }
+}
+
+pub fn translate_active(addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+    match active_mapper().translate(addr) {
+        TranslateResult::Mapped {
+            frame,
+            offset,
+            flags,
+        } => Some((frame.start_address() + offset, flags)),
+        _ => None,
+    }
+}
@@ -1,9 +1,23 @@
-use bootloader_api::info::FrameBufferInfo;
-use core::{fmt, ptr};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use bootloader_api::info::{FrameBuffer, FrameBufferInfo};
+use core::{
+    fmt::{self, Write},
+    ops::Range,
+    ptr,
+    sync::atomic::Ordering,
+};
 use font_constants::BACKUP_CHAR;
 use noto_sans_mono_bitmap::{
     get_raster, get_raster_width, FontWeight, RasterHeight, RasterizedChar,
 };
+use x86_64::instructions::interrupts;
+
+use crate::{
+    allocator,
+    device::{self, CharDevice},
+    info, ints, okay, task,
+    vfs::FsError,
+};
 
 /// Additional vertical space between lines
 const LINE_SPACING: usize = 2;
@@ -32,18 +46,25 @@ mod font_constants {
 }
 
 /// Returns the raster of the given char or the raster of [`font_constants::BACKUP_CHAR`].
-fn get_char_raster(c: char) -> RasterizedChar {
-    fn get(c: char) -> Option<RasterizedChar> {
-        get_raster(
-            c,
-            font_constants::FONT_WEIGHT,
-            font_constants::CHAR_RASTER_HEIGHT,
-        )
-    }
+fn get_char_raster(c: char, weight: FontWeight) -> RasterizedChar {
+    let get = |c| get_raster(c, weight, font_constants::CHAR_RASTER_HEIGHT);
     get(c).unwrap_or_else(|| get(BACKUP_CHAR).expect("Should get raster of backup char."))
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// Reads the color of SGR 38 or 48 from the parameters after it, either `5;index` in the 256
+/// color palette or `2;r;g;b`.
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<RgbColor> {
+    match params.next()? {
+        5 => Some(palette_color(params.next()?.min(255) as u8)),
+        2 => {
+            let mut channel = || params.next().map(|value| value.min(255) as u8);
+            Some(RgbColor::new(channel()?, channel()?, channel()?))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RgbColor {
     pub r: u8,
     pub g: u8,
@@ -62,6 +83,18 @@ impl RgbColor {
     pub fn into_reversed_array(&self) -> [u8; 4] {
         [self.b, self.g, self.r, 0]
     }
+
+    /// Mixes in `other` by `amount` out of 255.
+    pub fn blend(self, other: Self, amount: u8) -> Self {
+        let mix = |from: u8, to: u8| {
+            (from as i32 + (to as i32 - from as i32) * amount as i32 / 255) as u8
+        };
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
 }
 
 impl core::ops::Mul<f32> for RgbColor {
@@ -74,91 +107,855 @@ impl core::ops::Mul<f32> for RgbColor {
     }
 }
 
-/// Allows logging text to a pixel-based framebuffer.
+/// Width of a character cell in pixels.
+pub(crate) const CELL_WIDTH: usize = font_constants::CHAR_RASTER_WIDTH + LETTER_SPACING;
+/// Height of a character cell, and of a line of text, in pixels.
+pub(crate) const CELL_HEIGHT: usize = font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
+
+/// Most cells of the screen, the rest of bigger framebuffers being left black.
+const MAX_COLUMNS: usize = 256;
+const MAX_ROWS: usize = 128;
+/// Lines kept once they scroll off the top of the screen.
+const SCROLLBACK_LINES: usize = 4000;
+/// Most bytes the text and styles of the scrollback take, as long lines of styled text would
+/// fill the heap well before [`SCROLLBACK_LINES`].
+const SCROLLBACK_BYTES: usize = allocator::HEAP_SIZE / 4;
+/// Most parameters of a control sequence, the others being ignored.
+const MAX_PARAMS: usize = 16;
+/// Time the blinking cursor stays shown, then hidden.
+const BLINK_INTERVAL_MS: u64 = 500;
+/// Pixels across the underline and bar cursors.
+const CURSOR_THICKNESS: usize = 2;
+
+/// What the screen shows, kept outside the heap as text is written before it exists.
+static mut SCREEN: [[Cell; MAX_COLUMNS]; MAX_ROWS] = [[Cell::BLANK; MAX_COLUMNS]; MAX_ROWS];
+
+/// The 16 colors of the SGR codes 30 to 37 and 90 to 97, as xterm shows them.
+const ANSI_COLORS: [RgbColor; 16] = [
+    RgbColor::new(0, 0, 0),
+    RgbColor::new(205, 0, 0),
+    RgbColor::new(0, 205, 0),
+    RgbColor::new(205, 205, 0),
+    RgbColor::new(0, 0, 238),
+    RgbColor::new(205, 0, 205),
+    RgbColor::new(0, 205, 205),
+    RgbColor::new(229, 229, 229),
+    RgbColor::new(127, 127, 127),
+    RgbColor::new(255, 0, 0),
+    RgbColor::new(0, 255, 0),
+    RgbColor::new(255, 255, 0),
+    RgbColor::new(92, 92, 255),
+    RgbColor::new(255, 0, 255),
+    RgbColor::new(0, 255, 255),
+    RgbColor::new(255, 255, 255),
+];
+
+/// A color of the xterm 256 color palette: the 16 ANSI colors, a 6x6x6 cube, then 24 grays.
+fn palette_color(index: u8) -> RgbColor {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match index {
+        0..=15 => ANSI_COLORS[index as usize],
+        16..=231 => {
+            let cube = index - 16;
+            RgbColor::new(
+                LEVELS[cube as usize / 36],
+                LEVELS[cube as usize / 6 % 6],
+                LEVELS[cube as usize % 6],
+            )
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            RgbColor::new(gray, gray, gray)
+        }
+    }
+}
+
+/// Attributes of [`Style`], set by SGR 1, 4 and 7.
+pub(crate) const BOLD: u8 = 1 << 0;
+pub(crate) const UNDERLINE: u8 = 1 << 1;
+/// Swaps the foreground and background colors.
+pub(crate) const REVERSE: u8 = 1 << 2;
+
+/// How text is drawn, as set by SGR sequences.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Style {
+    pub(crate) fg: RgbColor,
+    pub(crate) bg: RgbColor,
+    pub(crate) attrs: u8,
+}
+
+impl Style {
+    pub(crate) const DEFAULT: Self = Self {
+        fg: RgbColor::new(255, 255, 255),
+        bg: RgbColor::new(0, 0, 0),
+        attrs: 0,
+    };
+}
+
+/// A character of the screen, with how to draw it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Cell {
+    pub(crate) ch: char,
+    pub(crate) style: Style,
+}
+
+impl Cell {
+    pub(crate) const BLANK: Self = Self {
+        ch: ' ',
+        style: Style::DEFAULT,
+    };
+}
+
+/// A line of the scrollback, stored as its text and the columns where its style changes as
+/// it would take a cell for each character otherwise.
+struct Line {
+    text: Box<str>,
+    styles: Box<[(u16, Style)]>,
+}
+
+impl Line {
+    fn new(cells: &[Cell]) -> Self {
+        let len = cells
+            .iter()
+            .rposition(|cell| *cell != Cell::BLANK)
+            .map_or(0, |i| i + 1);
+        let mut styles: Vec<(u16, Style)> = Vec::new();
+        for (column, cell) in cells[..len].iter().enumerate() {
+            if styles.last().is_none_or(|(_, style)| *style != cell.style) {
+                styles.push((column as u16, cell.style));
+            }
+        }
+        Self {
+            text: cells[..len].iter().map(|cell| cell.ch).collect(),
+            styles: styles.into(),
+        }
+    }
+
+    /// Bytes taken on the heap.
+    fn bytes(&self) -> usize {
+        size_of::<Self>() + self.text.len() + size_of_val(&*self.styles)
+    }
+
+    fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        let mut styles = self.styles.iter().peekable();
+        let mut style = Style::DEFAULT;
+        self.text.chars().enumerate().map(move |(column, ch)| {
+            while let Some((_, next)) = styles.next_if(|(start, _)| *start as usize <= column) {
+                style = *next;
+            }
+            Cell { ch, style }
+        })
+    }
+}
+
+/// How the cursor is drawn over the cell it's on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CursorStyle {
+    /// The whole cell, in reverse video.
+    Block,
+    /// A line under the character.
+    Underline,
+    /// A line left of the character.
+    Bar,
+}
+
+/// Where the writer is in an escape sequence.
+#[derive(Clone, Copy)]
+enum Escape {
+    /// Plain text.
+    None,
+    /// After `ESC`.
+    Started,
+    /// In a control sequence, after `ESC [`, collecting its parameters.
+    Csi {
+        params: [u16; MAX_PARAMS],
+        /// Parameters started, the last one being the one read.
+        len: usize,
+        /// Set by a `?` before the parameters.
+        private: bool,
+    },
+}
+
+/// Allows logging text to a pixel-based framebuffer. The text scrolls up as lines are added at
+/// the bottom, and the lines going off the top can be browsed back.
+///
+/// Understands the VT100 and xterm escape sequences for colors, moving the cursor and erasing.
+/// Text is kept as a grid of cells, and only the cells changed since the last
+/// [`flush`](Self::flush) are drawn again.
 pub struct FrameBufferWriter {
     pub framebuffer: &'static mut [u8],
     pub info: FrameBufferInfo,
-    pub color: RgbColor,
-    pub x_pos: usize,
-    pub y_pos: usize,
+    /// The cells of each row, at least as many as the screen has.
+    screen: &'static mut [[Cell; MAX_COLUMNS]],
+    /// Columns of each row changed since the last flush, from the first to before the second.
+    dirty: [(u16, u16); MAX_ROWS],
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    style: Style,
+    /// Position and style kept by `ESC 7` until `ESC 8`.
+    saved: (usize, usize, Style),
+    escape: Escape,
+    /// Cleared by `ESC [ ? 25 l`, and set again by `ESC [ ? 25 h`.
+    cursor_visible: bool,
+    cursor_style: CursorStyle,
+    cursor_blinks: bool,
+    /// Cleared while a blinking cursor is hidden.
+    blink_on: bool,
+    /// Where the cursor was drawn, over the cell it's on.
+    drawn_cursor: Option<(usize, usize)>,
+    scrollback: VecDeque<Line>,
+    /// What the lines of the scrollback take, up to [`SCROLLBACK_BYTES`].
+    scrollback_bytes: usize,
+    /// How many lines of the scrollback are shown above the screen while browsing it.
+    view: usize,
+    /// Set when the framebuffer and the cells were allocated by [`detached`](Self::detached),
+    /// and are freed with the writer.
+    owned: bool,
 }
 
 impl FrameBufferWriter {
     /// Creates a new logger that uses the given framebuffer.
-    pub fn new(framebuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+    ///
+    /// # Safety
+    ///
+    /// There can only be one writer made this way, as they all keep their text in [`SCREEN`].
+    pub unsafe fn new(framebuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+        Self::with_screen(framebuffer, info, unsafe {
+            &mut *ptr::addr_of_mut!(SCREEN)
+        })
+    }
+
+    /// Creates a writer drawing to a framebuffer of its own on the heap, with its text there too
+    /// rather than in [`SCREEN`], so that it can be used besides the console, as the tests do.
+    pub(crate) fn detached(info: FrameBufferInfo) -> Self {
+        let (_, rows) = Self::grid_size(&info);
+        let framebuffer = Box::leak(alloc::vec![0; info.byte_len].into_boxed_slice());
+        let screen = Box::leak(alloc::vec![[Cell::BLANK; MAX_COLUMNS]; rows].into_boxed_slice());
+        let mut writer = Self::with_screen(framebuffer, info, screen);
+        writer.owned = true;
+        writer
+    }
+
+    fn with_screen(
+        framebuffer: &'static mut [u8],
+        info: FrameBufferInfo,
+        screen: &'static mut [[Cell; MAX_COLUMNS]],
+    ) -> Self {
+        let (columns, rows) = Self::grid_size(&info);
         let mut logger = Self {
             framebuffer,
             info,
-            color: RgbColor::new(255, 255, 255),
-            x_pos: 0,
-            y_pos: 0,
+            screen,
+            dirty: [(0, 0); MAX_ROWS],
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            style: Style::DEFAULT,
+            saved: (0, 0, Style::DEFAULT),
+            escape: Escape::None,
+            cursor_visible: true,
+            cursor_style: CursorStyle::Block,
+            cursor_blinks: true,
+            blink_on: true,
+            drawn_cursor: None,
+            scrollback: VecDeque::new(),
+            scrollback_bytes: 0,
+            view: 0,
+            owned: false,
         };
         logger.clear();
         logger
     }
 
     fn newline(&mut self) {
-        self.y_pos += font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
-        self.carriage_return()
+        self.carriage_return();
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll_up();
+        }
     }
 
     fn carriage_return(&mut self) {
-        self.x_pos = BORDER_PADDING;
+        self.column = 0;
+    }
+
+    /// Moves back one character without erasing it, to the end of the line above at the start of
+    /// a line.
+    fn cursor_left(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.column = self.columns - 1;
+        }
     }
 
-    /// Erases all text on the screen. Resets `self.x_pos` and `self.y_pos`.
+    /// The columns and rows of cells fitting in a framebuffer.
+    fn grid_size(info: &FrameBufferInfo) -> (usize, usize) {
+        let columns = (info.width - 2 * BORDER_PADDING) / CELL_WIDTH;
+        let rows = (info.height - 2 * BORDER_PADDING) / CELL_HEIGHT;
+        (columns.min(MAX_COLUMNS), rows.min(MAX_ROWS))
+    }
+
+    /// The columns and rows of the screen.
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    /// The column and row of the cursor, counted from 0 at the top left. The column is past the
+    /// last one once a row is full, until the next character goes to the row below.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    pub(crate) fn cell(&self, column: usize, row: usize) -> Cell {
+        self.screen[row][column]
+    }
+
+    /// The columns of `row` to draw again with the next flush.
+    pub(crate) fn dirty(&self, row: usize) -> Range<usize> {
+        let (start, end) = self.dirty[row];
+        start as usize..end.max(start) as usize
+    }
+
+    /// The cells of the line `back` lines above the screen in the scrollback, up to its last
+    /// one that isn't blank.
+    pub(crate) fn scrollback_line(&self, back: usize) -> Option<Vec<Cell>> {
+        let index = self.scrollback.len().checked_sub(back)?;
+        Some(self.scrollback.get(index)?.cells().collect())
+    }
+
+    /// The color of the pixel `x` and `y` across the cell at `column` and `row`, as drawn in
+    /// an RGB framebuffer.
+    pub(crate) fn cell_pixel(&self, column: usize, row: usize, x: usize, y: usize) -> RgbColor {
+        let x = BORDER_PADDING + column * CELL_WIDTH + x;
+        let y = BORDER_PADDING + row * CELL_HEIGHT + y;
+        let offset = (y * self.info.stride + x) * self.info.bytes_per_pixel;
+        let [r, g, b] = [0, 1, 2].map(|byte| self.framebuffer[offset + byte]);
+        RgbColor::new(r, g, b)
+    }
+
+    /// Moves the cursor within the screen.
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row = row.min(self.rows - 1);
+        self.column = column.min(self.columns - 1);
+    }
+
+    /// The column of the cursor, which is past the last one once the row is full.
+    fn cursor_column(&self) -> usize {
+        self.column.min(self.columns - 1)
+    }
+
+    /// Goes back to the position and style saved by `ESC 7`, past the last column too, so that
+    /// text written next wraps as it would have.
+    fn restore_cursor(&mut self) {
+        let (row, column, style) = self.saved;
+        self.row = row.min(self.rows - 1);
+        self.column = column.min(self.columns);
+        self.style = style;
+    }
+
+    /// Erases all text on the screen, starting again at the top. The scrollback is kept.
     pub fn clear(&mut self) {
-        self.x_pos = BORDER_PADDING;
-        self.y_pos = BORDER_PADDING;
+        self.column = 0;
+        self.row = 0;
+        self.view = 0;
+        self.screen[..self.rows]
+            .iter_mut()
+            .for_each(|row| row.fill(Cell::BLANK));
+        self.dirty.fill((0, 0));
+        self.drawn_cursor = None;
         self.framebuffer.fill(0);
     }
 
-    fn width(&self) -> usize {
-        self.info.width
+    fn mark_dirty(&mut self, row: usize, start: usize, end: usize) {
+        let (first, last) = &mut self.dirty[row];
+        if first >= last {
+            (*first, *last) = (start as u16, end as u16);
+        } else {
+            *first = (*first).min(start as u16);
+            *last = (*last).max(end as u16);
+        }
+    }
+
+    fn set_cell(&mut self, row: usize, column: usize, cell: Cell) {
+        if self.screen[row][column] != cell {
+            self.screen[row][column] = cell;
+            self.mark_dirty(row, column, column + 1);
+        }
+    }
+
+    /// An erased cell, which keeps the current background color.
+    fn blank(&self) -> Cell {
+        Cell {
+            ch: ' ',
+            style: Style {
+                bg: self.style.bg,
+                ..Style::DEFAULT
+            },
+        }
+    }
+
+    /// Erases the cells from `start` to before `end`, counted from the top left of the screen.
+    fn erase(&mut self, start: usize, end: usize) {
+        let blank = self.blank();
+        for index in start..end {
+            self.set_cell(index / self.columns, index % self.columns, blank);
+        }
+    }
+
+    /// Moves the cells of the cursor row from `from` on to `to` on, as inserting or deleting
+    /// characters does, and erases those left behind.
+    fn shift_cells(&mut self, from: usize, to: usize) {
+        let (row, columns) = (self.row, self.columns);
+        let len = columns - from.max(to);
+        self.screen[row].copy_within(from..from + len, to);
+        let blank = self.blank();
+        let erased = if from < to {
+            from..to
+        } else {
+            to + len..columns
+        };
+        self.screen[row][erased].fill(blank);
+        self.mark_dirty(row, from.min(to), columns);
+    }
+
+    /// Moves the rows from `from` on to `to` on, as inserting or deleting lines does, and erases
+    /// those left behind.
+    fn shift_rows(&mut self, from: usize, to: usize) {
+        let len = self.rows - from.max(to);
+        self.screen.copy_within(from..from + len, to);
+        let blank = self.blank();
+        let erased = if from < to {
+            from..to
+        } else {
+            to + len..self.rows
+        };
+        self.screen[erased]
+            .iter_mut()
+            .for_each(|row| row.fill(blank));
+        for row in from.min(to)..self.rows {
+            self.mark_dirty(row, 0, self.columns);
+        }
+    }
+
+    /// Bytes of the framebuffer taken by a line of text.
+    fn line_bytes(&self) -> usize {
+        CELL_HEIGHT * self.info.stride * self.info.bytes_per_pixel
+    }
+
+    /// Offset in the framebuffer of the line of text `row`.
+    fn line_offset(&self, row: usize) -> usize {
+        (BORDER_PADDING * self.info.stride) * self.info.bytes_per_pixel + row * self.line_bytes()
+    }
+
+    /// Moves the text up a line, keeping the top one in the scrollback, and leaves the bottom
+    /// line empty.
+    fn scroll_up(&mut self) {
+        // lines scrolled off before the heap is ready are lost
+        if allocator::HEAP_READY.load(Ordering::Acquire) {
+            let line = Line::new(&self.screen[0][..self.columns]);
+            self.scrollback_bytes += line.bytes();
+            self.scrollback.push_back(line);
+            while self.scrollback.len() > SCROLLBACK_LINES
+                || self.scrollback_bytes > SCROLLBACK_BYTES
+            {
+                let Some(oldest) = self.scrollback.pop_front() else {
+                    break;
+                };
+                self.scrollback_bytes -= oldest.bytes();
+            }
+        }
+        // the pixels are moved along with the cells, so they must match them
+        if let Some((row, column)) = self.drawn_cursor.take() {
+            self.mark_dirty(row, column, column + 1);
+        }
+        self.draw_dirty(None);
+        self.screen[..self.rows].rotate_left(1);
+        let blank = self.blank();
+        self.screen[self.rows - 1].fill(blank);
+        // the saved position stays on the text it was saved on
+        self.saved.0 = self.saved.0.saturating_sub(1);
+
+        let (top, bottom) = (self.line_offset(0), self.line_offset(self.rows));
+        let line = self.line_bytes();
+        self.framebuffer.copy_within(top + line..bottom, top);
+        self.framebuffer[bottom - line..bottom].fill(0);
+        // the background color is drawn with the next flush
+        if blank != Cell::BLANK {
+            self.mark_dirty(self.rows - 1, 0, self.columns);
+        }
+    }
+
+    /// Shows `lines` more lines of the scrollback, or fewer when negative, redrawing the screen.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let view = self
+            .view
+            .saturating_add_signed(lines)
+            .min(self.scrollback.len());
+        if view != self.view {
+            self.view = view;
+            self.redraw();
+        }
+    }
+
+    /// Draws every line again, from the scrollback for those browsed back.
+    fn redraw(&mut self) {
+        if self.view == 0 {
+            for row in 0..self.rows {
+                self.mark_dirty(row, 0, self.columns);
+            }
+            self.drawn_cursor = None;
+            return self.flush();
+        }
+        for row in 0..self.rows {
+            if row < self.view {
+                let line = &self.scrollback[self.scrollback.len() - self.view + row];
+                let mut cells: Vec<Cell> = line.cells().collect();
+                cells.resize(self.columns, Cell::BLANK);
+                for (column, cell) in cells.into_iter().enumerate() {
+                    self.draw_cell(row, column, cell, None);
+                }
+            } else {
+                let cells = self.screen[row - self.view];
+                for (column, cell) in cells[..self.columns].iter().enumerate() {
+                    self.draw_cell(row, column, *cell, None);
+                }
+            }
+        }
+    }
+
+    /// Draws the cells changed since the last flush, and the cursor where it is now.
+    pub fn flush(&mut self) {
+        // the scrollback is shown instead
+        if self.view != 0 {
+            return;
+        }
+        let shown = self.cursor_visible && (self.blink_on || !self.cursor_blinks);
+        let cursor = shown.then(|| (self.row, self.cursor_column()));
+        if self.drawn_cursor != cursor {
+            if let Some((row, column)) = self.drawn_cursor {
+                self.mark_dirty(row, column, column + 1);
+            }
+            if let Some((row, column)) = cursor {
+                self.mark_dirty(row, column, column + 1);
+            }
+        }
+        self.draw_dirty(cursor);
+        self.drawn_cursor = cursor;
+    }
+
+    fn draw_dirty(&mut self, cursor: Option<(usize, usize)>) {
+        for row in 0..self.rows {
+            let (start, end) = core::mem::take(&mut self.dirty[row]);
+            for column in start as usize..end as usize {
+                let cell = self.screen[row][column];
+                let style = (cursor == Some((row, column))).then_some(self.cursor_style);
+                self.draw_cell(row, column, cell, style);
+            }
+        }
+    }
+
+    /// Shows or hides a blinking cursor, called every [`BLINK_INTERVAL_MS`].
+    pub(crate) fn blink(&mut self) {
+        if self.cursor_blinks {
+            self.blink_on = !self.blink_on;
+            self.flush();
+        }
     }
 
-    fn height(&self) -> usize {
-        self.info.height
+    /// Moves the cursor to `column` and `row`, counted from 0 at the top left.
+    pub fn set_cursor(&mut self, column: usize, row: usize) {
+        self.move_to(row, column);
+        self.blink_on = true;
+        self.flush();
+    }
+
+    /// Changes the shape of the cursor, and whether it blinks.
+    pub fn set_cursor_style(&mut self, style: CursorStyle, blinks: bool) {
+        self.cursor_style = style;
+        self.cursor_blinks = blinks;
+        self.blink_on = true;
+        // drawn again in its new style
+        if let Some((row, column)) = self.drawn_cursor.take() {
+            self.mark_dirty(row, column, column + 1);
+        }
+        self.flush();
     }
 
     pub fn write_colored_str(&mut self, s: impl AsRef<str>) {
         for c in s.as_ref().chars() {
             self.write_char(c);
         }
+        self.flush();
     }
 
-    /// Writes a single char to the framebuffer. Takes care of special control characters, such as
-    /// newlines and carriage returns.
+    /// Writes a single char to the screen. Takes care of special control characters, such as
+    /// newlines and carriage returns, and escape sequences.
     fn write_char(&mut self, c: char) {
+        // new text brings back the screen from the scrollback
+        if self.view != 0 {
+            self.scroll_view(-(self.view as isize));
+        }
+        // the cursor stays shown while text comes
+        self.blink_on = true;
+        match self.escape {
+            Escape::None => {}
+            Escape::Started => return self.escape(c),
+            Escape::Csi { .. } => return self.control_sequence(c),
+        }
         match c {
             '\n' => self.newline(),
             '\r' => self.carriage_return(),
             '\t' => (0..4).for_each(|_| self.write_char(' ')),
-            c => {
-                let new_xpos = self.x_pos + font_constants::CHAR_RASTER_WIDTH;
-                if new_xpos >= self.width() {
+            '\x08' => self.cursor_left(),
+            '\x1b' => self.escape = Escape::Started,
+            ch => {
+                if self.column >= self.columns {
                     self.newline();
                 }
-                let new_ypos =
-                    self.y_pos + font_constants::CHAR_RASTER_HEIGHT.val() + BORDER_PADDING;
-                if new_ypos >= self.height() {
-                    self.clear();
+                let cell = Cell {
+                    ch,
+                    style: self.style,
+                };
+                self.set_cell(self.row, self.column, cell);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Handles the character after `ESC`.
+    fn escape(&mut self, c: char) {
+        self.escape = Escape::None;
+        match c {
+            '[' => {
+                self.escape = Escape::Csi {
+                    params: [0; MAX_PARAMS],
+                    len: 0,
+                    private: false,
+                }
+            }
+            '7' => self.saved = (self.row, self.column, self.style),
+            '8' => self.restore_cursor(),
+            // a full reset
+            'c' => {
+                self.style = Style::DEFAULT;
+                self.saved = (0, 0, Style::DEFAULT);
+                self.cursor_visible = true;
+                self.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds `c` to the control sequence being read, running it once complete.
+    fn control_sequence(&mut self, c: char) {
+        let Escape::Csi {
+            mut params,
+            mut len,
+            mut private,
+        } = self.escape
+        else {
+            return;
+        };
+        match c {
+            '0'..='9' => {
+                len = len.max(1);
+                if let Some(param) = params.get_mut(len - 1) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(c as u16 - '0' as u16);
+                }
+            }
+            ';' => len = len.max(1) + 1,
+            '?' => private = true,
+            // intermediate bytes, not used by any sequence handled
+            ' '..='/' => {}
+            '@'..='~' => {
+                self.escape = Escape::None;
+                let params = &params[..len.min(MAX_PARAMS)];
+                if private {
+                    self.run_private_sequence(c, params);
+                } else {
+                    self.run_control_sequence(c, params);
+                }
+                return;
+            }
+            // anything else aborts the sequence
+            _ => {
+                self.escape = Escape::None;
+                return;
+            }
+        }
+        self.escape = Escape::Csi {
+            params,
+            len,
+            private,
+        };
+    }
+
+    fn run_control_sequence(&mut self, action: char, params: &[u16]) {
+        // a missing or zero count means one
+        let count = params.first().map_or(1, |&count| count.max(1) as usize);
+        let param = |index: usize| params.get(index).copied().unwrap_or(0) as usize;
+        let column = self.cursor_column();
+        match action {
+            'A' => self.move_to(self.row.saturating_sub(count), self.column),
+            'B' => self.move_to(self.row + count, self.column),
+            'C' => self.move_to(self.row, self.column + count),
+            'D' => self.move_to(self.row, self.column.saturating_sub(count)),
+            'E' => self.move_to(self.row + count, 0),
+            'F' => self.move_to(self.row.saturating_sub(count), 0),
+            'G' => self.move_to(self.row, count - 1),
+            'd' => self.move_to(count - 1, self.column),
+            'H' | 'f' => self.move_to(param(0).max(1) - 1, param(1).max(1) - 1),
+            'J' => {
+                let cursor = self.row * self.columns + self.column.min(self.columns);
+                let end = self.rows * self.columns;
+                match param(0) {
+                    0 => self.erase(cursor, end),
+                    1 => self.erase(0, (cursor + 1).min(end)),
+                    2 => self.erase(0, end),
+                    3 => {
+                        self.scrollback.clear();
+                        self.scrollback_bytes = 0;
+                        self.erase(0, end);
+                    }
+                    _ => {}
                 }
-                self.write_rendered_char(get_char_raster(c));
             }
+            'K' => {
+                let start = self.row * self.columns;
+                let cursor = start + self.column.min(self.columns);
+                let end = start + self.columns;
+                match param(0) {
+                    0 => self.erase(cursor, end),
+                    1 => self.erase(start, (cursor + 1).min(end)),
+                    2 => self.erase(start, end),
+                    _ => {}
+                }
+            }
+            '@' => self.shift_cells(column, (column + count).min(self.columns)),
+            'P' => self.shift_cells((column + count).min(self.columns), column),
+            'X' => {
+                let start = self.row * self.columns + column;
+                self.erase(start, start + count.min(self.columns - column));
+            }
+            'L' => self.shift_rows(self.row, (self.row + count).min(self.rows)),
+            'M' => self.shift_rows((self.row + count).min(self.rows), self.row),
+            'm' => self.select_graphic_rendition(params),
+            // DECSCUSR, sent as `ESC [ n SP q`
+            'q' => {
+                let (style, blinks) = match param(0) {
+                    0 | 1 => (CursorStyle::Block, true),
+                    2 => (CursorStyle::Block, false),
+                    3 => (CursorStyle::Underline, true),
+                    4 => (CursorStyle::Underline, false),
+                    5 => (CursorStyle::Bar, true),
+                    6 => (CursorStyle::Bar, false),
+                    _ => return,
+                };
+                self.set_cursor_style(style, blinks);
+            }
+            's' => self.saved = (self.row, self.column, self.style),
+            'u' => self.restore_cursor(),
+            _ => {}
         }
     }
 
-    /// Prints a rendered char into the framebuffer.
-    /// Updates `self.x_pos`.
-    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
-        for (y, row) in rendered_char.raster().iter().enumerate() {
-            for (x, byte) in row.iter().enumerate() {
-                let intensity = *byte as f32 / 255.0;
-                self.write_pixel(self.x_pos + x, self.y_pos + y, self.color * intensity);
+    /// Runs a DEC private mode sequence, as in `ESC [ ? 25 l`.
+    fn run_private_sequence(&mut self, action: char, params: &[u16]) {
+        let set = match action {
+            'h' => true,
+            'l' => false,
+            _ => return,
+        };
+        for param in params {
+            if *param == 25 {
+                self.cursor_visible = set;
+            }
+        }
+    }
+
+    /// Changes the style of the text written next, as in `ESC [ 1 ; 31 m`.
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.style = Style::DEFAULT;
+        }
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => self.style = Style::DEFAULT,
+                1 => self.style.attrs |= BOLD,
+                4 => self.style.attrs |= UNDERLINE,
+                7 => self.style.attrs |= REVERSE,
+                22 => self.style.attrs &= !BOLD,
+                24 => self.style.attrs &= !UNDERLINE,
+                27 => self.style.attrs &= !REVERSE,
+                30..=37 => self.style.fg = ANSI_COLORS[param as usize - 30],
+                90..=97 => self.style.fg = ANSI_COLORS[param as usize - 90 + 8],
+                38 => {
+                    if let Some(color) = extended_color(&mut params) {
+                        self.style.fg = color;
+                    }
+                }
+                39 => self.style.fg = Style::DEFAULT.fg,
+                40..=47 => self.style.bg = ANSI_COLORS[param as usize - 40],
+                100..=107 => self.style.bg = ANSI_COLORS[param as usize - 100 + 8],
+                48 => {
+                    if let Some(color) = extended_color(&mut params) {
+                        self.style.bg = color;
+                    }
+                }
+                49 => self.style.bg = Style::DEFAULT.bg,
+                _ => {}
+            }
+        }
+    }
+
+    /// Draws a cell over what was there, background included, with the cursor when it's on it.
+    fn draw_cell(&mut self, row: usize, column: usize, cell: Cell, cursor: Option<CursorStyle>) {
+        let x_pos = BORDER_PADDING + column * CELL_WIDTH;
+        let y_pos = BORDER_PADDING + row * CELL_HEIGHT;
+        let Style {
+            mut fg,
+            mut bg,
+            attrs,
+        } = cell.style;
+        if (attrs & REVERSE != 0) != (cursor == Some(CursorStyle::Block)) {
+            core::mem::swap(&mut fg, &mut bg);
+        }
+        let weight = if attrs & BOLD != 0 {
+            FontWeight::Bold
+        } else {
+            font_constants::FONT_WEIGHT
+        };
+        let rendered_char = get_char_raster(cell.ch, weight);
+        let raster = rendered_char.raster();
+        let underline = font_constants::CHAR_RASTER_HEIGHT.val() - 1;
+        for y in 0..CELL_HEIGHT {
+            for x in 0..CELL_WIDTH {
+                let in_cursor = match cursor {
+                    Some(CursorStyle::Underline) => y >= CELL_HEIGHT - CURSOR_THICKNESS,
+                    Some(CursorStyle::Bar) => x < CURSOR_THICKNESS,
+                    _ => false,
+                };
+                let intensity = if in_cursor || attrs & UNDERLINE != 0 && y == underline {
+                    255
+                } else {
+                    raster
+                        .get(y)
+                        .and_then(|row| row.get(x))
+                        .copied()
+                        .unwrap_or(0)
+                };
+                self.write_pixel(x_pos + x, y_pos + y, bg.blend(fg, intensity));
             }
         }
-        self.x_pos += rendered_char.width() + LETTER_SPACING;
     }
 
     fn write_pixel(&mut self, x: usize, y: usize, color: RgbColor) {
@@ -167,7 +964,7 @@ impl FrameBufferWriter {
             bootloader_api::info::PixelFormat::Rgb => color.into_array(),
             bootloader_api::info::PixelFormat::Bgr => color.into_reversed_array(),
             bootloader_api::info::PixelFormat::U8 => {
-                if self.color > RgbColor::new(200, 200, 200) {
+                if color > RgbColor::new(200, 200, 200) {
                     [125, 125, 125, 0]
                 } else {
                     [0; 4]
@@ -183,6 +980,18 @@ impl FrameBufferWriter {
     }
 }
 
+impl Drop for FrameBufferWriter {
+    fn drop(&mut self) {
+        if self.owned {
+            // both were leaked by `detached`, and nothing else refers to them
+            unsafe {
+                drop(Box::from_raw(ptr::from_mut(self.framebuffer)));
+                drop(Box::from_raw(ptr::from_mut(self.screen)));
+            }
+        }
+    }
+}
+
 unsafe impl Send for FrameBufferWriter {}
 unsafe impl Sync for FrameBufferWriter {}
 
@@ -194,3 +1003,138 @@ impl fmt::Write for FrameBufferWriter {
         Ok(())
     }
 }
+
+static WRITER: spin::Mutex<Option<FrameBufferWriter>> = spin::Mutex::new(None);
+
+/// The framebuffer as `fb0`, read and written as raw pixels in the layout of the boot mode.
+pub struct Screen;
+
+impl CharDevice for Screen {
+    fn name(&self) -> &str {
+        "fb0"
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        interrupts::without_interrupts(|| {
+            let writer = WRITER.lock();
+            let framebuffer = &writer.as_ref().ok_or(FsError::Io)?.framebuffer;
+            let start = (offset as usize).min(framebuffer.len());
+            let len = buf.len().min(framebuffer.len() - start);
+            buf[..len].copy_from_slice(&framebuffer[start..start + len]);
+            Ok(len)
+        })
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let framebuffer = &mut writer.as_mut().ok_or(FsError::Io)?.framebuffer;
+            let start = offset as usize;
+            if start >= framebuffer.len() {
+                return if buf.is_empty() {
+                    Ok(0)
+                } else {
+                    Err(FsError::NoSpace)
+                };
+            }
+            let len = buf.len().min(framebuffer.len() - start);
+            framebuffer[start..start + len].copy_from_slice(&buf[..len]);
+            Ok(len)
+        })
+    }
+
+    fn size(&self) -> u64 {
+        interrupts::without_interrupts(|| {
+            WRITER
+                .lock()
+                .as_ref()
+                .map_or(0, |writer| writer.framebuffer.len() as u64)
+        })
+    }
+
+    fn mode(&self) -> u16 {
+        0o660
+    }
+}
+
+fn write_console(text: fmt::Arguments) {
+    if let Some(writer) = WRITER.lock().as_mut() {
+        let _ = writer.write_fmt(text);
+        writer.flush();
+    }
+}
+
+/// Clears the screen and logs kernel messages to it, before anything else is ready.
+pub fn init(fb: &'static mut FrameBuffer) {
+    let info = fb.info();
+    *WRITER.lock() = Some(unsafe { FrameBufferWriter::new(fb.buffer_mut(), info) });
+    device::add_console(write_console);
+}
+
+/// Erases the screen, starting again at the top.
+pub fn clear() {
+    interrupts::without_interrupts(|| {
+        if let Some(writer) = WRITER.lock().as_mut() {
+            writer.clear();
+        }
+    })
+}
+
+/// Browses half a screen back in the scrollback, or forward when `back` isn't set.
+pub fn scroll_view(back: bool) {
+    interrupts::without_interrupts(|| {
+        if let Some(writer) = WRITER.lock().as_mut() {
+            let lines = (writer.rows / 2) as isize;
+            writer.scroll_view(if back { lines } else { -lines });
+        }
+    })
+}
+
+/// The column and row of the cursor, counted from 0 at the top left. The column is past the last
+/// one once a row is full, until the next character goes to the row below.
+pub fn cursor() -> Option<(usize, usize)> {
+    interrupts::without_interrupts(|| WRITER.lock().as_ref().map(FrameBufferWriter::cursor))
+}
+
+/// Moves the cursor to `column` and `row`, counted from 0 at the top left, where the next
+/// character will be written.
+pub fn set_cursor(column: usize, row: usize) {
+    interrupts::without_interrupts(|| {
+        if let Some(writer) = WRITER.lock().as_mut() {
+            writer.set_cursor(column, row);
+        }
+    })
+}
+
+/// Changes the shape of the console cursor, and whether it blinks.
+pub fn set_cursor_style(style: CursorStyle, blinks: bool) {
+    interrupts::without_interrupts(|| {
+        if let Some(writer) = WRITER.lock().as_mut() {
+            writer.set_cursor_style(style, blinks);
+        }
+    })
+}
+
+/// The columns and rows of the screen.
+pub fn size() -> Option<(usize, usize)> {
+    interrupts::without_interrupts(|| WRITER.lock().as_ref().map(FrameBufferWriter::size))
+}
+
+fn blink_main() {
+    loop {
+        task::sleep_ticks(ints::ms_to_ticks(BLINK_INTERVAL_MS));
+        interrupts::without_interrupts(|| {
+            if let Some(writer) = WRITER.lock().as_mut() {
+                writer.blink();
+            }
+        })
+    }
+}
+
+/// Registers the framebuffer as `fb0`, and starts blinking the cursor.
+pub fn register() {
+    info!("registering framebuffer");
+    device::register(Arc::new(Screen));
+    task::spawn(blink_main);
+    okay!("registered framebuffer");
+}
@@ -0,0 +1,413 @@
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt;
+
+use crate::{
+    block::{self, BlockDevice, BlockDeviceRef, BlockError},
+    info, okay, warn,
+};
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_TABLE: usize = 446;
+const MBR_PROTECTIVE: u8 = 0xee;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// Logical partitions are numbered after the four primary slots, as Linux does.
+const FIRST_LOGICAL: usize = 5;
+/// Extended boot records followed before assuming the chain loops.
+const MAX_LOGICAL: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER: usize = 92;
+const GPT_MIN_ENTRY: usize = 128;
+const GPT_MAX_ENTRIES: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionError {
+    /// Neither an MBR nor a GPT was found.
+    NoTable,
+    /// A GPT header or its entries don't match their CRC, in both copies.
+    BadChecksum,
+    /// A table describes something impossible, like a partition past the end of the disk.
+    Invalid,
+    Block(BlockError),
+}
+
+impl From<BlockError> for PartitionError {
+    fn from(value: BlockError) -> Self {
+        PartitionError::Block(value)
+    }
+}
+
+/// A GUID, stored as on disk, with its first three fields little endian.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const ZERO: Guid = Guid([0; 16]);
+    /// Microsoft basic data, used for FAT partitions.
+    pub const BASIC_DATA: Guid = Guid::parse("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+    pub const LINUX_FS: Guid = Guid::parse("0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+    pub const EFI_SYSTEM: Guid = Guid::parse("C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+
+    /// Parses the usual text form, panicking on malformed input as it is meant for constants.
+    pub const fn parse(text: &str) -> Guid {
+        let text = text.as_bytes();
+        assert!(text.len() == 36, "bad guid");
+        // order of the bytes on disk, by position in the text
+        const ORDER: [usize; 16] = [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15];
+        let mut digits = [0u8; 32];
+        let (mut i, mut n) = (0, 0);
+        while i < text.len() {
+            let c = text[i];
+            if c != b'-' {
+                digits[n] = match c {
+                    b'0'..=b'9' => c - b'0',
+                    b'a'..=b'f' => c - b'a' + 10,
+                    b'A'..=b'F' => c - b'A' + 10,
+                    _ => panic!("bad guid"),
+                };
+                n += 1;
+            }
+            i += 1;
+        }
+        let mut bytes = [0u8; 16];
+        let mut j = 0;
+        while j < 16 {
+            bytes[ORDER[j]] = digits[j * 2] << 4 | digits[j * 2 + 1];
+            j += 1;
+        }
+        Guid(bytes)
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]])
+        )?;
+        for (i, byte) in b[8..].iter().enumerate() {
+            if i == 2 {
+                write!(f, "-")?;
+            }
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// The system id byte of an MBR entry.
+    Mbr(u8),
+    Gpt {
+        type_guid: Guid,
+        unique_guid: Guid,
+        name: String,
+    },
+}
+
+/// A partition found in a table, in sectors of its disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// Number appended to the disk name, from 1.
+    pub number: usize,
+    pub start: u64,
+    pub sectors: u64,
+    pub kind: PartitionKind,
+}
+
+/// A range of sectors of a disk, seen as a disk of its own.
+pub struct Partition {
+    name: String,
+    disk: BlockDeviceRef,
+    info: PartitionInfo,
+}
+
+impl Partition {
+    pub fn new(disk: BlockDeviceRef, info: PartitionInfo) -> Self {
+        Self {
+            name: format!("{}{}", disk.name(), info.number),
+            disk,
+            info,
+        }
+    }
+
+    pub fn info(&self) -> &PartitionInfo {
+        &self.info
+    }
+
+    pub fn disk(&self) -> &BlockDeviceRef {
+        &self.disk
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_size(&self) -> usize {
+        self.disk.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.info.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.disk.read_sectors(self.info.start + lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.disk.write_sectors(self.info.start + lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.disk.flush()
+    }
+}
+
+fn read_sector(disk: &dyn BlockDevice, lba: u64) -> Result<Vec<u8>, BlockError> {
+    let mut sector = vec![0; disk.sector_size()];
+    disk.read_sectors(lba, &mut sector)?;
+    Ok(sector)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Reads the partition table of `disk`, preferring the GPT over its protective MBR.
+pub fn read_table(disk: &dyn BlockDevice) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let mbr = read_sector(disk, 0)?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Err(PartitionError::NoTable);
+    }
+    let protective = (0..4).any(|i| mbr[MBR_TABLE + i * 16 + 4] == MBR_PROTECTIVE);
+    let partitions = if protective {
+        parse_gpt(disk)?
+    } else {
+        parse_mbr(disk, &mbr)?
+    };
+    if partitions.iter().any(|p| {
+        p.start
+            .checked_add(p.sectors)
+            .is_none_or(|end| end > disk.sector_count())
+    }) {
+        return Err(PartitionError::Invalid);
+    }
+    Ok(partitions)
+}
+
+/// An entry of an MBR or extended boot record as (system id, first sector, sector count).
+fn mbr_entry(sector: &[u8], index: usize) -> (u8, u64, u64) {
+    let entry = &sector[MBR_TABLE + index * 16..MBR_TABLE + (index + 1) * 16];
+    (entry[4], u32_at(entry, 8) as u64, u32_at(entry, 12) as u64)
+}
+
+fn parse_mbr(disk: &dyn BlockDevice, mbr: &[u8]) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let mut partitions = Vec::new();
+    let mut logical = FIRST_LOGICAL;
+    for index in 0..4 {
+        let (kind, start, sectors) = mbr_entry(mbr, index);
+        if kind == 0 || sectors == 0 {
+            continue;
+        }
+        if !MBR_EXTENDED.contains(&kind) {
+            partitions.push(PartitionInfo {
+                number: index + 1,
+                start,
+                sectors,
+                kind: PartitionKind::Mbr(kind),
+            });
+            continue;
+        }
+        // each extended boot record holds a logical partition, relative to itself, and the
+        // next record, relative to the extended partition
+        let mut ebr = start;
+        for _ in 0..MAX_LOGICAL {
+            let sector = read_sector(disk, ebr)?;
+            if sector[510..512] != MBR_SIGNATURE {
+                return Err(PartitionError::Invalid);
+            }
+            let (kind, offset, sectors) = mbr_entry(&sector, 0);
+            if kind != 0 && sectors != 0 {
+                partitions.push(PartitionInfo {
+                    number: logical,
+                    start: ebr + offset,
+                    sectors,
+                    kind: PartitionKind::Mbr(kind),
+                });
+                logical += 1;
+            }
+            let (next_kind, next, _) = mbr_entry(&sector, 1);
+            if next_kind == 0 || next == 0 {
+                break;
+            }
+            ebr = start + next;
+        }
+    }
+    Ok(partitions)
+}
+
+fn parse_gpt(disk: &dyn BlockDevice) -> Result<Vec<PartitionInfo>, PartitionError> {
+    // the backup header at the end of the disk is there for when the first one is damaged
+    match parse_gpt_at(disk, 1) {
+        Err(PartitionError::BadChecksum | PartitionError::Invalid) => {
+            warn!("{}: primary gpt damaged, using the backup", disk.name());
+            parse_gpt_at(disk, disk.sector_count() - 1)
+        }
+        result => result,
+    }
+}
+
+fn parse_gpt_at(disk: &dyn BlockDevice, lba: u64) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let mut header = read_sector(disk, lba)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Err(PartitionError::Invalid);
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if !(GPT_MIN_HEADER..=header.len()).contains(&header_size) {
+        return Err(PartitionError::Invalid);
+    }
+    let header_crc = u32_at(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != header_crc || u64_at(&header, 24) != lba {
+        return Err(PartitionError::BadChecksum);
+    }
+
+    let entries_lba = u64_at(&header, 72);
+    let count = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    let sector_size = disk.sector_size();
+    // bigger entries would only be there to make the table too big to read
+    if count > GPT_MAX_ENTRIES
+        || !(GPT_MIN_ENTRY..=sector_size).contains(&entry_size)
+        || entry_size % 8 != 0
+    {
+        return Err(PartitionError::Invalid);
+    }
+    let len = count
+        .checked_mul(entry_size)
+        .ok_or(PartitionError::Invalid)?;
+    let sectors = len.div_ceil(sector_size);
+    // checked before reading them in, so a bad table can't ask for more than the disk holds
+    let entries_end = entries_lba.checked_add(sectors as u64);
+    if entries_end.is_none_or(|end| end > disk.sector_count()) {
+        return Err(PartitionError::Invalid);
+    }
+    let mut entries = vec![0; sectors * sector_size];
+    disk.read_sectors(entries_lba, &mut entries)?;
+    if crc32(&entries[..len]) != u32_at(&header, 88) {
+        return Err(PartitionError::BadChecksum);
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.chunks_exact(entry_size).take(count).enumerate() {
+        let type_guid = Guid(entry[..16].try_into().unwrap());
+        if type_guid == Guid::ZERO {
+            continue;
+        }
+        let first = u64_at(entry, 32);
+        let last = u64_at(entry, 40);
+        let sectors = last
+            .checked_sub(first)
+            .and_then(|len| len.checked_add(1))
+            .ok_or(PartitionError::Invalid)?;
+        let name = entry[56..128]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0);
+        partitions.push(PartitionInfo {
+            number: index + 1,
+            start: first,
+            sectors,
+            kind: PartitionKind::Gpt {
+                type_guid,
+                unique_guid: Guid(entry[16..32].try_into().unwrap()),
+                name: char::decode_utf16(name)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            },
+        });
+    }
+    Ok(partitions)
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The CRC-32 used by GPT, zlib and others.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ crc >> 8
+    })
+}
+
+/// Registers every partition of `disk` as a block device, returning them.
+pub fn scan(disk: BlockDeviceRef) -> Result<Vec<Arc<Partition>>, PartitionError> {
+    let table = read_table(&*disk)?;
+    let mut partitions = Vec::new();
+    for info in table {
+        let partition = Arc::new(Partition::new(disk.clone(), info));
+        block::register(partition.clone());
+        partitions.push(partition);
+    }
+    Ok(partitions)
+}
+
+/// Scans the disks found so far for partitions.
+pub fn init() {
+    info!("scanning partition tables");
+    for disk in block::list() {
+        match scan(disk.clone()) {
+            Ok(partitions) => {
+                for partition in partitions {
+                    let info = partition.info();
+                    info!(
+                        "{}: {} sectors from {}",
+                        partition.name(),
+                        info.sectors,
+                        info.start
+                    );
+                }
+            }
+            Err(PartitionError::NoTable) => {}
+            Err(err) => warn!("{}: bad partition table: {err:?}", disk.name()),
+        }
+    }
+    okay!("scanned partition tables");
+}
@@ -0,0 +1,156 @@
+use core::{
+    arch::asm,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+};
+
+use x86_64::{
+    registers::model_specific::{GsBase, KernelGsBase},
+    VirtAddr,
+};
+
+use crate::gdt;
+
+pub const MAX_CPUS: usize = 8;
+
+/// Block pointed by the GS base of each CPU. Only fields that must be reached with a
+/// single `gs:[offset]` load live here, everything else is declared with [`percpu!`].
+#[repr(C)]
+pub struct CpuBlock {
+    self_ptr: *const CpuBlock,
+    id: usize,
+    kernel_rsp: u64,
+    #[allow(dead_code)] // only touched by the `syscall` entry stub
+    user_rsp: u64,
+}
+
+/// Offset of the stack loaded by the `syscall` entry stub.
+pub const KERNEL_RSP_OFFSET: usize = 16;
+/// Offset of the scratch slot where the `syscall` entry stub saves the user stack.
+pub const USER_RSP_OFFSET: usize = 24;
+
+impl CpuBlock {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+static mut CPU_BLOCKS: [CpuBlock; MAX_CPUS] = [const {
+    CpuBlock {
+        self_ptr: core::ptr::null(),
+        id: 0,
+        kernel_rsp: 0,
+        user_rsp: 0,
+    }
+}; MAX_CPUS];
+
+/// A variable that has one instance for each CPU, indexed by the id stored in the GS block.
+pub struct CpuLocal<T> {
+    slots: [T; MAX_CPUS],
+}
+
+impl<T> CpuLocal<T> {
+    pub const fn new(slots: [T; MAX_CPUS]) -> Self {
+        Self { slots }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.slots[cpu_id()]
+    }
+
+    pub fn get_for(&self, cpu: usize) -> &T {
+        &self.slots[cpu]
+    }
+}
+
+// Each CPU only touches its own slot, so sharing the whole array is fine as long as the slots are.
+unsafe impl<T: Send> Sync for CpuLocal<T> {}
+
+/// Declares CPU-local statics: `percpu! { pub static NAME: Type = init; }`.
+///
+/// `init` is evaluated once for every CPU slot, so it must be a constant expression.
+#[macro_export]
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::percpu::CpuLocal<$ty> =
+                $crate::percpu::CpuLocal::new([const { $init }; $crate::percpu::MAX_CPUS]);
+        )*
+    };
+}
+
+percpu! {
+    pub static CURRENT_THREAD: AtomicPtr<()> = AtomicPtr::new(null_mut());
+    pub static TICKS: AtomicU64 = AtomicU64::new(0);
+    pub static INT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Points the GS base of the running CPU to the block of `cpu`, and clears the one user mode
+/// gets: the entry stubs `swapgs` them on every crossing between the rings.
+///
+/// Must be called again after every reload of the GS selector, as it resets the base.
+///
+/// # Safety
+///
+/// Must run in ring 0 on the CPU numbered `cpu`, which no other CPU loads, before anything reads
+/// the GS block.
+pub unsafe fn load(cpu: usize) {
+    let block = &mut CPU_BLOCKS[cpu];
+    block.self_ptr = block;
+    block.id = cpu;
+    block.kernel_rsp = gdt::kernel_stack().as_u64();
+    GsBase::write(VirtAddr::from_ptr(block));
+    KernelGsBase::write(VirtAddr::zero());
+}
+
+/// Sets the stack the `syscall` entry stub switches to on the running CPU.
+///
+/// # Safety
+///
+/// The GS base must have been set by [`load`] on this CPU, and `rsp` must be the top of a stack
+/// nothing else uses while the running thread is in ring 3.
+pub unsafe fn set_kernel_rsp(rsp: VirtAddr) {
+    asm!(
+        "mov gs:[{offset}], {rsp}",
+        rsp = in(reg) rsp.as_u64(),
+        offset = const KERNEL_RSP_OFFSET,
+        options(nostack, preserves_flags),
+    );
+}
+
+pub fn cpu_id() -> usize {
+    let id: usize;
+    unsafe {
+        asm!("mov {}, gs:[8]", out(reg) id, options(nostack, readonly, preserves_flags));
+    }
+    id
+}
+
+pub fn current_block() -> &'static CpuBlock {
+    let ptr: *const CpuBlock;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) ptr, options(nostack, readonly, preserves_flags));
+        &*ptr
+    }
+}
+
+pub fn in_interrupt() -> bool {
+    INT_DEPTH.get().load(Ordering::Relaxed) != 0
+}
+
+/// Marks the running CPU as inside an interrupt handler until dropped.
+pub struct IntNesting(());
+
+impl IntNesting {
+    pub fn enter() -> Self {
+        INT_DEPTH.get().fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for IntNesting {
+    fn drop(&mut self) {
+        INT_DEPTH.get().fetch_sub(1, Ordering::Relaxed);
+    }
+}
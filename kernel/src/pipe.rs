@@ -0,0 +1,141 @@
+use alloc::{collections::VecDeque, sync::Arc};
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+    fd::{FileLike, FileRef},
+    process, signal,
+    syscall::Errno,
+    task::WaitQueue,
+};
+
+/// Bytes a pipe holds before writers block.
+pub const PIPE_CAPACITY: usize = 4096;
+
+struct PipeBuffer {
+    data: VecDeque<u8>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+/// Shared between both ends of a pipe.
+struct Pipe {
+    buffer: spin::Mutex<PipeBuffer>,
+    /// Notified when bytes are written or the write end is closed.
+    readable: WaitQueue,
+    /// Notified when bytes are read or the read end is closed.
+    writable: WaitQueue,
+}
+
+impl Pipe {
+    fn with_buffer<T>(&self, f: impl FnOnce(&mut PipeBuffer) -> T) -> T {
+        interrupts::without_interrupts(|| f(&mut self.buffer.lock()))
+    }
+}
+
+/// The read end of a pipe, closed once every descriptor pointing to it is.
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+/// The write end of a pipe.
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+/// Creates an anonymous pipe, returning its read and write ends.
+pub fn pipe() -> (FileRef, FileRef) {
+    let pipe = Arc::new(Pipe {
+        buffer: spin::Mutex::new(PipeBuffer {
+            data: VecDeque::new(),
+            reader_closed: false,
+            writer_closed: false,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    let reader = Arc::new(PipeReader { pipe: pipe.clone() });
+    let writer = Arc::new(PipeWriter { pipe });
+    (reader, writer)
+}
+
+impl FileLike for PipeReader {
+    /// Blocks until some bytes are available, or returns 0 once the write end is closed.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.pipe;
+        let read = signal::wait(&pipe.readable, || {
+            let mut buffer = pipe.buffer.lock();
+            if buffer.data.is_empty() {
+                return buffer.writer_closed.then_some(0);
+            }
+            let len = buf.len().min(buffer.data.len());
+            for (dst, src) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+                *dst = src;
+            }
+            Some(len)
+        })?;
+        pipe.writable.notify_all();
+        Ok(read)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::BadFd)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.with_buffer(|buffer| buffer.reader_closed = true);
+        self.pipe.writable.notify_all();
+    }
+}
+
+impl FileLike for PipeWriter {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::BadFd)
+    }
+
+    /// Blocks until all of `buf` is written. Writing with the read end closed raises `SIGPIPE`.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let pipe = &self.pipe;
+        let mut written = 0;
+        while written < buf.len() {
+            let result = signal::wait(&pipe.writable, || {
+                let mut buffer = pipe.buffer.lock();
+                if buffer.reader_closed {
+                    return Some(None);
+                }
+                let free = PIPE_CAPACITY - buffer.data.len();
+                if free == 0 {
+                    return None;
+                }
+                let len = free.min(buf.len() - written);
+                buffer.data.extend(&buf[written..written + len]);
+                Some(Some(len))
+            });
+            match result {
+                Ok(Some(len)) => {
+                    written += len;
+                    pipe.readable.notify_all();
+                }
+                Ok(None) => {
+                    let _ = signal::send(process::current().pid(), signal::SIGPIPE as u64);
+                    return Err(Errno::Pipe);
+                }
+                Err(_) if written > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.with_buffer(|buffer| buffer.writer_closed = true);
+        self.pipe.readable.notify_all();
+    }
+}
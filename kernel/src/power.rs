@@ -0,0 +1,59 @@
+use x86_64::{
+    instructions::{interrupts, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+    VirtAddr,
+};
+
+use crate::{info, vfs, warn};
+
+const KBC_STATUS_PORT: u16 = 0x64;
+/// Set while the keyboard controller hasn't taken the last command.
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xfe;
+
+/// ACPI power off ports of QEMU, older QEMU and Bochs, and VirtualBox, with the value to write.
+const POWER_OFF_PORTS: [(u16, u16); 3] = [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+fn sync() {
+    if let Err(err) = vfs::sync() {
+        warn!("failed to sync filesystems: {err:?}");
+    }
+}
+
+/// Writes back the filesystems and restarts the machine through the keyboard controller, or a
+/// triple fault when that doesn't work.
+pub fn reboot() -> ! {
+    sync();
+    info!("rebooting");
+    interrupts::disable();
+    unsafe {
+        let mut status = Port::<u8>::new(KBC_STATUS_PORT);
+        while status.read() & KBC_INPUT_FULL != 0 {}
+        status.write(KBC_PULSE_RESET);
+        for _ in 0..100_000 {
+            core::hint::spin_loop();
+        }
+        // any exception without an IDT ends in a triple fault
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+    }
+    interrupts::int3();
+    unreachable!("survived a triple fault");
+}
+
+/// Writes back the filesystems and powers the machine off, halting it when the machine isn't
+/// known to the kernel.
+pub fn shutdown() -> ! {
+    sync();
+    info!("powering off");
+    interrupts::disable();
+    for (port, value) in POWER_OFF_PORTS {
+        unsafe { Port::<u16>::new(port).write(value) };
+    }
+    warn!("failed to power off, halting");
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,340 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use x86_64::{instructions::interrupts, registers::control::Cr3, VirtAddr};
+
+use crate::{
+    channel::HandleTable,
+    elf::{self, ElfError},
+    fd::FdTable,
+    info,
+    mem::AddressSpace,
+    okay,
+    shm::SharedMapping,
+    signal,
+    signal::SignalState,
+    task,
+    task::{Thread, WaitQueue},
+    user,
+};
+
+pub type Pid = u64;
+
+/// Process owning the kernel threads, and adopting every orphan.
+pub const INIT_PID: Pid = 1;
+
+/// Anonymous mappings are placed from here when no address is requested.
+pub const MMAP_BASE: u64 = 0x0000_2000_0000_0000;
+
+/// How a process ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Called `exit` with this code.
+    Exited(i32),
+    /// Killed by `signal`, whose default action dumps core if `core` is set.
+    Signaled { signal: u8, core: bool },
+}
+
+impl ExitStatus {
+    /// The code a shell reports: the exit code, or 128 plus the signal.
+    pub fn code(self) -> i32 {
+        match self {
+            ExitStatus::Exited(code) => code,
+            ExitStatus::Signaled { signal, .. } => 128 + signal as i32,
+        }
+    }
+
+    /// Encoded as the status filled in by `wait4`.
+    pub fn wait_status(self) -> u32 {
+        match self {
+            ExitStatus::Exited(code) => ((code & 0xff) << 8) as u32,
+            ExitStatus::Signaled { signal, core } => signal as u32 | if core { 0x80 } else { 0 },
+        }
+    }
+}
+
+pub struct Process {
+    pid: Pid,
+    parent: AtomicU64,
+    /// Set once the process was reparented to init, which then reaps it on exit.
+    orphaned: AtomicBool,
+    /// `None` for init, whose threads run in the kernel address space.
+    pub space: spin::Mutex<Option<AddressSpace>>,
+    pub threads: spin::Mutex<Vec<Arc<Thread>>>,
+    pub files: spin::Mutex<FdTable>,
+    pub handles: spin::Mutex<HandleTable>,
+    /// Absolute path of the working directory, without links, `.` or `..`.
+    cwd: spin::Mutex<String>,
+    /// Shared memory regions mapped in `space`.
+    pub shared: spin::Mutex<Vec<SharedMapping>>,
+    children: spin::Mutex<Vec<Pid>>,
+    /// Next address handed to anonymous `mmap` calls without a hint.
+    mmap_next: spin::Mutex<u64>,
+    pub signals: spin::Mutex<SignalState>,
+    exit_status: spin::Mutex<Option<ExitStatus>>,
+    /// Status given by the first thread that exited the process, which the other threads follow
+    /// on their way back to user mode.
+    exiting: spin::Mutex<Option<ExitStatus>>,
+    /// Notified when one of the children exits.
+    child_exited: WaitQueue,
+}
+
+impl Process {
+    fn new(
+        pid: Pid,
+        parent: Pid,
+        space: Option<AddressSpace>,
+        files: FdTable,
+        cwd: String,
+    ) -> Self {
+        Self {
+            pid,
+            parent: AtomicU64::new(parent),
+            orphaned: AtomicBool::new(false),
+            space: spin::Mutex::new(space),
+            threads: spin::Mutex::new(Vec::new()),
+            files: spin::Mutex::new(files),
+            handles: spin::Mutex::new(HandleTable::new()),
+            cwd: spin::Mutex::new(cwd),
+            shared: spin::Mutex::new(Vec::new()),
+            children: spin::Mutex::new(Vec::new()),
+            mmap_next: spin::Mutex::new(MMAP_BASE),
+            signals: spin::Mutex::new(SignalState::new()),
+            exit_status: spin::Mutex::new(None),
+            exiting: spin::Mutex::new(None),
+            child_exited: WaitQueue::new(),
+        }
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn parent(&self) -> Pid {
+        self.parent.load(Ordering::Relaxed)
+    }
+
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        *self.exit_status.lock()
+    }
+
+    pub fn is_zombie(&self) -> bool {
+        self.exit_status().is_some()
+    }
+
+    /// The status the process ends with once all of its threads are gone, if it is exiting.
+    pub fn exit_pending(&self) -> Option<ExitStatus> {
+        *self.exiting.lock()
+    }
+
+    pub fn cwd(&self) -> String {
+        self.cwd.lock().clone()
+    }
+
+    /// Use [`crate::vfs::chdir`], which checks that `cwd` is a directory.
+    pub fn set_cwd(&self, cwd: String) {
+        *self.cwd.lock() = cwd;
+    }
+
+    pub fn children(&self) -> Vec<Pid> {
+        self.children.lock().clone()
+    }
+
+    /// Reserves `len` bytes of address space for an anonymous mapping, or `None` once they
+    /// wouldn't fit below [`user::USER_END`].
+    pub fn reserve_mmap(&self, len: u64) -> Option<u64> {
+        let mut next = self.mmap_next.lock();
+        let addr = *next;
+        *next = addr.checked_add(len).filter(|&end| end <= user::USER_END)?;
+        Some(addr)
+    }
+
+    /// Gives back a reservation that ended up unused, if nothing was reserved after it.
+    pub fn unreserve_mmap(&self, addr: u64, len: u64) {
+        let mut next = self.mmap_next.lock();
+        if addr.checked_add(len) == Some(*next) {
+            *next = addr;
+        }
+    }
+}
+
+static PROCESSES: spin::Mutex<BTreeMap<Pid, Arc<Process>>> = spin::Mutex::new(BTreeMap::new());
+static NEXT_PID: AtomicU64 = AtomicU64::new(INIT_PID);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitError {
+    /// The caller has no child matching the request.
+    NoChild,
+    /// A signal arrived before any child exited.
+    Interrupted,
+}
+
+/// Creates init and makes the running code its first thread.
+pub fn init() {
+    info!("creating init process");
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    assert_eq!(pid, INIT_PID);
+    let init = Arc::new(Process::new(
+        pid,
+        pid,
+        None,
+        FdTable::with_console(),
+        String::from("/"),
+    ));
+    with_table(|table| table.insert(pid, init.clone()));
+    task::init(pid);
+    init.threads.lock().push(task::current());
+    okay!("created init process");
+}
+
+fn with_table<T>(f: impl FnOnce(&mut BTreeMap<Pid, Arc<Process>>) -> T) -> T {
+    interrupts::without_interrupts(|| f(&mut PROCESSES.lock()))
+}
+
+pub fn get(pid: Pid) -> Option<Arc<Process>> {
+    with_table(|table| table.get(&pid).cloned())
+}
+
+pub fn list() -> Vec<Arc<Process>> {
+    with_table(|table| table.values().cloned().collect())
+}
+
+/// The process of the running thread.
+pub fn current() -> Arc<Process> {
+    get(task::current().process).expect("thread without process")
+}
+
+/// Starts the executable `data` as a child of the running process, inheriting its files.
+pub fn spawn(data: &[u8], argv: &[&str], envp: &[&str]) -> Result<Pid, ElfError> {
+    let files = current().files.lock().clone();
+    spawn_with_files(data, argv, envp, files)
+}
+
+/// Like [`spawn`], with `files` as the descriptors of the child, to redirect its input and output.
+pub fn spawn_with_files(
+    data: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+    files: FdTable,
+) -> Result<Pid, ElfError> {
+    let image = elf::load(data, argv, envp)?;
+    spawn_image(image, &current(), files)
+}
+
+pub fn spawn_image(
+    image: elf::UserImage,
+    parent: &Process,
+    files: FdTable,
+) -> Result<Pid, ElfError> {
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    let cr3 = (image.space.l4_frame(), Cr3::read().1);
+    let process = Arc::new(Process::new(
+        pid,
+        parent.pid,
+        Some(image.space),
+        files,
+        parent.cwd(),
+    ));
+    with_table(|table| table.insert(pid, process.clone()));
+    parent.children.lock().push(pid);
+
+    // entry and stack of the first thread, encoded in a single argument
+    let start = alloc::boxed::Box::new((image.entry, image.stack));
+    let arg = alloc::boxed::Box::into_raw(start) as usize;
+    let thread = task::spawn_raw(enter_user, arg, pid, Some(cr3));
+    process.threads.lock().push(thread);
+    Ok(pid)
+}
+
+fn enter_user(arg: usize) {
+    let start = unsafe { alloc::boxed::Box::from_raw(arg as *mut (VirtAddr, VirtAddr)) };
+    let (entry, stack) = *start;
+    unsafe { user::enter(entry, stack) }
+}
+
+/// Ends the running process with `code`. Never returns to the caller.
+pub fn exit(code: i32) -> ! {
+    exit_with(ExitStatus::Exited(code))
+}
+
+/// Ends the running thread, and the process with `status` once it was the last one. The other
+/// threads are woken up to exit too, and the resources of the process are only released by the
+/// last of them, as the others may still run on its address space.
+pub fn exit_with(status: ExitStatus) -> ! {
+    let process = current();
+    assert_ne!(process.pid, INIT_PID, "init exited");
+
+    let status = *process.exiting.lock().get_or_insert(status);
+    let thread = task::current();
+    let siblings = {
+        let mut threads = process.threads.lock();
+        threads.retain(|t| !Arc::ptr_eq(t, &thread));
+        threads.clone()
+    };
+    drop(thread);
+    if !siblings.is_empty() {
+        for sibling in &siblings {
+            task::wake(sibling);
+        }
+        drop(siblings);
+        drop(process);
+        task::exit_current()
+    }
+
+    // the address space can't be freed while in use
+    unsafe { crate::mem::activate_kernel_space() };
+    process.space.lock().take();
+    process.shared.lock().clear();
+    process.files.lock().close_all();
+    process.handles.lock().close_all();
+
+    let init = get(INIT_PID).unwrap();
+    for child in core::mem::take(&mut *process.children.lock()) {
+        if let Some(child) = get(child) {
+            child.parent.store(INIT_PID, Ordering::Relaxed);
+            child.orphaned.store(true, Ordering::Relaxed);
+            init.children.lock().push(child.pid);
+            // reaps the children that exited before their parent
+            if child.is_zombie() {
+                reap(&init, child.pid);
+            }
+        }
+    }
+
+    *process.exit_status.lock() = Some(status);
+    if process.orphaned.load(Ordering::Relaxed) {
+        reap(&init, process.pid);
+    } else if let Some(parent) = get(process.parent()) {
+        parent.child_exited.notify_all();
+        let _ = signal::send(parent.pid, signal::SIGCHLD as u64);
+    }
+    drop(process);
+    task::exit_current()
+}
+
+fn reap(parent: &Process, pid: Pid) -> Option<ExitStatus> {
+    let child = with_table(|table| table.remove(&pid))?;
+    parent.children.lock().retain(|c| *c != pid);
+    child.exit_status()
+}
+
+/// Waits for the child `pid` of the running process to exit, or any of them if `pid` is `None`,
+/// returning its pid and exit status.
+pub fn wait(pid: Option<Pid>) -> Result<(Pid, ExitStatus), WaitError> {
+    let process = current();
+    let result = signal::wait(&process.child_exited, || {
+        let children = process.children();
+        let mut candidates = children.iter().filter(|c| pid.is_none_or(|p| p == **c));
+        let mut any = false;
+        for child in &mut candidates {
+            any = true;
+            let Some(child) = get(*child) else { continue };
+            if child.is_zombie() {
+                let status = reap(&process, child.pid).unwrap();
+                return Some(Ok((child.pid, status)));
+            }
+        }
+        (!any).then_some(Err(WaitError::NoChild))
+    });
+    result.unwrap_or(Err(WaitError::Interrupted))
+}
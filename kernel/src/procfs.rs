@@ -0,0 +1,360 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{arch::x86_64::__cpuid, fmt::Write};
+
+use bootloader_api::info::MemoryRegionKind;
+
+use crate::{
+    allocator, info,
+    ints::{self, IntIndex},
+    mem, okay,
+    process::{self, Pid},
+    vfs::{self, DirEntry, FileSystem, FileType, FsError, Inode, InodeRef, Metadata},
+    warn,
+};
+
+const ROOT_INO: u64 = 1;
+const SELF_INO: u64 = ROOT_INO + 1 + FILES.len() as u64;
+/// Process directories take the inode numbers from here, two for each pid.
+const PID_INO_BASE: u64 = 0x1000;
+
+/// Renders the content of a file when it is opened.
+type Generator = fn() -> String;
+
+/// Files of the root directory, and what they contain.
+const FILES: &[(&str, Generator)] = &[
+    ("cpuinfo", cpuinfo),
+    ("interrupts", interrupts),
+    ("meminfo", meminfo),
+    ("memmap", memmap),
+    ("uptime", uptime),
+];
+
+/// Files describing the running kernel, generated whenever they are read. Each process has a
+/// directory named after its pid, and `self` links to the one of the reader.
+pub struct ProcFs {
+    root: Arc<ProcRoot>,
+}
+
+impl ProcFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: Arc::new(ProcRoot),
+        })
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &str {
+        "procfs"
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+}
+
+fn metadata(ino: u64, kind: FileType, mode: u16) -> Metadata {
+    let now = ints::uptime_secs();
+    Metadata {
+        ino,
+        kind,
+        size: 0,
+        mode,
+        nlink: if kind == FileType::Directory { 2 } else { 1 },
+        uid: 0,
+        gid: 0,
+        atime: now,
+        mtime: now,
+        ctime: now,
+    }
+}
+
+struct ProcRoot;
+
+impl Inode for ProcRoot {
+    fn metadata(&self) -> Metadata {
+        metadata(ROOT_INO, FileType::Directory, 0o555)
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef, FsError> {
+        if let Some(index) = FILES.iter().position(|(file, _)| *file == name) {
+            return Ok(Arc::new(ProcFile {
+                ino: ROOT_INO + 1 + index as u64,
+                source: Source::Kernel(FILES[index].1),
+            }));
+        }
+        if name == "self" {
+            return Ok(Arc::new(SelfLink));
+        }
+        let pid = name.parse().map_err(|_| FsError::NotFound)?;
+        process::get(pid).ok_or(FsError::NotFound)?;
+        Ok(Arc::new(PidDir { pid }))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let files = FILES.iter().enumerate().map(|(i, (name, _))| DirEntry {
+            name: name.to_string(),
+            ino: ROOT_INO + 1 + i as u64,
+            kind: FileType::File,
+        });
+        let link = DirEntry {
+            name: "self".into(),
+            ino: SELF_INO,
+            kind: FileType::Symlink,
+        };
+        let processes = process::list().into_iter().map(|process| DirEntry {
+            name: process.pid().to_string(),
+            ino: pid_ino(process.pid()),
+            kind: FileType::Directory,
+        });
+        Ok(files
+            .chain(core::iter::once(link))
+            .chain(processes)
+            .collect())
+    }
+}
+
+fn pid_ino(pid: Pid) -> u64 {
+    PID_INO_BASE + pid * 2
+}
+
+/// The directory of the process reading it.
+struct SelfLink;
+
+impl Inode for SelfLink {
+    fn metadata(&self) -> Metadata {
+        metadata(SELF_INO, FileType::Symlink, 0o777)
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        Ok(process::current().pid().to_string())
+    }
+}
+
+struct PidDir {
+    pid: Pid,
+}
+
+impl Inode for PidDir {
+    fn metadata(&self) -> Metadata {
+        metadata(pid_ino(self.pid), FileType::Directory, 0o555)
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef, FsError> {
+        if name != "status" {
+            return Err(FsError::NotFound);
+        }
+        Ok(Arc::new(ProcFile {
+            ino: pid_ino(self.pid) + 1,
+            source: Source::Status(self.pid),
+        }))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(alloc::vec![DirEntry {
+            name: "status".into(),
+            ino: pid_ino(self.pid) + 1,
+            kind: FileType::File,
+        }])
+    }
+}
+
+enum Source {
+    Kernel(Generator),
+    Status(Pid),
+}
+
+struct ProcFile {
+    ino: u64,
+    source: Source,
+}
+
+impl ProcFile {
+    fn generate(&self) -> Result<String, FsError> {
+        match self.source {
+            Source::Kernel(generate) => Ok(generate()),
+            Source::Status(pid) => status(pid),
+        }
+    }
+}
+
+impl Inode for ProcFile {
+    fn metadata(&self) -> Metadata {
+        metadata(self.ino, FileType::File, 0o444)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let text = self.generate()?;
+        let bytes = text.as_bytes();
+        let start = (offset as usize).min(bytes.len());
+        let len = buf.len().min(bytes.len() - start);
+        buf[..len].copy_from_slice(&bytes[start..start + len]);
+        Ok(len)
+    }
+}
+
+fn uptime() -> String {
+    let ticks = ints::get_ticks();
+    let centis = ticks * ints::PIT_DIVISOR * 100 / ints::PIT_FREQUENCY_HZ;
+    format!(
+        "ticks: {ticks}\nseconds: {}.{:02}\n",
+        centis / 100,
+        centis % 100
+    )
+}
+
+fn meminfo() -> String {
+    let frames = mem::frame_stats();
+    let heap = allocator::heap_stats();
+    format!(
+        "frame_size: {}\nframes_total: {}\nframes_used: {}\nheap_size: {}\nheap_used: {}\n",
+        mem::PAGE_SIZE,
+        frames.total,
+        frames.used,
+        heap.size,
+        heap.used
+    )
+}
+
+fn interrupts() -> String {
+    let mut text = String::new();
+    for irq in IntIndex::ALL {
+        let _ = writeln!(
+            text,
+            "{:>3} {:<8} {}",
+            u8::from(irq),
+            irq.name(),
+            irq.count()
+        );
+    }
+    text
+}
+
+fn memmap() -> String {
+    let mut text = String::new();
+    for region in mem::memory_map().iter() {
+        let _ = write!(text, "{:#014x}-{:#014x} ", region.start, region.end);
+        let _ = match region.kind {
+            MemoryRegionKind::Usable => writeln!(text, "usable"),
+            MemoryRegionKind::Bootloader => writeln!(text, "bootloader"),
+            MemoryRegionKind::UnknownUefi(kind) => writeln!(text, "uefi {kind}"),
+            MemoryRegionKind::UnknownBios(kind) => writeln!(text, "bios {kind}"),
+            _ => writeln!(text, "unknown"),
+        };
+    }
+    text
+}
+
+/// Feature bits of CPUID leaf 1 in `edx`, then in `ecx`.
+const EDX_FEATURES: &[(u32, &str)] = &[
+    (0, "fpu"),
+    (1, "vme"),
+    (3, "pse"),
+    (4, "tsc"),
+    (5, "msr"),
+    (6, "pae"),
+    (8, "cx8"),
+    (9, "apic"),
+    (11, "sep"),
+    (12, "mtrr"),
+    (13, "pge"),
+    (15, "cmov"),
+    (16, "pat"),
+    (19, "clflush"),
+    (23, "mmx"),
+    (24, "fxsr"),
+    (25, "sse"),
+    (26, "sse2"),
+    (28, "ht"),
+];
+const ECX_FEATURES: &[(u32, &str)] = &[
+    (0, "sse3"),
+    (1, "pclmulqdq"),
+    (9, "ssse3"),
+    (12, "fma"),
+    (13, "cx16"),
+    (19, "sse4_1"),
+    (20, "sse4_2"),
+    (21, "x2apic"),
+    (23, "popcnt"),
+    (25, "aes"),
+    (26, "xsave"),
+    (28, "avx"),
+    (30, "rdrand"),
+    (31, "hypervisor"),
+];
+
+fn cpuinfo() -> String {
+    let leaf0 = __cpuid(0);
+    let mut vendor = Vec::new();
+    for reg in [leaf0.ebx, leaf0.edx, leaf0.ecx] {
+        vendor.extend_from_slice(&reg.to_le_bytes());
+    }
+    let mut text = format!("vendor: {}\n", String::from_utf8_lossy(&vendor));
+
+    if __cpuid(0x8000_0000).eax >= 0x8000_0004 {
+        let mut brand = Vec::new();
+        for leaf in 0x8000_0002..=0x8000_0004 {
+            let regs = __cpuid(leaf);
+            for reg in [regs.eax, regs.ebx, regs.ecx, regs.edx] {
+                brand.extend_from_slice(&reg.to_le_bytes());
+            }
+        }
+        let brand = String::from_utf8_lossy(&brand);
+        let _ = writeln!(text, "brand: {}", brand.trim_end_matches('\0').trim());
+    }
+
+    if leaf0.eax >= 1 {
+        let leaf1 = __cpuid(1);
+        let mut family = (leaf1.eax >> 8) & 0xf;
+        let mut model = (leaf1.eax >> 4) & 0xf;
+        if family == 0xf {
+            family += (leaf1.eax >> 20) & 0xff;
+        }
+        if family == 0x6 || family >= 0xf {
+            model |= ((leaf1.eax >> 16) & 0xf) << 4;
+        }
+        let _ = writeln!(text, "family: {family}\nmodel: {model}");
+        let _ = writeln!(text, "stepping: {}", leaf1.eax & 0xf);
+        let flags: Vec<_> = EDX_FEATURES
+            .iter()
+            .filter(|(bit, _)| leaf1.edx & 1 << bit != 0)
+            .chain(
+                ECX_FEATURES
+                    .iter()
+                    .filter(|(bit, _)| leaf1.ecx & 1 << bit != 0),
+            )
+            .map(|(_, name)| *name)
+            .collect();
+        let _ = writeln!(text, "flags: {}", flags.join(" "));
+    }
+    text
+}
+
+fn status(pid: Pid) -> Result<String, FsError> {
+    let process = process::get(pid).ok_or(FsError::NotFound)?;
+    let state = match process.exit_status() {
+        Some(status) => format!("zombie ({})", status.code()),
+        None => "running".into(),
+    };
+    Ok(format!(
+        "pid: {pid}\nppid: {}\nstate: {state}\nthreads: {}\ncwd: {}\n",
+        process.parent(),
+        process.threads.lock().len(),
+        process.cwd()
+    ))
+}
+
+/// Mounts a procfs on `/proc`, creating the directory when missing.
+pub fn init() {
+    info!("mounting procfs");
+    match vfs::mount_at("/proc", 0o555, ProcFs::new()) {
+        Ok(()) => okay!("mounted procfs"),
+        Err(err) => warn!("failed to mount procfs: {err:?}"),
+    }
+}
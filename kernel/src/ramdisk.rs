@@ -0,0 +1,54 @@
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::block::{BlockDevice, BlockError, SECTOR_SIZE};
+
+/// A block device stored on the kernel heap.
+pub struct RamDisk {
+    name: String,
+    data: spin::Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    /// A zeroed disk of `sectors` sectors.
+    pub fn new(name: &str, sectors: u64) -> Self {
+        Self::from_bytes(name, vec![0; sectors as usize * SECTOR_SIZE])
+    }
+
+    /// A disk holding `data`, padded with zeroes to whole sectors.
+    pub fn from_bytes(name: &str, mut data: Vec<u8>) -> Self {
+        data.resize(data.len().next_multiple_of(SECTOR_SIZE), 0);
+        Self {
+            name: name.into(),
+            data: spin::Mutex::new(data),
+        }
+    }
+
+    /// A copy of the whole content.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data.lock().clone()
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    ints,
+    vfs::{DirEntry, FileSystem, FileType, FsError, Inode, InodeRef, Metadata},
+};
+
+/// Largest file, to keep a single writer from draining the heap.
+pub const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Inode numbers, unique across every ramfs instance.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+/// A filesystem living on the kernel heap, lost on reboot.
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: RamInode::new(Content::Directory(BTreeMap::new()), 0o755),
+        })
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &str {
+        "ramfs"
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+}
+
+enum Content {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<RamInode>>),
+    Symlink(String),
+}
+
+struct State {
+    content: Content,
+    mode: u16,
+    nlink: u32,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+}
+
+pub struct RamInode {
+    ino: u64,
+    state: spin::Mutex<State>,
+}
+
+impl RamInode {
+    fn new(content: Content, mode: u16) -> Arc<Self> {
+        let now = ints::uptime_secs();
+        let nlink = match content {
+            Content::Directory(_) => 2,
+            _ => 1,
+        };
+        Arc::new(Self {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            state: spin::Mutex::new(State {
+                content,
+                mode: mode & 0o7777,
+                nlink,
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+        })
+    }
+
+    /// Adds `inode` as `name` in this directory.
+    fn insert(&self, name: &str, inode: Arc<RamInode>) -> Result<InodeRef, FsError> {
+        let is_dir = inode.metadata().is_dir();
+        let mut state = self.state.lock();
+        let Content::Directory(entries) = &mut state.content else {
+            return Err(FsError::NotDirectory);
+        };
+        if entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        entries.insert(name.to_string(), inode.clone());
+        // the `..` of the new directory
+        if is_dir {
+            state.nlink += 1;
+        }
+        state.touch();
+        Ok(inode)
+    }
+}
+
+impl State {
+    fn kind(&self) -> FileType {
+        match self.content {
+            Content::File(_) => FileType::File,
+            Content::Directory(_) => FileType::Directory,
+            Content::Symlink(_) => FileType::Symlink,
+        }
+    }
+
+    /// Records a change of the content.
+    fn touch(&mut self) {
+        self.mtime = ints::uptime_secs();
+        self.ctime = self.mtime;
+    }
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        let size = match &state.content {
+            Content::File(data) => data.len() as u64,
+            Content::Directory(entries) => entries.len() as u64,
+            Content::Symlink(target) => target.len() as u64,
+        };
+        Metadata {
+            ino: self.ino,
+            kind: state.kind(),
+            size,
+            mode: state.mode,
+            nlink: state.nlink,
+            uid: 0,
+            gid: 0,
+            atime: state.atime,
+            mtime: state.mtime,
+            ctime: state.ctime,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut state = self.state.lock();
+        let Content::File(data) = &state.content else {
+            return Err(not_file(state.kind()));
+        };
+        let start = (offset as usize).min(data.len());
+        let read = buf.len().min(data.len() - start);
+        buf[..read].copy_from_slice(&data[start..start + read]);
+        state.atime = ints::uptime_secs();
+        Ok(read)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or(FsError::NoSpace)?;
+        let mut state = self.state.lock();
+        let kind = state.kind();
+        let Content::File(data) = &mut state.content else {
+            return Err(not_file(kind));
+        };
+        // writing past the end leaves a hole of zeroes
+        if data.len() < end as usize {
+            data.resize(end as usize, 0);
+        }
+        data[offset as usize..end as usize].copy_from_slice(buf);
+        state.touch();
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if size > MAX_FILE_SIZE {
+            return Err(FsError::NoSpace);
+        }
+        let mut state = self.state.lock();
+        let kind = state.kind();
+        let Content::File(data) = &mut state.content else {
+            return Err(not_file(kind));
+        };
+        data.resize(size as usize, 0);
+        state.touch();
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<InodeRef, FsError> {
+        let state = self.state.lock();
+        let Content::Directory(entries) = &state.content else {
+            return Err(FsError::NotDirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+        Ok(inode.clone())
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> Result<InodeRef, FsError> {
+        let content = match kind {
+            FileType::File => Content::File(Vec::new()),
+            FileType::Directory => Content::Directory(BTreeMap::new()),
+            _ => return Err(FsError::Unsupported),
+        };
+        self.insert(name, RamInode::new(content, mode))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<InodeRef, FsError> {
+        let link = RamInode::new(Content::Symlink(target.to_string()), 0o777);
+        self.insert(name, link)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let mut state = self.state.lock();
+        let Content::Directory(entries) = &mut state.content else {
+            return Err(FsError::NotDirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+        let mut child = inode.state.lock();
+        let is_dir = match &child.content {
+            Content::Directory(children) if !children.is_empty() => return Err(FsError::NotEmpty),
+            Content::Directory(_) => true,
+            _ => false,
+        };
+        child.nlink -= 1;
+        child.ctime = ints::uptime_secs();
+        drop(child);
+        // open files keep the inode alive until they are closed
+        entries.remove(name);
+        if is_dir {
+            state.nlink -= 1;
+        }
+        state.touch();
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &InodeRef, new_name: &str) -> Result<(), FsError> {
+        let new_dir = (&**new_dir as &dyn Any)
+            .downcast_ref::<RamInode>()
+            .ok_or(FsError::CrossDevice)?;
+        let inode = match &self.state.lock().content {
+            Content::Directory(entries) => entries.get(old_name).cloned(),
+            _ => return Err(FsError::NotDirectory),
+        };
+        let inode = inode.ok_or(FsError::NotFound)?;
+        let is_dir = inode.metadata().is_dir();
+        if let Ok(existing) = new_dir.lookup(new_name) {
+            let existing = existing.metadata();
+            if existing.ino == inode.ino {
+                return Ok(());
+            }
+            match (is_dir, existing.is_dir()) {
+                (true, false) => return Err(FsError::NotDirectory),
+                (false, true) => return Err(FsError::IsDirectory),
+                _ => new_dir.unlink(new_name)?,
+            }
+        }
+
+        // linked under its new name first, so that a failure leaves it under the old one
+        new_dir.insert(new_name, inode.clone())?;
+        inode.state.lock().ctime = ints::uptime_secs();
+        let mut state = self.state.lock();
+        if let Content::Directory(entries) = &mut state.content {
+            entries.remove(old_name);
+        }
+        if is_dir {
+            state.nlink -= 1;
+        }
+        state.touch();
+        Ok(())
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let entries: Vec<(String, Arc<RamInode>)> = {
+            let mut state = self.state.lock();
+            state.atime = ints::uptime_secs();
+            let Content::Directory(entries) = &state.content else {
+                return Err(FsError::NotDirectory);
+            };
+            entries
+                .iter()
+                .map(|(name, inode)| (name.clone(), inode.clone()))
+                .collect()
+        };
+        Ok(entries
+            .into_iter()
+            .map(|(name, inode)| DirEntry {
+                name,
+                ino: inode.ino,
+                kind: inode.state.lock().kind(),
+            })
+            .collect())
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        match &self.state.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidPath),
+        }
+    }
+
+    fn set_mode(&self, mode: u16) -> Result<(), FsError> {
+        let mut state = self.state.lock();
+        state.mode = mode & 0o7777;
+        state.ctime = ints::uptime_secs();
+        Ok(())
+    }
+}
+
+fn not_file(kind: FileType) -> FsError {
+    match kind {
+        FileType::Directory => FsError::IsDirectory,
+        _ => FsError::Unsupported,
+    }
+}
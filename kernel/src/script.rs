@@ -0,0 +1,380 @@
+use alloc::{string::String, vec::Vec};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnterminatedQuote,
+    /// A `${` without its `}`.
+    UnterminatedVariable,
+    /// An operator where a command was expected, as in `| ls` or `ls &&`.
+    MissingCommand,
+    /// A redirection not followed by a file name.
+    MissingTarget,
+    /// `&` alone, as commands can't run in the background.
+    Background,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Part {
+    Literal(String),
+    /// The value of a variable, substituted when the command runs.
+    Variable(String),
+}
+
+/// A word of a command, which stays a single argument whatever its variables expand to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Word(pub Vec<Part>);
+
+impl Word {
+    fn push_char(&mut self, ch: char) {
+        match self.0.last_mut() {
+            Some(Part::Literal(text)) => text.push(ch),
+            _ => self.0.push(Part::Literal(ch.into())),
+        }
+    }
+
+    /// The word when it has no variable in it.
+    pub fn as_literal(&self) -> Option<String> {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => Some(text.as_str()),
+                Part::Variable(_) => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `<`
+    Read,
+    /// `>`
+    Write,
+    /// `>>`
+    Append,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redirect {
+    /// The descriptor redirected, 0 for the input, 1 for the output and 2 for errors.
+    pub fd: usize,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// `NAME=value` words before the command name.
+    pub assignments: Vec<(String, Word)>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// Commands connected by `|`, each reading what the previous one writes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pipeline(pub Vec<SimpleCommand>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`, running the next pipeline after a success.
+    And,
+    /// `||`, running the next pipeline after a failure.
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(Word),
+    Pipe,
+    And,
+    Or,
+    Redirect(usize, RedirectKind),
+    /// `;`
+    Semicolon,
+    Newline,
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// Whether `name` can be assigned, as in `NAME=value`.
+pub fn is_valid_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|ch| !ch.is_ascii_digit()) && name.chars().all(is_name_char)
+}
+
+struct Lexer<'a> {
+    chars: core::iter::Peekable<core::str::Chars<'a>>,
+    tokens: Vec<Token>,
+    word: Word,
+    /// Set once the word has something, even an empty quoted string.
+    in_word: bool,
+}
+
+impl Lexer<'_> {
+    fn finish_word(&mut self) {
+        if self.in_word {
+            self.tokens
+                .push(Token::Word(core::mem::take(&mut self.word)));
+            self.in_word = false;
+        }
+    }
+
+    /// Reads a variable after `$`, keeping the `$` when no name follows.
+    fn variable(&mut self) -> Result<(), ParseError> {
+        self.in_word = true;
+        let name = match self.chars.peek() {
+            Some('{') => {
+                self.chars.next();
+                let mut name = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break name,
+                        Some(ch) => name.push(ch),
+                        None => return Err(ParseError::UnterminatedVariable),
+                    }
+                }
+            }
+            Some(&ch) if ch == '?' || ch == '#' || ch.is_ascii_digit() => {
+                self.chars.next();
+                ch.into()
+            }
+            Some(&ch) if is_name_char(ch) => {
+                let mut name = String::new();
+                while let Some(&ch) = self.chars.peek().filter(|&&ch| is_name_char(ch)) {
+                    name.push(ch);
+                    self.chars.next();
+                }
+                name
+            }
+            _ => {
+                self.word.push_char('$');
+                return Ok(());
+            }
+        };
+        self.word.0.push(Part::Variable(name));
+        Ok(())
+    }
+
+    fn double_quoted(&mut self) -> Result<(), ParseError> {
+        self.in_word = true;
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(()),
+                Some('$') => self.variable()?,
+                Some('\\') => match self.chars.next() {
+                    Some(ch @ ('"' | '\\' | '$')) => self.word.push_char(ch),
+                    Some('\n') => {}
+                    Some(ch) => {
+                        self.word.push_char('\\');
+                        self.word.push_char(ch);
+                    }
+                    None => return Err(ParseError::UnterminatedQuote),
+                },
+                Some(ch) => self.word.push_char(ch),
+                None => return Err(ParseError::UnterminatedQuote),
+            }
+        }
+    }
+
+    fn redirect(&mut self, kind: RedirectKind) {
+        // `2>` redirects the errors, but `a2>` writes the output of a command taking `a2`
+        let fd = if kind != RedirectKind::Read && self.word.as_literal().as_deref() == Some("2") {
+            self.word = Word::default();
+            self.in_word = false;
+            2
+        } else {
+            self.finish_word();
+            if kind == RedirectKind::Read {
+                0
+            } else {
+                1
+            }
+        };
+        self.tokens.push(Token::Redirect(fd, kind));
+    }
+
+    fn run(mut self) -> Result<Vec<Token>, ParseError> {
+        while let Some(ch) = self.chars.next() {
+            match ch {
+                ' ' | '\t' | '\r' => self.finish_word(),
+                '\n' => {
+                    self.finish_word();
+                    self.tokens.push(Token::Newline);
+                }
+                '#' if !self.in_word => while self.chars.next_if(|&ch| ch != '\n').is_some() {},
+                ';' => {
+                    self.finish_word();
+                    self.tokens.push(Token::Semicolon);
+                }
+                '|' => {
+                    self.finish_word();
+                    let token = match self.chars.next_if_eq(&'|') {
+                        Some(_) => Token::Or,
+                        None => Token::Pipe,
+                    };
+                    self.tokens.push(token);
+                }
+                '&' => {
+                    self.finish_word();
+                    self.chars.next_if_eq(&'&').ok_or(ParseError::Background)?;
+                    self.tokens.push(Token::And);
+                }
+                '<' => self.redirect(RedirectKind::Read),
+                '>' => {
+                    let kind = match self.chars.next_if_eq(&'>') {
+                        Some(_) => RedirectKind::Append,
+                        None => RedirectKind::Write,
+                    };
+                    self.redirect(kind);
+                }
+                '\'' => {
+                    self.in_word = true;
+                    loop {
+                        match self.chars.next() {
+                            Some('\'') => break,
+                            Some(ch) => self.word.push_char(ch),
+                            None => return Err(ParseError::UnterminatedQuote),
+                        }
+                    }
+                }
+                '"' => self.double_quoted()?,
+                '\\' => match self.chars.next() {
+                    // a line continues after a backslash ending it
+                    Some('\n') => {}
+                    Some(ch) => {
+                        self.in_word = true;
+                        self.word.push_char(ch);
+                    }
+                    None => {}
+                },
+                '$' => self.variable()?,
+                ch => {
+                    self.in_word = true;
+                    self.word.push_char(ch);
+                }
+            }
+        }
+        self.finish_word();
+        Ok(self.tokens)
+    }
+}
+
+/// Splits an assignment word into its name and value.
+fn assignment(word: &Word) -> Option<(String, Word)> {
+    let Some(Part::Literal(first)) = word.0.first() else {
+        return None;
+    };
+    let (name, value) = first.split_once('=')?;
+    if !is_valid_name(name) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    if !value.is_empty() {
+        parts.push(Part::Literal(value.into()));
+    }
+    parts.extend(word.0[1..].iter().cloned());
+    Some((name.into(), Word(parts)))
+}
+
+struct Parser {
+    tokens: core::iter::Peekable<alloc::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    /// Skips the newlines an operator can be followed by.
+    fn skip_newlines(&mut self) {
+        while self.tokens.next_if_eq(&Token::Newline).is_some() {}
+    }
+
+    fn command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            match self.tokens.peek() {
+                Some(Token::Word(_)) => {
+                    let Some(Token::Word(word)) = self.tokens.next() else {
+                        unreachable!()
+                    };
+                    match assignment(&word) {
+                        Some(assign) if command.words.is_empty() => {
+                            command.assignments.push(assign)
+                        }
+                        _ => command.words.push(word),
+                    }
+                }
+                Some(&Token::Redirect(fd, kind)) => {
+                    self.tokens.next();
+                    let Some(Token::Word(target)) = self.tokens.next() else {
+                        return Err(ParseError::MissingTarget);
+                    };
+                    command.redirects.push(Redirect { fd, kind, target });
+                }
+                _ => break,
+            }
+        }
+        if command == SimpleCommand::default() {
+            return Err(ParseError::MissingCommand);
+        }
+        Ok(command)
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = alloc::vec![self.command()?];
+        while self.tokens.next_if_eq(&Token::Pipe).is_some() {
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline(commands))
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.tokens.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => break,
+            };
+            self.tokens.next();
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+}
+
+/// Parses a line or a whole script into the commands it runs, in order.
+pub fn parse(text: &str) -> Result<Vec<AndOr>, ParseError> {
+    let lexer = Lexer {
+        chars: text.chars().peekable(),
+        tokens: Vec::new(),
+        word: Word::default(),
+        in_word: false,
+    };
+    let mut parser = Parser {
+        tokens: lexer.run()?.into_iter().peekable(),
+    };
+    let mut list = Vec::new();
+    loop {
+        while parser
+            .tokens
+            .next_if(|token| matches!(token, Token::Newline | Token::Semicolon))
+            .is_some()
+        {}
+        if parser.tokens.peek().is_none() {
+            return Ok(list);
+        }
+        list.push(parser.and_or()?);
+        match parser.tokens.peek() {
+            None | Some(Token::Newline | Token::Semicolon) => {}
+            Some(_) => return Err(ParseError::MissingCommand),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use alloc::sync::Arc;
+use core::fmt::{self, Write};
+
+use uart_16550::SerialPort;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::{
+    device::{self, CharDevice},
+    info,
+    ints::PICS,
+    okay, signal,
+    task::WaitQueue,
+    vfs::FsError,
+};
+
+pub const COM1_PORT: u16 = 0x3F8;
+/// Line status register, offset from the base port.
+const LINE_STATUS: u16 = 5;
+const DATA_READY: u8 = 1;
+/// Received bytes buffered until read, as the interrupt handler can't allocate.
+const INPUT_CAPACITY: usize = 256;
+
+static PORT: spin::Mutex<SerialPort> = spin::Mutex::new(unsafe { SerialPort::new(COM1_PORT) });
+static INPUT: spin::Mutex<Ring> = spin::Mutex::new(Ring::new());
+/// Notified when bytes are received.
+static INPUT_READY: WaitQueue = WaitQueue::new();
+
+/// Bytes received but not read yet, the oldest dropped when full.
+struct Ring {
+    data: [u8; INPUT_CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            data: [0; INPUT_CAPACITY],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.data[(self.start + self.len) % INPUT_CAPACITY] = byte;
+        if self.len == INPUT_CAPACITY {
+            self.start = (self.start + 1) % INPUT_CAPACITY;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.data[self.start];
+        self.start = (self.start + 1) % INPUT_CAPACITY;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// The first serial port, as `ttyS0`. Reads block until something is received.
+pub struct Serial;
+
+impl CharDevice for Serial {
+    fn name(&self) -> &str {
+        "ttyS0"
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        signal::wait(&INPUT_READY, || {
+            let mut input = INPUT.lock();
+            let mut len = 0;
+            while len < buf.len() {
+                let Some(byte) = input.pop() else { break };
+                buf[len] = byte;
+                len += 1;
+            }
+            (len > 0).then_some(len)
+        })
+        .map_err(|_| FsError::Interrupted)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        interrupts::without_interrupts(|| {
+            let mut port = PORT.lock();
+            for &byte in buf {
+                port.send_raw(byte);
+            }
+        });
+        Ok(buf.len())
+    }
+
+    fn mode(&self) -> u16 {
+        0o620
+    }
+}
+
+/// Sends text as is, where [`SerialPort`] would turn a backspace into erasing the character.
+struct Raw<'a>(&'a mut SerialPort);
+
+impl Write for Raw<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.0.send_raw(byte));
+        Ok(())
+    }
+}
+
+fn write_console(text: fmt::Arguments) {
+    let _ = Raw(&mut PORT.lock()).write_fmt(text);
+}
+
+/// Sets up the port and logs kernel messages to it, before anything else is ready.
+pub fn init() {
+    PORT.lock().init();
+    device::add_console(write_console);
+}
+
+/// Registers the port as `ttyS0` and starts receiving on its interrupt line.
+pub fn register() {
+    info!("registering serial port");
+    device::register(Arc::new(Serial));
+    unsafe {
+        let mut pics = PICS.lock();
+        let [master, slave] = pics.read_masks();
+        pics.write_masks(master & !(1 << 4), slave);
+    }
+    okay!("registered serial port");
+}
+
+/// Buffers the received bytes, called from the serial interrupt.
+pub fn handle_interrupt() {
+    let mut status = Port::<u8>::new(COM1_PORT + LINE_STATUS);
+    let mut data = Port::<u8>::new(COM1_PORT);
+    let mut input = INPUT.lock();
+    while unsafe { status.read() } & DATA_READY != 0 {
+        input.push(unsafe { data.read() });
+    }
+    drop(input);
+    INPUT_READY.notify_all();
+}
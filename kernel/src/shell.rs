@@ -0,0 +1,920 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+
+use crate::{
+    allocator, channel, elf,
+    fd::{Console, FdTable, FileRef},
+    info, ints, mem, okay, pipe, power, process,
+    script::{self, Connector, Part, Pipeline, Redirect, RedirectKind, SimpleCommand, Word},
+    task,
+    vfs::{self, FileType, O_APPEND, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY},
+};
+
+/// Most lines kept in the history.
+const HISTORY_SIZE: usize = 100;
+/// Exit status of a line naming no known command.
+pub const NOT_FOUND: i32 = 127;
+/// Exit status of a program that can't be started.
+pub const NOT_EXECUTABLE: i32 = 126;
+/// Exit status of a line that can't be parsed.
+pub const SYNTAX_ERROR: i32 = 2;
+/// Script run when the shell starts.
+pub const RC_PATH: &str = "/etc/rc";
+/// Most scripts running one another, which all share the stack of a thread.
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+macro_rules! outln {
+    ($io:expr, $($args:tt)*) => {
+        $io.out(format_args!("{}\n", format_args!($($args)*)))
+    };
+}
+
+macro_rules! errln {
+    ($io:expr, $($args:tt)*) => {
+        $io.err(format_args!("{}\n", format_args!($($args)*)))
+    };
+}
+
+/// Writes formatted text to a file, giving up silently at the first error.
+struct FileWriter<'a>(&'a FileRef);
+
+impl Write for FileWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut done = 0;
+        while done < s.len() {
+            match self.0.write(&s.as_bytes()[done..]) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(written) => done += written,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a shell keeps from one command to the next.
+#[derive(Clone, Default)]
+pub struct Session {
+    /// Shell variables, which commands see as their environment.
+    vars: BTreeMap<String, String>,
+    /// Exit status of the last pipeline, as `$?`.
+    last_status: i32,
+    /// Scripts started and not finished yet.
+    depth: usize,
+}
+
+impl Session {
+    /// Sets each of `names` to the value at the same index, unsetting it for `None`, and
+    /// returns what they were before.
+    fn swap_vars(&mut self, names: &[&str], values: Vec<Option<String>>) -> Vec<Option<String>> {
+        names
+            .iter()
+            .zip(values)
+            .map(|(name, value)| match value {
+                Some(value) => self.vars.insert(name.to_string(), value),
+                None => self.vars.remove(*name),
+            })
+            .collect()
+    }
+}
+
+/// Where a command reads and writes, the console unless redirected, and the session it runs in.
+#[derive(Clone)]
+pub struct Io {
+    pub stdin: FileRef,
+    pub stdout: FileRef,
+    pub stderr: FileRef,
+    /// Shared by the commands run one after the other, and copied for those of a pipeline
+    /// running in threads of their own.
+    pub session: Arc<spin::Mutex<Session>>,
+}
+
+impl Io {
+    /// The console, in a new session.
+    pub fn console() -> Self {
+        let console: FileRef = Arc::new(Console);
+        Self {
+            stdin: console.clone(),
+            stdout: console.clone(),
+            stderr: console,
+            session: Arc::default(),
+        }
+    }
+
+    pub fn var(&self, name: &str) -> Option<String> {
+        let session = self.session.lock();
+        match name {
+            "?" => Some(session.last_status.to_string()),
+            _ => session.vars.get(name).cloned(),
+        }
+    }
+
+    pub fn set_var(&self, name: &str, value: &str) {
+        self.session.lock().vars.insert(name.into(), value.into());
+    }
+
+    pub fn unset_var(&self, name: &str) {
+        self.session.lock().vars.remove(name);
+    }
+
+    /// Every variable, sorted by name.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let session = self.session.lock();
+        session
+            .vars
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// The text of `word` with its variables substituted, unset ones being empty.
+    fn expand(&self, word: &Word) -> String {
+        word.0
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Variable(name) => self.var(name).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn out(&self, args: fmt::Arguments) {
+        let _ = FileWriter(&self.stdout).write_fmt(args);
+    }
+
+    pub fn err(&self, args: fmt::Arguments) {
+        let _ = FileWriter(&self.stderr).write_fmt(args);
+    }
+}
+
+/// Runs a command with the arguments after its name, returning its exit status, 0 on success.
+pub type CommandFn = fn(&[&str], &Io) -> i32;
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    /// The arguments taken, as in `[path...]`.
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+static COMMANDS: spin::Mutex<BTreeMap<&'static str, Command>> = spin::Mutex::new(BTreeMap::new());
+
+/// Makes `command` runnable by its name, replacing any command of the same name.
+pub fn register(command: Command) {
+    COMMANDS.lock().insert(command.name, command);
+}
+
+pub fn get(name: &str) -> Option<Command> {
+    COMMANDS.lock().get(name).copied()
+}
+
+/// Every command, sorted by name.
+pub fn commands() -> Vec<Command> {
+    COMMANDS.lock().values().copied().collect()
+}
+
+/// Variables a script sees its arguments as: their count, its path, then the first nine.
+const POSITIONAL: [&str; 11] = ["#", "0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+/// Runs `text`, a line or a whole script, returning the exit status of its last pipeline.
+pub fn run_line(text: &str, io: &Io) -> i32 {
+    let list = match script::parse(text) {
+        Ok(list) => list,
+        Err(err) => {
+            errln!(io, "sh: syntax error: {err:?}");
+            return SYNTAX_ERROR;
+        }
+    };
+    let mut status = 0;
+    for and_or in &list {
+        status = run_pipeline(&and_or.first, io);
+        for (connector, pipeline) in &and_or.rest {
+            let run = match connector {
+                Connector::And => status == 0,
+                Connector::Or => status != 0,
+            };
+            if run {
+                status = run_pipeline(pipeline, io);
+            }
+        }
+    }
+    status
+}
+
+/// Runs the script `path`, with `args` as `$1` and on.
+pub fn run_file(path: &str, args: &[&str], io: &Io) -> i32 {
+    match vfs::read_file(path) {
+        Ok(text) => run_script(path, &text, args, io),
+        Err(err) => {
+            errln!(io, "sh: {path}: {err:?}");
+            NOT_FOUND
+        }
+    }
+}
+
+/// Runs the file `path`: as a program of its own if it is an ELF executable, or else as a script.
+fn run_path(path: &str, args: &[&str], io: &Io) -> i32 {
+    match vfs::read_file(path) {
+        Ok(data) if data.starts_with(elf::ELF_MAGIC) => run_program(path, &data, args, io),
+        Ok(text) => run_script(path, &text, args, io),
+        Err(err) => {
+            errln!(io, "sh: {path}: {err:?}");
+            NOT_FOUND
+        }
+    }
+}
+
+/// Runs the executable `data` in a new process, with the descriptors and variables of `io`, and
+/// waits for it to finish.
+fn run_program(path: &str, data: &[u8], args: &[&str], io: &Io) -> i32 {
+    let mut files = FdTable::new();
+    for (fd, file) in [&io.stdin, &io.stdout, &io.stderr].into_iter().enumerate() {
+        files.set(fd, file.clone()).unwrap();
+    }
+    let mut argv = alloc::vec![path];
+    argv.extend_from_slice(args);
+    let env: Vec<String> = io
+        .vars()
+        .into_iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    let envp: Vec<&str> = env.iter().map(String::as_str).collect();
+    let pid = match process::spawn_with_files(data, &argv, &envp, files) {
+        Ok(pid) => pid,
+        Err(err) => {
+            errln!(io, "sh: {path}: {err:?}");
+            return NOT_EXECUTABLE;
+        }
+    };
+    match process::wait(Some(pid)) {
+        Ok((_, status)) => status.code(),
+        Err(err) => {
+            errln!(io, "sh: {path}: {err:?}");
+            1
+        }
+    }
+}
+
+/// Runs `text` as the script `path`, with `args` as `$1` and on.
+fn run_script(path: &str, text: &[u8], args: &[&str], io: &Io) -> i32 {
+    {
+        let mut session = io.session.lock();
+        if session.depth >= MAX_SCRIPT_DEPTH {
+            drop(session);
+            errln!(io, "sh: {path}: scripts nested too deeply");
+            return 1;
+        }
+        session.depth += 1;
+    }
+    let mut positional = alloc::vec![Some(args.len().to_string()), Some(path.into())];
+    positional.extend((0..9).map(|i| args.get(i).map(|arg| arg.to_string())));
+    let saved = io.session.lock().swap_vars(&POSITIONAL, positional);
+    let status = run_line(&String::from_utf8_lossy(text), io);
+    io.session.lock().swap_vars(&POSITIONAL, saved);
+    io.session.lock().depth -= 1;
+    status
+}
+
+/// Runs every command of `pipeline` at once, each in its own thread but the last, and returns the
+/// exit status of the last. Only the last one runs in the session of `io`, the others in copies
+/// of it.
+fn run_pipeline(pipeline: &Pipeline, io: &Io) -> i32 {
+    let (last, firsts) = pipeline.0.split_last().unwrap();
+    let (sender, receiver) = channel::channel(pipeline.0.len());
+    let mut stdin = io.stdin.clone();
+    for command in firsts {
+        let (reader, writer) = pipe::pipe();
+        let session = io.session.lock().clone();
+        let stage = Io {
+            stdin: core::mem::replace(&mut stdin, reader),
+            stdout: writer,
+            stderr: io.stderr.clone(),
+            session: Arc::new(spin::Mutex::new(session)),
+        };
+        let command = command.clone();
+        let sender = sender.clone();
+        // the ends of the pipes close once the command is done with its `Io`
+        task::spawn(move || {
+            let _ = sender.send(run_command(&command, stage));
+        });
+    }
+    let status = run_command(
+        last,
+        Io {
+            stdin,
+            ..io.clone()
+        },
+    );
+    for _ in firsts {
+        let _ = receiver.receive();
+    }
+    io.session.lock().last_status = status;
+    status
+}
+
+/// Opens the files of `redirects` in place of the descriptors of `io`.
+fn redirect(redirects: &[Redirect], mut io: Io) -> Result<Io, i32> {
+    for redirect in redirects {
+        let path = io.expand(&redirect.target);
+        let flags = match redirect.kind {
+            RedirectKind::Read => O_RDONLY,
+            RedirectKind::Write => O_WRONLY | O_CREAT | O_TRUNC,
+            RedirectKind::Append => O_WRONLY | O_CREAT | O_APPEND,
+        };
+        let file: FileRef = match vfs::open(&path, flags, 0o644) {
+            Ok(file) => file,
+            Err(err) => {
+                errln!(io, "sh: {path}: {err:?}");
+                return Err(1);
+            }
+        };
+        match redirect.fd {
+            0 => io.stdin = file,
+            1 => io.stdout = file,
+            _ => io.stderr = file,
+        }
+    }
+    Ok(io)
+}
+
+/// Runs a command, or a program or script when its name is a path. Assignments alone set variables for
+/// good, while those before a command only last as long as it runs.
+fn run_command(command: &SimpleCommand, io: Io) -> i32 {
+    let words: Vec<String> = command.words.iter().map(|word| io.expand(word)).collect();
+    let mut names: Vec<&str> = command
+        .assignments
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let values = command
+        .assignments
+        .iter()
+        .map(|(_, value)| Some(io.expand(value)))
+        .collect();
+    let mut saved = io.session.lock().swap_vars(&names, values);
+    let session = io.session.clone();
+    let status = run_words(&words, &command.redirects, io);
+    if !words.is_empty() {
+        // backwards, so a name assigned twice gets the value it had before the first
+        names.reverse();
+        saved.reverse();
+        session.lock().swap_vars(&names, saved);
+    }
+    status
+}
+
+fn run_words(words: &[String], redirects: &[Redirect], io: Io) -> i32 {
+    let io = match redirect(redirects, io) {
+        Ok(io) => io,
+        Err(status) => return status,
+    };
+    let Some((name, args)) = words.split_first() else {
+        return 0;
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match get(name) {
+        Some(command) => (command.run)(&args, &io),
+        None if name.contains('/') => run_path(name, &args, &io),
+        None => {
+            errln!(io, "{name}: command not found");
+            NOT_FOUND
+        }
+    }
+}
+
+/// Finds what the word ending `line` could be completed to, returning where the word starts and
+/// every full word it could become.
+pub type CompleteFn = fn(&str) -> (usize, Vec<String>);
+
+/// Completes the first word of a command with command names, and the others with paths.
+pub fn complete(line: &str) -> (usize, Vec<String>) {
+    let start = line.rfind(' ').map_or(0, |i| i + 1);
+    let word = &line[start..];
+    let before = line[..start].trim_end();
+    if before.is_empty() || before.ends_with(['|', '&', ';']) {
+        let names = commands()
+            .into_iter()
+            .map(|command| command.name)
+            .filter(|name| name.starts_with(word))
+            .map(String::from)
+            .collect();
+        return (start, names);
+    }
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let Ok(entries) = vfs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return (start, Vec::new());
+    };
+    let mut paths: Vec<String> = entries
+        .into_iter()
+        .filter(|entry| entry.name.starts_with(prefix))
+        .filter(|entry| !entry.name.starts_with('.') || prefix.starts_with('.'))
+        .map(|entry| {
+            let slash = if entry.kind == FileType::Directory {
+                "/"
+            } else {
+                ""
+            };
+            format!("{dir}{}{slash}", entry.name)
+        })
+        .collect();
+    paths.sort();
+    (start, paths)
+}
+
+enum Escape {
+    None,
+    /// After `ESC`.
+    Started,
+    /// After `ESC [` or `ESC O`, with the parameters so far.
+    Sequence(String),
+}
+
+/// Edits a line typed on a terminal, echoing it to the terminal. Understands the keys a VT100
+/// sends for arrows, home, end and delete, with backspace, tab completion and a history browsed
+/// with up and down.
+pub struct LineEditor {
+    prompt: String,
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// Index of the history entry shown, `history.len()` for the line being typed.
+    shown: usize,
+    /// The line being typed while the history is shown.
+    draft: Vec<char>,
+    escape: Escape,
+    /// Bytes of a character not received entirely yet.
+    utf8: Vec<u8>,
+    complete: CompleteFn,
+}
+
+impl LineEditor {
+    pub fn new(complete: CompleteFn) -> Self {
+        Self {
+            prompt: String::new(),
+            line: Vec::new(),
+            cursor: 0,
+            history: Vec::new(),
+            shown: 0,
+            draft: Vec::new(),
+            escape: Escape::None,
+            utf8: Vec::new(),
+            complete,
+        }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Characters of the line before the editing cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Shows `prompt` and starts editing an empty line. The terminal keeps where the prompt ended
+    /// with `ESC 7`, and the cursor is put back on the line from there.
+    pub fn start(&mut self, prompt: &str, out: &mut impl Write) {
+        self.prompt = prompt.into();
+        self.line.clear();
+        self.cursor = 0;
+        self.shown = self.history.len();
+        self.draft.clear();
+        let _ = write!(out, "{prompt}\x1b7");
+    }
+
+    /// Handles a received byte, returning the line once it is entered. A line cancelled with
+    /// Ctrl-C is returned empty.
+    pub fn feed(&mut self, byte: u8, out: &mut impl Write) -> Option<String> {
+        match core::mem::replace(&mut self.escape, Escape::None) {
+            Escape::Started if byte == b'[' || byte == b'O' => {
+                self.escape = Escape::Sequence(String::new());
+                return None;
+            }
+            Escape::Started => return None,
+            Escape::Sequence(mut params) => {
+                if byte.is_ascii_digit() || byte == b';' {
+                    params.push(byte as char);
+                    self.escape = Escape::Sequence(params);
+                } else {
+                    self.escape_sequence(&params, byte, out);
+                }
+                return None;
+            }
+            Escape::None => {}
+        }
+
+        match byte {
+            b'\r' | b'\n' => return Some(self.enter(out)),
+            0x1b => self.escape = Escape::Started,
+            0x03 => {
+                self.move_to(self.line.len(), out);
+                let _ = out.write_str("^C\n");
+                self.line.clear();
+                self.cursor = 0;
+                return Some(String::new());
+            }
+            0x01 => self.move_to(0, out),
+            0x05 => self.move_to(self.line.len(), out),
+            0x08 | 0x7f => self.backspace(out),
+            b'\t' => self.tab(out),
+            0..=0x1f => {}
+            0x20..=0x7e => self.insert(&[byte as char], out),
+            _ => {
+                self.utf8.push(byte);
+                match core::str::from_utf8(&self.utf8) {
+                    Ok(text) => {
+                        let chars: Vec<char> = text.chars().collect();
+                        self.utf8.clear();
+                        self.insert(&chars, out);
+                    }
+                    Err(err) if err.error_len().is_some() || self.utf8.len() >= 4 => {
+                        self.utf8.clear();
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        None
+    }
+
+    fn escape_sequence(&mut self, params: &str, last: u8, out: &mut impl Write) {
+        match (last, params) {
+            (b'A', _) => self.history_up(out),
+            (b'B', _) => self.history_down(out),
+            (b'C', _) if self.cursor < self.line.len() => self.move_to(self.cursor + 1, out),
+            (b'D', _) if self.cursor > 0 => self.move_to(self.cursor - 1, out),
+            (b'H', _) | (b'~', "1" | "7") => self.move_to(0, out),
+            (b'F', _) | (b'~', "4" | "8") => self.move_to(self.line.len(), out),
+            (b'~', "3") => self.delete(out),
+            _ => {}
+        }
+    }
+
+    /// Puts the terminal cursor on the editing cursor, going back to where the prompt ended and
+    /// writing the characters before it, so that it wraps with the line wherever other output
+    /// left the cursor.
+    fn place_cursor(&self, out: &mut impl Write) {
+        let before: String = self.line[..self.cursor].iter().collect();
+        let _ = write!(out, "\x1b8{before}");
+    }
+
+    fn move_to(&mut self, to: usize, out: &mut impl Write) {
+        self.cursor = to;
+        self.place_cursor(out);
+    }
+
+    /// Shows the whole line, blanking what is left of a line of `old_len` characters, then puts
+    /// the cursor back.
+    fn redraw(&mut self, old_len: usize, out: &mut impl Write) {
+        let blank = old_len.saturating_sub(self.line.len());
+        let line: String = self.line.iter().collect();
+        let _ = write!(out, "\x1b8{line}{:blank$}", "");
+        self.place_cursor(out);
+    }
+
+    fn insert(&mut self, chars: &[char], out: &mut impl Write) {
+        let at = self.cursor;
+        let old_len = self.line.len();
+        self.line.splice(at..at, chars.iter().copied());
+        self.cursor += chars.len();
+        self.redraw(old_len, out);
+    }
+
+    fn backspace(&mut self, out: &mut impl Write) {
+        if self.cursor == 0 {
+            return;
+        }
+        self.move_to(self.cursor - 1, out);
+        self.delete(out);
+    }
+
+    fn delete(&mut self, out: &mut impl Write) {
+        if self.cursor == self.line.len() {
+            return;
+        }
+        let old_len = self.line.len();
+        self.line.remove(self.cursor);
+        self.redraw(old_len, out);
+    }
+
+    /// Replaces the whole line, leaving the cursor at its end.
+    fn replace_line(&mut self, line: Vec<char>, out: &mut impl Write) {
+        let old_len = self.line.len();
+        self.line = line;
+        self.cursor = self.line.len();
+        self.redraw(old_len, out);
+    }
+
+    fn history_up(&mut self, out: &mut impl Write) {
+        if self.shown == 0 {
+            return;
+        }
+        if self.shown == self.history.len() {
+            self.draft = self.line.clone();
+        }
+        self.shown -= 1;
+        let line = self.history[self.shown].chars().collect();
+        self.replace_line(line, out);
+    }
+
+    fn history_down(&mut self, out: &mut impl Write) {
+        if self.shown == self.history.len() {
+            return;
+        }
+        self.shown += 1;
+        let line = match self.history.get(self.shown) {
+            Some(entry) => entry.chars().collect(),
+            None => core::mem::take(&mut self.draft),
+        };
+        self.replace_line(line, out);
+    }
+
+    fn tab(&mut self, out: &mut impl Write) {
+        let before: String = self.line[..self.cursor].iter().collect();
+        let (start, candidates) = (self.complete)(&before);
+        let start = before[..start].chars().count();
+        let word_len = self.cursor - start;
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        let mut common: Vec<char> = first.chars().collect();
+        for candidate in &candidates[1..] {
+            let same = common
+                .iter()
+                .zip(candidate.chars())
+                .take_while(|(a, b)| **a == *b)
+                .count();
+            common.truncate(same);
+        }
+        if candidates.len() == 1 && !first.ends_with('/') {
+            common.push(' ');
+        }
+        if common.len() > word_len {
+            self.move_to(start, out);
+            self.line.drain(start..start + word_len);
+            self.insert(&common, out);
+            return;
+        }
+        if candidates.len() > 1 {
+            // show the choices below the line, then the prompt and the line again
+            let line: String = self.line.iter().collect();
+            let choices = candidates.join("  ");
+            let _ = write!(out, "\x1b8{line}\n{choices}\n{}\x1b7", self.prompt);
+            self.redraw(0, out);
+        }
+    }
+
+    fn enter(&mut self, out: &mut impl Write) -> String {
+        let line: String = self.line.drain(..).collect();
+        let _ = writeln!(out, "\x1b8{line}");
+        self.cursor = 0;
+        self.utf8.clear();
+        let entry = line.trim();
+        if !entry.is_empty() && self.history.last().map(String::as_str) != Some(entry) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.remove(0);
+            }
+            self.history.push(entry.into());
+        }
+        line
+    }
+}
+
+fn prompt() -> String {
+    format!("(root) [{}]: ", process::current().cwd())
+}
+
+/// Reads lines from the console and runs them, until its input ends.
+fn shell_main() {
+    let io = Io::console();
+    let mut editor = LineEditor::new(complete);
+    let mut byte = [0];
+    if vfs::stat(RC_PATH).is_ok() {
+        run_file(RC_PATH, &[], &io);
+    }
+    loop {
+        let mut out = FileWriter(&io.stdout);
+        editor.start(&prompt(), &mut out);
+        let line = loop {
+            match io.stdin.read(&mut byte) {
+                Ok(1) => {}
+                // end of input, nothing more will ever come
+                Ok(_) => return,
+                Err(err) => {
+                    errln!(io, "sh: read error: {err:?}");
+                    return;
+                }
+            }
+            if let Some(line) = editor.feed(byte[0], &mut out) {
+                break line;
+            }
+        };
+        run_line(&line, &io);
+    }
+}
+
+/// Starts the shell on the console.
+pub fn start() {
+    task::spawn(shell_main);
+}
+
+fn help(_args: &[&str], io: &Io) -> i32 {
+    for command in commands() {
+        let usage = format!("{} {}", command.name, command.usage);
+        outln!(io, "{:<24}{}", usage.trim_end(), command.help);
+    }
+    0
+}
+
+fn clear(_args: &[&str], io: &Io) -> i32 {
+    // homes the cursor, then erases the screen
+    io.out(format_args!("\x1b[H\x1b[2J"));
+    0
+}
+
+fn echo(args: &[&str], io: &Io) -> i32 {
+    outln!(io, "{}", args.join(" "));
+    0
+}
+
+fn ls(args: &[&str], io: &Io) -> i32 {
+    let paths = if args.is_empty() { &["."][..] } else { args };
+    let mut status = 0;
+    for (i, path) in paths.iter().enumerate() {
+        let entries = match vfs::stat(path) {
+            Ok(metadata) if metadata.is_dir() => vfs::read_dir(path),
+            Ok(_) => {
+                outln!(io, "{path}");
+                continue;
+            }
+            Err(err) => Err(err),
+        };
+        let mut entries = match entries {
+            Ok(entries) => entries,
+            Err(err) => {
+                errln!(io, "ls: {path}: {err:?}");
+                status = 1;
+                continue;
+            }
+        };
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        if paths.len() > 1 {
+            let gap = if i == 0 { "" } else { "\n" };
+            outln!(io, "{gap}{path}:");
+        }
+        for entry in entries {
+            let suffix = match entry.kind {
+                FileType::Directory => "/",
+                FileType::Symlink => "@",
+                _ => "",
+            };
+            outln!(io, "{}{suffix}", entry.name);
+        }
+    }
+    status
+}
+
+fn cat(args: &[&str], io: &Io) -> i32 {
+    if args.is_empty() {
+        let mut buf = [0; 512];
+        while let Ok(read @ 1..) = io.stdin.read(&mut buf) {
+            if FileWriter(&io.stdout)
+                .write_str(&String::from_utf8_lossy(&buf[..read]))
+                .is_err()
+            {
+                return 1;
+            }
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for path in args {
+        match vfs::read_file(path) {
+            Ok(data) => io.out(format_args!("{}", String::from_utf8_lossy(&data))),
+            Err(err) => {
+                errln!(io, "cat: {path}: {err:?}");
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+fn cd(args: &[&str], io: &Io) -> i32 {
+    let path = args.first().copied().unwrap_or("/");
+    match vfs::chdir(path) {
+        Ok(()) => 0,
+        Err(err) => {
+            errln!(io, "cd: {path}: {err:?}");
+            1
+        }
+    }
+}
+
+fn pwd(_args: &[&str], io: &Io) -> i32 {
+    outln!(io, "{}", process::current().cwd());
+    0
+}
+
+fn mem(_args: &[&str], io: &Io) -> i32 {
+    let frames = mem::frame_stats();
+    let heap = allocator::heap_stats();
+    let kib = |frames: usize| frames * mem::PAGE_SIZE / 1024;
+    outln!(
+        io,
+        "frames: {} KiB used of {} KiB",
+        kib(frames.used),
+        kib(frames.total)
+    );
+    outln!(
+        io,
+        "heap:   {} KiB used of {} KiB",
+        heap.used / 1024,
+        heap.size / 1024
+    );
+    0
+}
+
+fn ticks(_args: &[&str], io: &Io) -> i32 {
+    let ticks = ints::get_ticks();
+    outln!(io, "{ticks} ticks, up {}s", ints::uptime_secs());
+    0
+}
+
+fn sh(args: &[&str], io: &Io) -> i32 {
+    let Some((path, args)) = args.split_first() else {
+        errln!(io, "sh: missing script");
+        return SYNTAX_ERROR;
+    };
+    run_file(path, args, io)
+}
+
+fn env(_args: &[&str], io: &Io) -> i32 {
+    for (name, value) in io.vars() {
+        outln!(io, "{name}={value}");
+    }
+    0
+}
+
+fn unset(args: &[&str], io: &Io) -> i32 {
+    args.iter().for_each(|name| io.unset_var(name));
+    0
+}
+
+fn true_(_args: &[&str], _io: &Io) -> i32 {
+    0
+}
+
+fn false_(_args: &[&str], _io: &Io) -> i32 {
+    1
+}
+
+fn reboot(_args: &[&str], _io: &Io) -> i32 {
+    power::reboot()
+}
+
+fn shutdown(_args: &[&str], _io: &Io) -> i32 {
+    power::shutdown()
+}
+
+/// Registers the built-in commands.
+pub fn init() {
+    info!("registering shell commands");
+    let builtins: [(&str, &str, &str, CommandFn); 16] = [
+        ("help", "", "list the commands", help),
+        ("clear", "", "erase the screen", clear),
+        ("echo", "[word...]", "print the words", echo),
+        ("ls", "[path...]", "list directories", ls),
+        ("cat", "[path...]", "print files, or the input", cat),
+        ("cd", "[path]", "change the working directory", cd),
+        ("pwd", "", "print the working directory", pwd),
+        ("mem", "", "show memory usage", mem),
+        ("ticks", "", "show the timer ticks since boot", ticks),
+        ("sh", "path [arg...]", "run a script", sh),
+        ("env", "", "list the variables", env),
+        ("unset", "[name...]", "remove variables", unset),
+        ("true", "", "succeed", true_),
+        ("false", "", "fail", false_),
+        ("reboot", "", "restart the machine", reboot),
+        ("shutdown", "", "power the machine off", shutdown),
+    ];
+    for (name, usage, help, run) in builtins {
+        register(Command {
+            name,
+            usage,
+            help,
+            run,
+        });
+    }
+    okay!("registered {} shell commands", commands().len());
+}
@@ -0,0 +1,168 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use crate::{
+    mem::{self, PAGE_SIZE},
+    process::Process,
+    user,
+};
+
+/// Largest shared region, to keep a single request from draining the frame allocator.
+pub const MAX_REGION_SIZE: usize = 64 * 1024 * 1024;
+pub const MAX_NAME_LEN: usize = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShmError {
+    AlreadyExists,
+    NotFound,
+    /// Bad size or name.
+    Invalid,
+    OutOfMemory,
+    /// No region is mapped at the given address.
+    NotMapped,
+}
+
+/// Frames shared by every process mapping the region, freed with the last reference to it.
+pub struct SharedRegion {
+    name: String,
+    frames: Vec<PhysFrame>,
+}
+
+impl SharedRegion {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    pub fn frames(&self) -> &[PhysFrame] {
+        &self.frames
+    }
+}
+
+impl Drop for SharedRegion {
+    fn drop(&mut self) {
+        for frame in self.frames.drain(..) {
+            unsafe { mem::frame_allocator().deallocate_frame(frame) };
+        }
+    }
+}
+
+/// A region mapped in a process, keeping it alive.
+pub struct SharedMapping {
+    pub start: VirtAddr,
+    pub region: Arc<SharedRegion>,
+}
+
+/// Regions reachable by name. Each one stays here until unlinked, even when mapped nowhere.
+static REGIONS: spin::Mutex<BTreeMap<String, Arc<SharedRegion>>> =
+    spin::Mutex::new(BTreeMap::new());
+
+/// Creates the region `name` of at least `size` bytes, zeroed.
+pub fn create(name: &str, size: usize) -> Result<Arc<SharedRegion>, ShmError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN || size == 0 || size > MAX_REGION_SIZE {
+        return Err(ShmError::Invalid);
+    }
+    let mut regions = REGIONS.lock();
+    if regions.contains_key(name) {
+        return Err(ShmError::AlreadyExists);
+    }
+    let mut region = SharedRegion {
+        name: name.to_string(),
+        frames: Vec::new(),
+    };
+    // frames allocated so far are given back by `Drop` on failure
+    for _ in 0..size.div_ceil(PAGE_SIZE) {
+        let frame = mem::frame_allocator()
+            .allocate_frame()
+            .ok_or(ShmError::OutOfMemory)?;
+        mem::zero_frame(frame);
+        region.frames.push(frame);
+    }
+    let region = Arc::new(region);
+    regions.insert(region.name.clone(), region.clone());
+    Ok(region)
+}
+
+pub fn open(name: &str) -> Result<Arc<SharedRegion>, ShmError> {
+    REGIONS.lock().get(name).cloned().ok_or(ShmError::NotFound)
+}
+
+/// Removes `name`, whose frames are freed once no process maps them anymore.
+pub fn unlink(name: &str) -> Result<(), ShmError> {
+    let region = REGIONS.lock().remove(name).ok_or(ShmError::NotFound)?;
+    drop(region);
+    Ok(())
+}
+
+/// Maps all of `region` in the user address space of `process`, returning where.
+pub fn map(
+    process: &Process,
+    region: Arc<SharedRegion>,
+    writable: bool,
+) -> Result<VirtAddr, ShmError> {
+    let mut flags = PageTableFlags::NO_EXECUTE | mem::SHARED;
+    if writable {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    let mut space = process.space.lock();
+    let space = space.as_mut().ok_or(ShmError::Invalid)?;
+    let size = region.size() as u64;
+    let start = VirtAddr::new(process.reserve_mmap(size).ok_or(ShmError::OutOfMemory)?);
+    let mut mapper = space.mapper();
+    let first = Page::containing_address(start);
+    for (i, frame) in region.frames.iter().enumerate() {
+        let page = first + i as u64;
+        if user::map_user_frame(
+            &mut mapper,
+            &mut mem::frame_allocator(),
+            page,
+            *frame,
+            flags,
+        )
+        .is_err()
+        {
+            for mapped in Page::range(first, page) {
+                mapper.unmap(mapped).unwrap().1.flush();
+            }
+            process.unreserve_mmap(start.as_u64(), size);
+            return Err(ShmError::OutOfMemory);
+        }
+    }
+    process.shared.lock().push(SharedMapping { start, region });
+    Ok(start)
+}
+
+/// Unmaps the region mapped at `start` in `process`.
+pub fn unmap(process: &Process, start: VirtAddr) -> Result<(), ShmError> {
+    let mapping = {
+        let mut shared = process.shared.lock();
+        let index = shared
+            .iter()
+            .position(|m| m.start == start)
+            .ok_or(ShmError::NotMapped)?;
+        shared.swap_remove(index)
+    };
+    let mut space = process.space.lock();
+    if let Some(space) = space.as_mut() {
+        let mut mapper = space.mapper();
+        let first = Page::<Size4KiB>::containing_address(start);
+        for i in 0..mapping.region.frames.len() {
+            mapper.unmap(first + i as u64).unwrap().1.flush();
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,415 @@
+use core::mem::size_of;
+
+use x86_64::registers::rflags::RFlags;
+
+use crate::{
+    info,
+    ints::TrapFrame,
+    process::{self, ExitStatus, Process, INIT_PID},
+    syscall::{self, Errno},
+    task::{self, WaitQueue},
+    user, warn,
+};
+
+pub const SIGHUP: u8 = 1;
+pub const SIGINT: u8 = 2;
+pub const SIGQUIT: u8 = 3;
+pub const SIGILL: u8 = 4;
+pub const SIGTRAP: u8 = 5;
+pub const SIGABRT: u8 = 6;
+pub const SIGBUS: u8 = 7;
+pub const SIGFPE: u8 = 8;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+pub const SIGSEGV: u8 = 11;
+pub const SIGUSR2: u8 = 12;
+pub const SIGPIPE: u8 = 13;
+pub const SIGALRM: u8 = 14;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+pub const SIGCONT: u8 = 18;
+pub const SIGSTOP: u8 = 19;
+pub const SIGTSTP: u8 = 20;
+pub const SIGTTIN: u8 = 21;
+pub const SIGTTOU: u8 = 22;
+pub const SIGURG: u8 = 23;
+pub const SIGWINCH: u8 = 28;
+pub const SIGSYS: u8 = 31;
+
+pub const NSIG: usize = 64;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+/// The handler returns to `restorer`, which must call `rt_sigreturn`.
+pub const SA_RESTORER: u64 = 0x0400_0000;
+/// The signal isn't blocked while its handler runs.
+pub const SA_NODEFER: u64 = 0x4000_0000;
+/// The action goes back to [`SIG_DFL`] once the handler is called.
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+/// Signals that can't be caught, blocked or ignored.
+const UNBLOCKABLE: u64 = bit(SIGKILL) | bit(SIGSTOP);
+
+/// Flags a handler may change through its saved frame.
+const USER_RFLAGS: RFlags = RFlags::CARRY_FLAG
+    .union(RFlags::PARITY_FLAG)
+    .union(RFlags::AUXILIARY_CARRY_FLAG)
+    .union(RFlags::ZERO_FLAG)
+    .union(RFlags::SIGN_FLAG)
+    .union(RFlags::DIRECTION_FLAG)
+    .union(RFlags::OVERFLOW_FLAG);
+
+/// Space below the interrupted stack pointer left untouched, as the ABI allows leaf functions to
+/// use it.
+const RED_ZONE: u64 = 128;
+
+const fn bit(signal: u8) -> u64 {
+    1 << (signal - 1)
+}
+
+/// Same layout as the `struct sigaction` taken by the `rt_sigaction` system call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct SigAction {
+    pub handler: u64,
+    pub flags: u64,
+    pub restorer: u64,
+    pub mask: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminates, reporting the state of the program.
+    Core,
+    Ignore,
+}
+
+pub fn default_action(signal: u8) -> DefaultAction {
+    match signal {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGSYS => {
+            DefaultAction::Core
+        }
+        // processes can't be stopped, so job control signals do nothing
+        SIGCHLD | SIGCONT | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU | SIGURG | SIGWINCH => {
+            DefaultAction::Ignore
+        }
+        _ => DefaultAction::Terminate,
+    }
+}
+
+pub fn is_valid(signal: u64) -> bool {
+    (1..=NSIG as u64).contains(&signal)
+}
+
+/// Signal dispositions and masks of a process.
+pub struct SignalState {
+    pending: u64,
+    blocked: u64,
+    actions: [SigAction; NSIG],
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction {
+                handler: SIG_DFL,
+                flags: 0,
+                restorer: 0,
+                mask: 0,
+            }; NSIG],
+        }
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    pub fn blocked(&self) -> u64 {
+        self.blocked
+    }
+
+    /// Pending signals that aren't blocked.
+    pub fn deliverable(&self) -> u64 {
+        self.pending & !self.blocked
+    }
+
+    fn action(&self, signal: u8) -> &SigAction {
+        &self.actions[signal as usize - 1]
+    }
+
+    fn ignores(&self, signal: u8) -> bool {
+        match self.action(signal).handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(signal) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Pushed on the user stack when a handler is called, and read back by `rt_sigreturn`.
+#[repr(C)]
+struct SignalFrame {
+    /// Return address of the handler.
+    restorer: u64,
+    signal: u64,
+    blocked: u64,
+    context: TrapFrame,
+}
+
+/// Makes `signal` pending in the process `pid`. The signal 0 only checks that the process exists.
+pub fn send(pid: process::Pid, signal: u64) -> Result<(), Errno> {
+    if signal != 0 && !is_valid(signal) {
+        return Err(Errno::Inval);
+    }
+    let process = process::get(pid).ok_or(Errno::Srch)?;
+    if pid == INIT_PID {
+        return Err(Errno::Perm);
+    }
+    if signal == 0 || process.is_zombie() {
+        return Ok(());
+    }
+    let signal = signal as u8;
+    {
+        let mut state = process.signals.lock();
+        // ignored signals are discarded right away, so they don't interrupt anything
+        if state.ignores(signal) {
+            return Ok(());
+        }
+        state.pending |= bit(signal);
+    }
+    // blocked threads give up their wait to take it
+    for thread in process.threads.lock().iter() {
+        task::wake(thread);
+    }
+    Ok(())
+}
+
+/// Makes `signal` pending in the running process even if blocked or ignored, for faults.
+pub fn force(signal: u8) {
+    let process = process::current();
+    let mut state = process.signals.lock();
+    state.blocked &= !bit(signal);
+    if state.action(signal).handler == SIG_IGN {
+        state.actions[signal as usize - 1].handler = SIG_DFL;
+    }
+    state.pending |= bit(signal);
+}
+
+/// Changes the action of `signal` to `new` if given, returning the previous one.
+pub fn action(signal: u64, new: Option<SigAction>) -> Result<SigAction, Errno> {
+    if !is_valid(signal) {
+        return Err(Errno::Inval);
+    }
+    let signal = signal as u8;
+    if new.is_some() && bit(signal) & UNBLOCKABLE != 0 {
+        return Err(Errno::Inval);
+    }
+    let process = process::current();
+    let mut state = process.signals.lock();
+    let old = *state.action(signal);
+    if let Some(new) = new {
+        state.actions[signal as usize - 1] = new;
+        // pending signals that are now ignored are dropped
+        if state.ignores(signal) {
+            state.pending &= !bit(signal);
+        }
+    }
+    Ok(old)
+}
+
+/// Updates the blocked mask with `set` as `how` says, returning the previous mask.
+pub fn set_mask(how: u64, set: Option<u64>) -> Result<u64, Errno> {
+    let process = process::current();
+    let mut state = process.signals.lock();
+    let old = state.blocked;
+    if let Some(set) = set {
+        state.blocked = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(Errno::Inval),
+        } & !UNBLOCKABLE;
+    }
+    Ok(old)
+}
+
+fn interrupted(process: &Process) -> bool {
+    process.signals.lock().deliverable() != 0 || process.exit_pending().is_some()
+}
+
+/// Like [`WaitQueue::wait_until`], but gives up with [`Errno::Intr`] once a signal can be
+/// delivered to the running process.
+pub fn wait<T>(queue: &WaitQueue, mut cond: impl FnMut() -> Option<T>) -> Result<T, Errno> {
+    let process = process::current();
+    queue.wait_until(|| {
+        cond()
+            .map(Ok)
+            .or_else(|| interrupted(&process).then_some(Err(Errno::Intr)))
+    })
+}
+
+/// Like [`WaitQueue::wait_until_timeout`], but interrupted by signals as [`wait`].
+pub fn wait_timeout<T>(
+    queue: &WaitQueue,
+    timeout: u128,
+    mut cond: impl FnMut() -> Option<T>,
+) -> Result<Option<T>, Errno> {
+    let process = process::current();
+    queue
+        .wait_until_timeout(timeout, || {
+            cond()
+                .map(Ok)
+                .or_else(|| interrupted(&process).then_some(Err(Errno::Intr)))
+        })
+        .transpose()
+}
+
+/// Acts on the pending signals of the running process before `frame` returns to user mode,
+/// either redirecting it to a handler or ending the process.
+pub fn deliver(frame: &mut TrapFrame) {
+    let Some(pid) = task::try_current().map(|t| t.process) else {
+        return;
+    };
+    let Some(process) = process::get(pid) else {
+        return;
+    };
+    // another thread ended the process
+    if let Some(status) = process.exit_pending() {
+        drop(process);
+        process::exit_with(status);
+    }
+    let fatal = handle_pending(&process, frame);
+    // nothing can be left owned on this stack once the process ends
+    drop(process);
+    if let Some((signal, core)) = fatal {
+        terminate(frame, signal, core);
+    }
+}
+
+/// Returns the signal ending the process and whether it dumps core, if any.
+fn handle_pending(process: &Process, frame: &mut TrapFrame) -> Option<(u8, bool)> {
+    loop {
+        let (signal, action, blocked) = {
+            let mut state = process.signals.lock();
+            let ready = state.deliverable();
+            if ready == 0 {
+                return None;
+            }
+            let signal = ready.trailing_zeros() as u8 + 1;
+            state.pending &= !bit(signal);
+            (signal, *state.action(signal), state.blocked)
+        };
+        match action.handler {
+            SIG_IGN => continue,
+            SIG_DFL => match default_action(signal) {
+                DefaultAction::Ignore => continue,
+                DefaultAction::Terminate => return Some((signal, false)),
+                DefaultAction::Core => return Some((signal, true)),
+            },
+            _ => {
+                if push_frame(frame, signal, &action, blocked).is_err() {
+                    return Some((SIGSEGV, true));
+                }
+                let mut state = process.signals.lock();
+                state.blocked |= action.mask & !UNBLOCKABLE;
+                if action.flags & SA_NODEFER == 0 {
+                    state.blocked |= bit(signal);
+                }
+                if action.flags & SA_RESETHAND != 0 {
+                    state.actions[signal as usize - 1].handler = SIG_DFL;
+                }
+                return None;
+            }
+        }
+    }
+}
+
+/// Saves `frame` on the user stack and points it to the handler of `signal`.
+fn push_frame(
+    frame: &mut TrapFrame,
+    signal: u8,
+    action: &SigAction,
+    blocked: u64,
+) -> Result<(), Errno> {
+    if action.handler >= user::USER_END {
+        return Err(Errno::Fault);
+    }
+    let size = size_of::<SignalFrame>() as u64;
+    // the handler starts as if called, with the stack 8 bytes off a 16 bytes boundary
+    let sp = frame.rsp.checked_sub(RED_ZONE + size).ok_or(Errno::Fault)? & !0xf;
+    let sp = sp.checked_sub(8).ok_or(Errno::Fault)?;
+    syscall::validate_user(sp, size, true)?;
+    let restorer = if action.flags & SA_RESTORER != 0 {
+        action.restorer
+    } else {
+        0
+    };
+    let signal_frame = SignalFrame {
+        restorer,
+        signal: signal as u64,
+        blocked,
+        context: *frame,
+    };
+    unsafe { (sp as *mut SignalFrame).write(signal_frame) };
+
+    frame.rip = action.handler;
+    frame.rsp = sp;
+    frame.rdi = signal as u64;
+    frame.rsi = 0;
+    frame.rdx = sp + core::mem::offset_of!(SignalFrame, context) as u64;
+    frame.rax = 0;
+    frame.rflags &= !(RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG).bits();
+    Ok(())
+}
+
+/// Restores the state saved before calling a handler, which just returned to its restorer.
+pub fn sigreturn(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    // the handler's `ret` popped the restorer address
+    let sp = frame.rsp.wrapping_sub(8);
+    let size = size_of::<SignalFrame>() as u64;
+    if syscall::validate_user(sp, size, false).is_err() || sp % 8 != 0 {
+        terminate(frame, SIGSEGV, true);
+    }
+    let saved = unsafe { (sp as *const SignalFrame).read() };
+    let context = saved.context;
+    if context.rip >= user::USER_END {
+        terminate(frame, SIGSEGV, true);
+    }
+
+    let (cs, ss, rflags) = (frame.cs, frame.ss, frame.rflags);
+    *frame = context;
+    frame.cs = cs;
+    frame.ss = ss;
+    frame.rflags = (rflags & !USER_RFLAGS.bits()) | (context.rflags & USER_RFLAGS.bits());
+    process::current().signals.lock().blocked = saved.blocked & !UNBLOCKABLE;
+    Ok(context.rax)
+}
+
+fn terminate(frame: &TrapFrame, signal: u8, core: bool) -> ! {
+    let pid = task::current().process;
+    if core {
+        warn!("pid {pid} killed by signal {signal} (core dumped)");
+        info!("\tregisters: {frame:#x?}");
+    } else {
+        info!("pid {pid} killed by signal {signal}");
+    }
+    let status = ExitStatus::Signaled { signal, core };
+    if user::is_running() {
+        unsafe { user::return_to_kernel(status.code() as u64) }
+    }
+    process::exit_with(status)
+}
@@ -0,0 +1,831 @@
+use core::{arch::global_asm, mem::size_of};
+
+use x86_64::{
+    instructions::interrupts,
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    structures::paging::{FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+use crate::{
+    channel::{self, Endpoint},
+    gdt, info, ints,
+    ints::{pop_regs, push_regs, swapgs_if_user, TrapFrame},
+    mem, okay, percpu, pipe, process, shm, signal,
+    signal::SigAction,
+    task::WaitQueue,
+    user,
+    vfs::{self, Metadata, SeekFrom},
+    warn,
+};
+
+pub const INT_VECTOR: u8 = 0x80;
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_STAT: u64 = 4;
+pub const SYS_FSTAT: u64 = 5;
+pub const SYS_LSTAT: u64 = 6;
+pub const SYS_LSEEK: u64 = 8;
+pub const SYS_MMAP: u64 = 9;
+pub const SYS_RT_SIGACTION: u64 = 13;
+pub const SYS_RT_SIGPROCMASK: u64 = 14;
+pub const SYS_RT_SIGRETURN: u64 = 15;
+pub const SYS_PIPE: u64 = 22;
+pub const SYS_DUP: u64 = 32;
+pub const SYS_DUP2: u64 = 33;
+pub const SYS_SLEEP: u64 = 35;
+pub const SYS_GETPID: u64 = 39;
+pub const SYS_EXIT: u64 = 60;
+pub const SYS_WAIT4: u64 = 61;
+pub const SYS_KILL: u64 = 62;
+pub const SYS_GETCWD: u64 = 79;
+pub const SYS_CHDIR: u64 = 80;
+pub const SYS_RENAME: u64 = 82;
+pub const SYS_MKDIR: u64 = 83;
+pub const SYS_RMDIR: u64 = 84;
+pub const SYS_UNLINK: u64 = 87;
+pub const SYS_SYMLINK: u64 = 88;
+pub const SYS_READLINK: u64 = 89;
+pub const SYS_CHMOD: u64 = 90;
+pub const SYS_GETPPID: u64 = 110;
+pub const SYS_GETDENTS64: u64 = 217;
+pub const SYS_CHANNEL: u64 = 500;
+pub const SYS_CHANNEL_SEND: u64 = 501;
+pub const SYS_CHANNEL_RECEIVE: u64 = 502;
+pub const SYS_HANDLE_CLOSE: u64 = 503;
+pub const SYS_SHM_CREATE: u64 = 504;
+pub const SYS_SHM_MAP: u64 = 505;
+pub const SYS_SHM_UNMAP: u64 = 506;
+pub const SYS_SHM_UNLINK: u64 = 507;
+
+pub type Handler = fn(&mut TrapFrame) -> Result<u64, Errno>;
+
+/// Numbered system calls. Arguments are passed in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`, the
+/// number in `rax`, and the result comes back in `rax` (negative values are errors).
+pub const SYSCALLS: &[(u64, &str, Handler)] = &[
+    (SYS_READ, "read", sys_read),
+    (SYS_WRITE, "write", sys_write),
+    (SYS_OPEN, "open", sys_open),
+    (SYS_CLOSE, "close", sys_close),
+    (SYS_STAT, "stat", sys_stat),
+    (SYS_FSTAT, "fstat", sys_fstat),
+    (SYS_LSTAT, "lstat", sys_lstat),
+    (SYS_LSEEK, "lseek", sys_lseek),
+    (SYS_MMAP, "mmap", sys_mmap),
+    (SYS_RT_SIGACTION, "rt_sigaction", sys_rt_sigaction),
+    (SYS_RT_SIGPROCMASK, "rt_sigprocmask", sys_rt_sigprocmask),
+    (SYS_RT_SIGRETURN, "rt_sigreturn", sys_rt_sigreturn),
+    (SYS_PIPE, "pipe", sys_pipe),
+    (SYS_DUP, "dup", sys_dup),
+    (SYS_DUP2, "dup2", sys_dup2),
+    (SYS_SLEEP, "sleep", sys_sleep),
+    (SYS_GETPID, "getpid", sys_getpid),
+    (SYS_EXIT, "exit", sys_exit),
+    (SYS_WAIT4, "wait4", sys_wait4),
+    (SYS_KILL, "kill", sys_kill),
+    (SYS_GETCWD, "getcwd", sys_getcwd),
+    (SYS_CHDIR, "chdir", sys_chdir),
+    (SYS_RENAME, "rename", sys_rename),
+    (SYS_MKDIR, "mkdir", sys_mkdir),
+    (SYS_RMDIR, "rmdir", sys_rmdir),
+    (SYS_UNLINK, "unlink", sys_unlink),
+    (SYS_SYMLINK, "symlink", sys_symlink),
+    (SYS_READLINK, "readlink", sys_readlink),
+    (SYS_CHMOD, "chmod", sys_chmod),
+    (SYS_GETPPID, "getppid", sys_getppid),
+    (SYS_GETDENTS64, "getdents64", sys_getdents64),
+    (SYS_CHANNEL, "channel", sys_channel),
+    (SYS_CHANNEL_SEND, "channel_send", sys_channel_send),
+    (SYS_CHANNEL_RECEIVE, "channel_receive", sys_channel_receive),
+    (SYS_HANDLE_CLOSE, "handle_close", sys_handle_close),
+    (SYS_SHM_CREATE, "shm_create", sys_shm_create),
+    (SYS_SHM_MAP, "shm_map", sys_shm_map),
+    (SYS_SHM_UNMAP, "shm_unmap", sys_shm_unmap),
+    (SYS_SHM_UNLINK, "shm_unlink", sys_shm_unlink),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Errno {
+    Perm = 1,
+    NoEnt = 2,
+    Srch = 3,
+    Intr = 4,
+    Io = 5,
+    BadFd = 9,
+    Child = 10,
+    Again = 11,
+    NoMem = 12,
+    Fault = 14,
+    Busy = 16,
+    Exist = 17,
+    XDev = 18,
+    NotDir = 20,
+    IsDir = 21,
+    Inval = 22,
+    MFile = 24,
+    NoSpc = 28,
+    SPipe = 29,
+    RoFs = 30,
+    Pipe = 32,
+    Range = 34,
+    NameTooLong = 36,
+    NoSys = 38,
+    NotEmpty = 39,
+    Loop = 40,
+    MsgSize = 90,
+    TimedOut = 110,
+}
+
+impl Errno {
+    pub fn into_ret(self) -> u64 {
+        (-(self as i64)) as u64
+    }
+}
+
+extern "C" {
+    fn syscall_int_entry();
+    fn syscall_entry();
+}
+
+global_asm!(
+    ".global syscall_int_entry",
+    "syscall_int_entry:",
+    "push 0",
+    swapgs_if_user!(),
+    push_regs!(),
+    "mov rdi, rsp",
+    "mov rbp, rsp",
+    "and rsp, -16",
+    "call {handler}",
+    "mov rsp, rbp",
+    pop_regs!(),
+    swapgs_if_user!(),
+    "add rsp, 8",
+    "iretq",
+    "",
+    // `syscall` leaves the user stack in place and the return address in rcx and the flags in
+    // r11, so an interrupt-like frame is built by hand on the kernel stack
+    ".global syscall_entry",
+    "syscall_entry:",
+    "swapgs",
+    "mov gs:[{user_rsp}], rsp",
+    "mov rsp, gs:[{kernel_rsp}]",
+    "push {user_ss}",
+    "push qword ptr gs:[{user_rsp}]",
+    "push r11",
+    "push {user_cs}",
+    "push rcx",
+    "push 0",
+    push_regs!(),
+    "mov rdi, rsp",
+    "mov rbp, rsp",
+    "and rsp, -16",
+    "call {handler}",
+    "mov rsp, rbp",
+    // `sysret` clobbers rcx and r11, so a frame rewritten by a signal goes back with `iretq`
+    "test rax, rax",
+    "jnz 2f",
+    pop_regs!(),
+    "add rsp, 8",
+    "pop rcx",
+    "add rsp, 8",
+    "pop r11",
+    "pop rsp",
+    "swapgs",
+    "sysretq",
+    "2:",
+    pop_regs!(),
+    "add rsp, 8",
+    "swapgs",
+    "iretq",
+    handler = sym syscall_handler,
+    user_rsp = const percpu::USER_RSP_OFFSET,
+    kernel_rsp = const percpu::KERNEL_RSP_OFFSET,
+    user_ss = const USER_SS,
+    user_cs = const USER_CS,
+);
+
+// Must match the layout built in `gdt`, checked in `init`.
+const USER_SS: u16 = 0x23;
+const USER_CS: u16 = 0x2b;
+
+pub fn init() {
+    info!("setting up syscalls");
+    let selectors = gdt::selectors();
+    assert_eq!(selectors.user_data().0, USER_SS);
+    assert_eq!(selectors.user_code().0, USER_CS);
+    Star::write(
+        selectors.user_code(),
+        selectors.user_data(),
+        selectors.kernel_code(),
+        selectors.kernel_data(),
+    )
+    .unwrap();
+    LStar::write(VirtAddr::from_ptr(syscall_entry as *const ()));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG);
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+    okay!("set up syscalls");
+}
+
+pub fn int_entry_addr() -> VirtAddr {
+    VirtAddr::from_ptr(syscall_int_entry as *const ())
+}
+
+/// Returns whether every register must be restored, instead of the fast `sysret` path.
+extern "C" fn syscall_handler(frame: &mut TrapFrame) -> u64 {
+    // `sysret` faults in ring 0 with a non canonical rip, so never go back to kernel addresses
+    if frame.rip >= user::USER_END {
+        warn!("syscall returning to {:#x}, exiting", frame.rip);
+        exit(u64::MAX);
+    }
+    interrupts::enable();
+    let rip = frame.rip;
+    let number = frame.rax;
+    frame.rax = match dispatch(frame) {
+        Ok(ret) => ret,
+        Err(errno) => errno.into_ret(),
+    };
+    interrupts::disable();
+    signal::deliver(frame);
+    (number == SYS_RT_SIGRETURN || frame.rip != rip) as u64
+}
+
+pub fn dispatch(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let number = frame.rax;
+    let (_, _, handler) = SYSCALLS
+        .iter()
+        .find(|(n, _, _)| *n == number)
+        .ok_or(Errno::NoSys)?;
+    handler(frame)
+}
+
+/// Checks that `[ptr, ptr + len)` lies in user space and is mapped for the caller, writable if
+/// `write` is set.
+pub fn validate_user(ptr: u64, len: u64, write: bool) -> Result<(), Errno> {
+    let end = ptr.checked_add(len).ok_or(Errno::Fault)?;
+    if end > user::USER_END {
+        return Err(Errno::Fault);
+    }
+    if len == 0 {
+        return Ok(());
+    }
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(ptr));
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        let (_, flags) = mem::translate_active(page.start_address()).ok_or(Errno::Fault)?;
+        if !flags.contains(PageTableFlags::USER_ACCESSIBLE)
+            || (write && !flags.contains(PageTableFlags::WRITABLE))
+        {
+            return Err(Errno::Fault);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a `T` from user memory.
+pub fn read_user<T: Copy>(ptr: u64) -> Result<T, Errno> {
+    validate_user(ptr, size_of::<T>() as u64, false)?;
+    Ok(unsafe { (ptr as *const T).read_unaligned() })
+}
+
+pub fn write_user<T: Copy>(ptr: u64, value: T) -> Result<(), Errno> {
+    validate_user(ptr, size_of::<T>() as u64, true)?;
+    unsafe { (ptr as *mut T).write_unaligned(value) };
+    Ok(())
+}
+
+pub fn user_slice<'a>(ptr: u64, len: u64) -> Result<&'a [u8], Errno> {
+    validate_user(ptr, len, false)?;
+    Ok(unsafe { core::slice::from_raw_parts(ptr as *const u8, len as usize) })
+}
+
+pub fn user_slice_mut<'a>(ptr: u64, len: u64) -> Result<&'a mut [u8], Errno> {
+    validate_user(ptr, len, true)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len as usize) })
+}
+
+fn sys_read(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fd, buf, len, ..] = frame.args();
+    let file = process::current().files.lock().get(fd as usize)?;
+    let buf = user_slice_mut(buf, len)?;
+    Ok(file.read(buf)? as u64)
+}
+
+fn sys_write(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fd, buf, len, ..] = frame.args();
+    let file = process::current().files.lock().get(fd as usize)?;
+    let buf = user_slice(buf, len)?;
+    Ok(file.write(buf)? as u64)
+}
+
+fn sys_close(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fd, ..] = frame.args();
+    process::current().files.lock().close(fd as usize)?;
+    Ok(0)
+}
+
+fn sys_pipe(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fds, ..] = frame.args();
+    validate_user(fds, 8, true)?;
+    let (reader, writer) = pipe::pipe();
+    let process = process::current();
+    let mut files = process.files.lock();
+    let read_fd = files.insert(reader)?;
+    let write_fd = match files.insert(writer) {
+        Ok(fd) => fd,
+        Err(err) => {
+            files.close(read_fd)?;
+            return Err(err);
+        }
+    };
+    drop(files);
+    write_user(fds, [read_fd as u32, write_fd as u32])?;
+    Ok(0)
+}
+
+fn sys_dup(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fd, ..] = frame.args();
+    Ok(process::current().files.lock().dup(fd as usize)? as u64)
+}
+
+fn sys_dup2(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [old, new, ..] = frame.args();
+    Ok(process::current()
+        .files
+        .lock()
+        .dup2(old as usize, new as usize)? as u64)
+}
+
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+fn sys_mmap(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [addr, len, prot, ..] = frame.args();
+    if len == 0 {
+        return Err(Errno::Inval);
+    }
+    let len = len
+        .checked_add(mem::PAGE_SIZE as u64 - 1)
+        .ok_or(Errno::Inval)?
+        & !(mem::PAGE_SIZE as u64 - 1);
+    let reserved = addr == 0;
+    let addr = if reserved {
+        process::current().reserve_mmap(len).ok_or(Errno::NoMem)?
+    } else {
+        addr
+    };
+    let result = map_anonymous(addr, len, prot);
+    if result.is_err() && reserved {
+        process::current().unreserve_mmap(addr, len);
+    }
+    result
+}
+
+/// Maps `len` bytes of zeroed memory at `addr`, leaving nothing mapped if any page fails.
+fn map_anonymous(addr: u64, len: u64, prot: u64) -> Result<u64, Errno> {
+    if addr % mem::PAGE_SIZE as u64 != 0 {
+        return Err(Errno::Inval);
+    }
+    let end = addr.checked_add(len).ok_or(Errno::Inval)?;
+    if end > user::USER_END {
+        return Err(Errno::Inval);
+    }
+
+    let mut flags = PageTableFlags::empty();
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let mut mapper = mem::active_mapper();
+    let first = Page::containing_address(VirtAddr::new(addr));
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        match user::map_user_page(&mut mapper, &mut mem::frame_allocator(), page, flags) {
+            // zeroed through the physical memory mapping, as the page might not be writable
+            Ok(frame) => mem::zero_frame(frame),
+            Err(_) => {
+                for mapped in Page::range(first, page) {
+                    let (frame, flush) = mapper.unmap(mapped).unwrap();
+                    flush.flush();
+                    unsafe { mem::frame_allocator().deallocate_frame(frame) };
+                }
+                return Err(Errno::NoMem);
+            }
+        }
+    }
+    Ok(addr)
+}
+
+fn sys_sleep(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [ms, ..] = frame.args();
+    // nothing notifies the queue, so only the timeout or a signal ends the wait
+    signal::wait_timeout(&WaitQueue::new(), ints::ms_to_ticks(ms), || None::<()>)?;
+    Ok(0)
+}
+
+fn sys_rt_sigaction(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [signal, act, old_act, set_size, ..] = frame.args();
+    if set_size != size_of::<u64>() as u64 {
+        return Err(Errno::Inval);
+    }
+    let new = if act != 0 {
+        Some(read_user::<SigAction>(act)?)
+    } else {
+        None
+    };
+    if old_act != 0 {
+        validate_user(old_act, size_of::<SigAction>() as u64, true)?;
+    }
+    let old = signal::action(signal, new)?;
+    if old_act != 0 {
+        write_user(old_act, old)?;
+    }
+    Ok(0)
+}
+
+fn sys_rt_sigprocmask(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [how, set, old_set, set_size, ..] = frame.args();
+    if set_size != size_of::<u64>() as u64 {
+        return Err(Errno::Inval);
+    }
+    let set = if set != 0 {
+        Some(read_user::<u64>(set)?)
+    } else {
+        None
+    };
+    if old_set != 0 {
+        validate_user(old_set, size_of::<u64>() as u64, true)?;
+    }
+    let old = signal::set_mask(how, set)?;
+    if old_set != 0 {
+        write_user(old_set, old)?;
+    }
+    Ok(0)
+}
+
+fn sys_rt_sigreturn(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    signal::sigreturn(frame)
+}
+
+fn sys_kill(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [pid, signal, ..] = frame.args();
+    // process groups aren't supported
+    if pid as i64 <= 0 {
+        return Err(Errno::Inval);
+    }
+    signal::send(pid, signal)?;
+    Ok(0)
+}
+
+fn sys_getpid(_frame: &mut TrapFrame) -> Result<u64, Errno> {
+    Ok(process::current().pid())
+}
+
+fn sys_getppid(_frame: &mut TrapFrame) -> Result<u64, Errno> {
+    Ok(process::current().parent())
+}
+
+fn sys_wait4(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [pid, status, ..] = frame.args();
+    let pid = match pid as i64 {
+        -1 => None,
+        pid if pid > 0 => Some(pid as u64),
+        _ => return Err(Errno::Inval),
+    };
+    if status != 0 {
+        validate_user(status, 4, true)?;
+    }
+    let (pid, exit) = process::wait(pid).map_err(|err| match err {
+        process::WaitError::NoChild => Errno::Child,
+        process::WaitError::Interrupted => Errno::Intr,
+    })?;
+    if status != 0 {
+        write_user(status, exit.wait_status())?;
+    }
+    Ok(pid)
+}
+
+fn sys_exit(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    exit(frame.rdi)
+}
+
+fn exit(code: u64) -> ! {
+    if user::is_running() {
+        unsafe { user::return_to_kernel(code) }
+    }
+    process::exit(code as i32)
+}
+
+/// Timeouts of channel calls are in milliseconds, with `u64::MAX` to wait forever.
+const NO_TIMEOUT: u64 = u64::MAX;
+
+fn sys_channel(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [capacity, handles, ..] = frame.args();
+    if capacity == 0 || capacity > channel::MAX_USER_CAPACITY as u64 {
+        return Err(Errno::Inval);
+    }
+    validate_user(handles, 8, true)?;
+    let (sender, receiver) = channel::channel(capacity as usize);
+    let process = process::current();
+    let mut table = process.handles.lock();
+    let send_handle = table.insert(Endpoint::Sender(sender))?;
+    let receive_handle = match table.insert(Endpoint::Receiver(receiver)) {
+        Ok(handle) => handle,
+        Err(err) => {
+            table.close(send_handle)?;
+            return Err(err);
+        }
+    };
+    drop(table);
+    write_user(handles, [send_handle as u32, receive_handle as u32])?;
+    Ok(0)
+}
+
+fn sys_channel_send(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [handle, buf, len, timeout, ..] = frame.args();
+    if len > channel::MAX_MESSAGE_SIZE as u64 {
+        return Err(Errno::MsgSize);
+    }
+    let Endpoint::Sender(sender) = process::current().handles.lock().get(handle as usize)? else {
+        return Err(Errno::BadFd);
+    };
+    let message = user_slice(buf, len)?.to_vec();
+    match timeout {
+        0 => sender.try_send(message),
+        NO_TIMEOUT => sender.send(message),
+        ms => sender.send_timeout(message, ints::ms_to_ticks(ms)),
+    }
+    .map_err(|err| err.error)?;
+    Ok(0)
+}
+
+/// Returns the length of the message, which is truncated if longer than the buffer.
+fn sys_channel_receive(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [handle, buf, len, timeout, ..] = frame.args();
+    let Endpoint::Receiver(receiver) = process::current().handles.lock().get(handle as usize)?
+    else {
+        return Err(Errno::BadFd);
+    };
+    validate_user(buf, len, true)?;
+    let message = match timeout {
+        0 => receiver.try_receive(),
+        NO_TIMEOUT => receiver.receive(),
+        ms => receiver.receive_timeout(ints::ms_to_ticks(ms)),
+    }?;
+    let copied = message.len().min(len as usize);
+    user_slice_mut(buf, copied as u64)?.copy_from_slice(&message[..copied]);
+    Ok(message.len() as u64)
+}
+
+fn sys_handle_close(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [handle, ..] = frame.args();
+    process::current().handles.lock().close(handle as usize)?;
+    Ok(0)
+}
+
+impl From<shm::ShmError> for Errno {
+    fn from(value: shm::ShmError) -> Self {
+        match value {
+            shm::ShmError::AlreadyExists => Errno::Exist,
+            shm::ShmError::NotFound => Errno::NoEnt,
+            shm::ShmError::Invalid | shm::ShmError::NotMapped => Errno::Inval,
+            shm::ShmError::OutOfMemory => Errno::NoMem,
+        }
+    }
+}
+
+/// Reads a UTF-8 string of `len` bytes from user memory.
+pub fn user_str<'a>(ptr: u64, len: u64) -> Result<&'a str, Errno> {
+    core::str::from_utf8(user_slice(ptr, len)?).map_err(|_| Errno::Inval)
+}
+
+fn sys_shm_create(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [name, name_len, size, ..] = frame.args();
+    shm::create(user_str(name, name_len)?, size as usize)?;
+    Ok(0)
+}
+
+fn sys_shm_map(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [name, name_len, prot, ..] = frame.args();
+    let region = shm::open(user_str(name, name_len)?)?;
+    let start = shm::map(&process::current(), region, prot & PROT_WRITE != 0)?;
+    Ok(start.as_u64())
+}
+
+fn sys_shm_unmap(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [addr, ..] = frame.args();
+    if addr >= user::USER_END {
+        return Err(Errno::Inval);
+    }
+    shm::unmap(&process::current(), VirtAddr::new(addr))?;
+    Ok(0)
+}
+
+fn sys_shm_unlink(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [name, name_len, ..] = frame.args();
+    shm::unlink(user_str(name, name_len)?)?;
+    Ok(0)
+}
+
+/// Reads a NUL terminated UTF-8 string of at most [`vfs::PATH_MAX`] bytes from user memory.
+pub fn user_cstr<'a>(ptr: u64) -> Result<&'a str, Errno> {
+    let mut len = 0;
+    loop {
+        // checks the rest of the page at once, as the next one may not be mapped
+        let start = ptr.checked_add(len).ok_or(Errno::Fault)?;
+        let chunk_end = start
+            .checked_add(1)
+            .and_then(|end| end.checked_next_multiple_of(mem::PAGE_SIZE as u64))
+            .ok_or(Errno::Fault)?;
+        let chunk = user_slice(start, chunk_end - start)?;
+        if let Some(nul) = chunk.iter().position(|&b| b == 0) {
+            return user_str(ptr, len + nul as u64);
+        }
+        len += chunk.len() as u64;
+        if len > vfs::PATH_MAX as u64 {
+            return Err(Errno::NameTooLong);
+        }
+    }
+}
+
+fn sys_open(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, flags, mode, ..] = frame.args();
+    let file = vfs::open(user_cstr(path)?, flags as u32, mode as u16)?;
+    Ok(process::current().files.lock().insert(file)? as u64)
+}
+
+/// `struct stat` as laid out on x86_64 Linux.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct Stat {
+    dev: u64,
+    ino: u64,
+    nlink: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    _pad: u32,
+    rdev: u64,
+    size: i64,
+    blksize: i64,
+    blocks: i64,
+    atime: u64,
+    atime_nsec: u64,
+    mtime: u64,
+    mtime_nsec: u64,
+    ctime: u64,
+    ctime_nsec: u64,
+    _unused: [i64; 3],
+}
+
+fn write_stat(ptr: u64, metadata: Metadata) -> Result<u64, Errno> {
+    let stat = Stat {
+        ino: metadata.ino,
+        nlink: metadata.nlink as u64,
+        mode: metadata.kind.mode_bits() | metadata.mode as u32,
+        uid: metadata.uid,
+        gid: metadata.gid,
+        size: metadata.size as i64,
+        blksize: mem::PAGE_SIZE as i64,
+        blocks: metadata.size.div_ceil(512) as i64,
+        atime: metadata.atime,
+        mtime: metadata.mtime,
+        ctime: metadata.ctime,
+        ..Default::default()
+    };
+    write_user(ptr, stat)?;
+    Ok(0)
+}
+
+fn sys_stat(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, stat, ..] = frame.args();
+    write_stat(stat, vfs::stat(user_cstr(path)?)?)
+}
+
+fn sys_lstat(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, stat, ..] = frame.args();
+    write_stat(stat, vfs::lstat(user_cstr(path)?)?)
+}
+
+fn sys_fstat(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fd, stat, ..] = frame.args();
+    let file = process::current().files.lock().get(fd as usize)?;
+    write_stat(stat, file.stat()?)
+}
+
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+fn sys_lseek(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fd, offset, whence, ..] = frame.args();
+    let pos = match whence {
+        SEEK_SET => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::Inval),
+    };
+    let file = process::current().files.lock().get(fd as usize)?;
+    file.seek(pos)
+}
+
+fn sys_getdents64(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [fd, buf, len, ..] = frame.args();
+    let file = process::current().files.lock().get(fd as usize)?;
+    let file = file.as_vfs_file().ok_or(Errno::NotDir)?;
+    let buf = user_slice_mut(buf, len)?;
+    let mut used = 0;
+    // each record is a `struct linux_dirent64`
+    let stopped = file.read_entries(|index, entry| {
+        let reclen = (19 + entry.name.len() + 1).next_multiple_of(8);
+        if used + reclen > buf.len() {
+            return false;
+        }
+        let record = &mut buf[used..used + reclen];
+        record.fill(0);
+        record[0..8].copy_from_slice(&entry.ino.to_ne_bytes());
+        record[8..16].copy_from_slice(&(index as i64 + 1).to_ne_bytes());
+        record[16..18].copy_from_slice(&(reclen as u16).to_ne_bytes());
+        record[18] = entry.kind.dirent_type();
+        record[19..19 + entry.name.len()].copy_from_slice(entry.name.as_bytes());
+        used += reclen;
+        true
+    })?;
+    // not even one entry fitted
+    if stopped && used == 0 {
+        return Err(Errno::Inval);
+    }
+    Ok(used as u64)
+}
+
+fn sys_getcwd(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [buf, size, ..] = frame.args();
+    let cwd = process::current().cwd();
+    if cwd.len() + 1 > size as usize {
+        return Err(Errno::Range);
+    }
+    let buf = user_slice_mut(buf, cwd.len() as u64 + 1)?;
+    buf[..cwd.len()].copy_from_slice(cwd.as_bytes());
+    buf[cwd.len()] = 0;
+    Ok(buf.len() as u64)
+}
+
+fn sys_chdir(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, ..] = frame.args();
+    vfs::chdir(user_cstr(path)?)?;
+    Ok(0)
+}
+
+fn sys_rename(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [old, new, ..] = frame.args();
+    vfs::rename(user_cstr(old)?, user_cstr(new)?)?;
+    Ok(0)
+}
+
+fn sys_mkdir(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, mode, ..] = frame.args();
+    vfs::mkdir(user_cstr(path)?, mode as u16)?;
+    Ok(0)
+}
+
+fn sys_rmdir(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, ..] = frame.args();
+    vfs::rmdir(user_cstr(path)?)?;
+    Ok(0)
+}
+
+fn sys_unlink(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, ..] = frame.args();
+    vfs::unlink(user_cstr(path)?)?;
+    Ok(0)
+}
+
+fn sys_symlink(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [target, path, ..] = frame.args();
+    vfs::symlink(user_cstr(target)?, user_cstr(path)?)?;
+    Ok(0)
+}
+
+fn sys_readlink(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, buf, size, ..] = frame.args();
+    let target = vfs::read_link(user_cstr(path)?)?;
+    // truncated without a NUL, like Linux
+    let len = target.len().min(size as usize);
+    user_slice_mut(buf, len as u64)?.copy_from_slice(&target.as_bytes()[..len]);
+    Ok(len as u64)
+}
+
+fn sys_chmod(frame: &mut TrapFrame) -> Result<u64, Errno> {
+    let [path, mode, ..] = frame.args();
+    vfs::chmod(user_cstr(path)?, mode as u16)?;
+    Ok(0)
+}
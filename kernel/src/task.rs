@@ -0,0 +1,453 @@
+use core::{
+    arch::global_asm,
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec, vec::Vec};
+use x86_64::{
+    instructions::interrupts,
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::PhysFrame,
+    VirtAddr,
+};
+
+use crate::{gdt, info, ints, okay, percpu, process::Pid};
+
+pub type Tid = u64;
+
+pub const KERNEL_STACK_SIZE: usize = 4096 * 8;
+/// Ticks a thread runs before being preempted.
+pub const TIME_SLICE: u128 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    Blocked,
+    Dead,
+}
+
+pub struct Thread {
+    id: Tid,
+    state: AtomicU8,
+    /// Stack pointer saved by `switch_asm` while the thread isn't running.
+    rsp: UnsafeCell<u64>,
+    /// Page tables the thread runs with, saved on every switch.
+    cr3: UnsafeCell<(PhysFrame, Cr3Flags)>,
+    /// Stack loaded on traps from ring 3.
+    kernel_stack_top: AtomicU64,
+    /// Kernel stack pointer to go back to when the user code started by `user::run` exits.
+    pub user_return: AtomicUsize,
+    pub process: Pid,
+    _stack: Option<Box<[u8]>>,
+}
+
+// The cells are only touched by the scheduler, with interrupts disabled.
+unsafe impl Send for Thread {}
+unsafe impl Sync for Thread {}
+
+impl Thread {
+    pub fn id(&self) -> Tid {
+        self.id
+    }
+
+    pub fn state(&self) -> ThreadState {
+        match self.state.load(Ordering::Acquire) {
+            0 => ThreadState::Ready,
+            1 => ThreadState::Running,
+            2 => ThreadState::Blocked,
+            _ => ThreadState::Dead,
+        }
+    }
+
+    fn set_state(&self, state: ThreadState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn kernel_stack_top(&self) -> VirtAddr {
+        VirtAddr::new(self.kernel_stack_top.load(Ordering::Relaxed))
+    }
+}
+
+struct Scheduler {
+    ready: VecDeque<Arc<Thread>>,
+    sleeping: Vec<(u128, Arc<Thread>)>,
+    idle: Option<Arc<Thread>>,
+    /// Thread that exited and whose stack can be freed once switched away from.
+    dead: Option<Arc<Thread>>,
+    slice_start: u128,
+}
+
+static SCHEDULER: spin::Mutex<Scheduler> = spin::Mutex::new(Scheduler {
+    ready: VecDeque::new(),
+    sleeping: Vec::new(),
+    idle: None,
+    dead: None,
+    slice_start: 0,
+});
+
+static NEXT_TID: AtomicU64 = AtomicU64::new(0);
+
+extern "C" {
+    fn switch_asm(old_rsp: *mut u64, new_rsp: u64);
+    fn thread_trampoline();
+}
+
+global_asm!(
+    ".global switch_asm",
+    "switch_asm:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    "",
+    // new threads are switched to with the entry in r12 and its argument in r13
+    ".global thread_trampoline",
+    "thread_trampoline:",
+    "mov rdi, r12",
+    "mov rsi, r13",
+    "call {start}",
+    "ud2",
+    start = sym thread_start,
+);
+
+/// Turns the running code into the first thread, owned by `process`, and creates the idle thread.
+pub fn init(process: Pid) {
+    info!("initializing scheduler");
+    let boot = Arc::new(Thread {
+        id: NEXT_TID.fetch_add(1, Ordering::Relaxed),
+        state: AtomicU8::new(ThreadState::Running as u8),
+        rsp: UnsafeCell::new(0),
+        cr3: UnsafeCell::new(Cr3::read()),
+        kernel_stack_top: AtomicU64::new(gdt::kernel_stack().as_u64()),
+        user_return: AtomicUsize::new(0),
+        process,
+        _stack: None,
+    });
+    percpu::CURRENT_THREAD
+        .get()
+        .store(Arc::into_raw(boot) as *mut (), Ordering::Release);
+
+    let idle = new_thread(idle_main, 0, process, None);
+    SCHEDULER.lock().idle = Some(idle);
+    okay!("initialized scheduler");
+}
+
+fn new_thread(
+    entry: fn(usize),
+    arg: usize,
+    process: Pid,
+    cr3: Option<(PhysFrame, Cr3Flags)>,
+) -> Arc<Thread> {
+    let stack = vec![0u8; KERNEL_STACK_SIZE].into_boxed_slice();
+    let top = VirtAddr::from_ptr(stack.as_ptr_range().end).align_down(16u64);
+
+    // registers popped by `switch_asm` before returning to the trampoline
+    let frame = [0, 0, arg as u64, entry as usize as u64, 0, 0];
+    let rsp = top - 8u64 * (frame.len() as u64 + 1);
+    unsafe {
+        let slots = rsp.as_mut_ptr::<u64>();
+        for (i, value) in frame.iter().enumerate() {
+            slots.add(i).write(*value);
+        }
+        slots
+            .add(frame.len())
+            .write(thread_trampoline as *const () as u64);
+    }
+
+    Arc::new(Thread {
+        id: NEXT_TID.fetch_add(1, Ordering::Relaxed),
+        state: AtomicU8::new(ThreadState::Ready as u8),
+        rsp: UnsafeCell::new(rsp.as_u64()),
+        cr3: UnsafeCell::new(cr3.unwrap_or_else(Cr3::read)),
+        kernel_stack_top: AtomicU64::new(top.as_u64()),
+        user_return: AtomicUsize::new(0),
+        process,
+        _stack: Some(stack),
+    })
+}
+
+/// Creates a ready thread running `entry(arg)` inside `process`, with the page tables `cr3`.
+pub fn spawn_raw(
+    entry: fn(usize),
+    arg: usize,
+    process: Pid,
+    cr3: Option<(PhysFrame, Cr3Flags)>,
+) -> Arc<Thread> {
+    let thread = new_thread(entry, arg, process, cr3);
+    make_ready(thread.clone());
+    thread
+}
+
+/// Creates a kernel thread running `f` in the process of the caller.
+pub fn spawn(f: impl FnOnce() + Send + 'static) -> Arc<Thread> {
+    fn run_boxed(arg: usize) {
+        let f = unsafe { Box::from_raw(arg as *mut Box<dyn FnOnce() + Send>) };
+        f()
+    }
+    let boxed: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+    spawn_raw(
+        run_boxed,
+        Box::into_raw(boxed) as usize,
+        current().process,
+        None,
+    )
+}
+
+extern "C" fn thread_start(entry: usize, arg: usize) -> ! {
+    finish_switch();
+    interrupts::enable();
+    let entry: fn(usize) = unsafe { core::mem::transmute(entry) };
+    entry(arg);
+    exit_current()
+}
+
+fn idle_main(_: usize) {
+    loop {
+        ints::wait_int();
+        yield_now();
+    }
+}
+
+pub fn current() -> Arc<Thread> {
+    let ptr = percpu::CURRENT_THREAD.get().load(Ordering::Acquire) as *const Thread;
+    assert!(!ptr.is_null(), "scheduler not initialized");
+    unsafe {
+        Arc::increment_strong_count(ptr);
+        Arc::from_raw(ptr)
+    }
+}
+
+pub fn try_current() -> Option<Arc<Thread>> {
+    let ptr = percpu::CURRENT_THREAD.get().load(Ordering::Acquire);
+    (!ptr.is_null()).then(current)
+}
+
+/// Sets the stack used by traps from ring 3 for the running thread.
+pub unsafe fn set_kernel_stack(top: VirtAddr) {
+    if let Some(thread) = try_current() {
+        thread
+            .kernel_stack_top
+            .store(top.as_u64(), Ordering::Relaxed);
+    }
+    gdt::set_kernel_stack(top);
+}
+
+fn make_ready(thread: Arc<Thread>) {
+    thread.set_state(ThreadState::Ready);
+    interrupts::without_interrupts(|| SCHEDULER.lock().ready.push_back(thread));
+}
+
+/// Makes a blocked thread runnable again. Does nothing for threads in any other state.
+pub fn wake(thread: &Arc<Thread>) {
+    interrupts::without_interrupts(|| {
+        if thread.state() == ThreadState::Blocked {
+            make_ready(thread.clone());
+        }
+    })
+}
+
+pub fn yield_now() {
+    interrupts::without_interrupts(schedule);
+}
+
+/// Blocks the running thread until someone calls [`wake`] on it.
+///
+/// Must be called with interrupts disabled, after the thread was registered wherever the wake up
+/// comes from, so it can't be missed.
+pub fn block_current() {
+    assert!(!interrupts::are_enabled());
+    current().set_state(ThreadState::Blocked);
+    schedule();
+}
+
+pub fn sleep_ticks(ticks: u128) {
+    let until = ints::get_ticks() + ticks;
+    // a stale timeout of an earlier wait could wake the thread sooner
+    while ints::get_ticks() < until {
+        interrupts::without_interrupts(|| {
+            SCHEDULER.lock().sleeping.push((until, current()));
+            block_current();
+        })
+    }
+}
+
+pub fn exit_current() -> ! {
+    interrupts::disable();
+    current().set_state(ThreadState::Dead);
+    schedule();
+    unreachable!("dead thread scheduled");
+}
+
+/// Called on every timer tick: wakes sleeping threads and preempts the running one.
+pub fn tick() {
+    let now = ints::get_ticks();
+    let preempt = {
+        let mut sched = SCHEDULER.lock();
+        let mut i = 0;
+        while i < sched.sleeping.len() {
+            if sched.sleeping[i].0 <= now {
+                let (_, thread) = sched.sleeping.swap_remove(i);
+                if thread.state() == ThreadState::Blocked {
+                    thread.set_state(ThreadState::Ready);
+                    sched.ready.push_back(thread);
+                }
+            } else {
+                i += 1;
+            }
+        }
+        now - sched.slice_start >= TIME_SLICE
+    };
+    if preempt && percpu::CURRENT_THREAD.get().load(Ordering::Relaxed) as usize != 0 {
+        schedule();
+    }
+}
+
+/// Switches to the next ready thread. Must be called with interrupts disabled.
+fn schedule() {
+    let current = current();
+    let next = {
+        let mut sched = SCHEDULER.lock();
+        sched.slice_start = ints::get_ticks();
+        let is_idle = sched
+            .idle
+            .as_ref()
+            .is_some_and(|i| Arc::ptr_eq(i, &current));
+        let next = match sched.ready.pop_front() {
+            Some(next) => next,
+            None if current.state() == ThreadState::Running && !is_idle => return,
+            None if is_idle => return,
+            None => sched.idle.clone().unwrap(),
+        };
+        if current.state() == ThreadState::Running && !is_idle {
+            current.set_state(ThreadState::Ready);
+            sched.ready.push_back(current.clone());
+        } else if current.state() == ThreadState::Dead {
+            sched.dead = Some(current.clone());
+        }
+        next
+    };
+    if Arc::ptr_eq(&next, &current) {
+        current.set_state(ThreadState::Running);
+        return;
+    }
+
+    next.set_state(ThreadState::Running);
+    unsafe {
+        *current.cr3.get() = Cr3::read();
+        let (frame, flags) = *next.cr3.get();
+        if Cr3::read().0 != frame {
+            Cr3::write(frame, flags);
+        }
+        gdt::set_kernel_stack(next.kernel_stack_top());
+    }
+    let old_rsp = current.rsp.get();
+    let new_rsp = unsafe { *next.rsp.get() };
+    // the per-CPU pointer owns a reference to the running thread
+    let next_ptr = Arc::into_raw(next) as *mut ();
+    let old_ptr = percpu::CURRENT_THREAD
+        .get()
+        .swap(next_ptr, Ordering::AcqRel);
+    unsafe { Arc::decrement_strong_count(old_ptr as *const Thread) };
+    drop(current);
+
+    unsafe { switch_asm(old_rsp, new_rsp) };
+    finish_switch();
+}
+
+/// Cleans up after the thread switched away from, once its stack isn't in use anymore.
+fn finish_switch() {
+    let dead = SCHEDULER.lock().dead.take();
+    drop(dead);
+}
+
+/// A list of threads waiting for some condition to become true.
+pub struct WaitQueue {
+    waiters: spin::Mutex<VecDeque<Arc<Thread>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: spin::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks until `cond` returns something. `cond` runs with interrupts disabled, and the
+    /// queue must be notified whenever its result might have changed.
+    pub fn wait_until<T>(&self, mut cond: impl FnMut() -> Option<T>) -> T {
+        self.wait_until_deadline(None, &mut cond).unwrap()
+    }
+
+    /// Like [`WaitQueue::wait_until`], but gives up with `None` after `timeout` ticks.
+    pub fn wait_until_timeout<T>(
+        &self,
+        timeout: u128,
+        mut cond: impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        let deadline = ints::get_ticks() + timeout;
+        self.wait_until_deadline(Some(deadline), &mut cond)
+    }
+
+    fn wait_until_deadline<T>(
+        &self,
+        deadline: Option<u128>,
+        cond: &mut impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        loop {
+            let done = interrupts::without_interrupts(|| {
+                if let Some(value) = cond() {
+                    return Some(Some(value));
+                }
+                if deadline.is_some_and(|d| ints::get_ticks() >= d) {
+                    return Some(None);
+                }
+                let current = current();
+                self.waiters.lock().push_back(current.clone());
+                if let Some(deadline) = deadline {
+                    SCHEDULER.lock().sleeping.push((deadline, current.clone()));
+                }
+                block_current();
+                // a timeout wakes the thread without removing it from the queue
+                self.waiters.lock().retain(|t| !Arc::ptr_eq(t, &current));
+                None
+            });
+            if let Some(result) = done {
+                return result;
+            }
+        }
+    }
+
+    pub fn notify_one(&self) {
+        let waiter = interrupts::without_interrupts(|| self.waiters.lock().pop_front());
+        if let Some(waiter) = waiter {
+            wake(&waiter);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        let waiters = interrupts::without_interrupts(|| core::mem::take(&mut *self.waiters.lock()));
+        for waiter in waiters.iter() {
+            wake(waiter);
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
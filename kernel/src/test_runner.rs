@@ -1,8 +1,15 @@
 use core::ptr::null_mut;
 
 use alloc::{boxed::Box, vec::Vec};
+use bootloader_api::info::{FrameBufferInfo, PixelFormat};
+use core::fmt::Write;
 
-use crate::{info, okay};
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+use crate::{info, monitor::FrameBufferWriter, okay, process::ExitStatus, user};
 
 pub const TESTS: &[(&'static str, fn())] = &[
     ("equality", eq_assertion),
@@ -14,6 +21,46 @@ pub const TESTS: &[(&'static str, fn())] = &[
     ),
     ("memory allocation box", memory_allocation_box),
     ("memory allocation vec", memory_allocation_vectors),
+    ("user mode roundtrip", user_mode_roundtrip),
+    ("syscall write and exit", syscall_write_and_exit),
+    ("elf loader stack", elf_loader_stack),
+    ("elf entry checks", elf_entry_checks),
+    ("kernel threads", kernel_threads),
+    ("process exit code", process_exit_code),
+    ("process getpid", process_getpid),
+    ("signal handler", signal_handler),
+    ("signal on fault", signal_on_fault),
+    ("fd dup2", fd_dup2),
+    ("pipe between processes", pipe_between_processes),
+    ("channel between threads", channel_between_threads),
+    ("channel syscalls", channel_syscalls),
+    ("shared memory", shared_memory),
+    ("vfs path normalization", vfs_path_normalization),
+    (
+        "user strings at address space end",
+        user_strings_at_address_space_end,
+    ),
+    ("ramfs files and directories", ramfs_files_and_directories),
+    ("ramfs symlinks and mounts", ramfs_symlinks_and_mounts),
+    ("initrd unpack", initrd_unpack),
+    ("ata read and write", ata_read_and_write),
+    ("buffer cache write back", buffer_cache_write_back),
+    ("mbr partitions", mbr_partitions),
+    ("gpt partitions", gpt_partitions),
+    ("fat long names and clusters", fat_long_names_and_clusters),
+    ("ext2 files and links", ext2_files_and_links),
+    ("ext2 rename", ext2_rename),
+    ("devfs nodes", devfs_nodes),
+    ("procfs files", procfs_files),
+    ("shell line editor", shell_line_editor),
+    ("monitor escape sequences", monitor_escape_sequences),
+    ("monitor cells", monitor_cells),
+    ("monitor scrollback", monitor_scrollback),
+    ("monitor cursor", monitor_cursor),
+    ("shell commands", shell_commands),
+    ("script parse", script_parse),
+    ("shell scripts", shell_scripts),
+    ("shell programs", shell_programs),
 ];
 
 pub fn run_tests() {
@@ -24,7 +71,7 @@ pub fn executor(tests: &[(&'static str, fn())]) {
     info!("running {} tests", tests.len());
     for (name, test) in tests.iter() {
         info!("\trunning test '{name}'");
-        catch_unwindtest();
+        test();
         okay!("\ttest suceeded");
     }
 }
@@ -82,3 +129,1728 @@ pub fn memory_allocation_vectors() {
     let v: Vec<u8> = (0..100).collect();
     assert_eq!(v.len(), 100);
 }
+
+/// Runs `program` in ring 3 with a single page of stack, returning its exit code.
+fn run_user_program(program: &[u8]) -> u64 {
+    use x86_64::structures::paging::Mapper;
+
+    const CODE: u64 = 0x0000_0080_0000_0000;
+    const STACK: u64 = CODE + 0x10_0000;
+
+    let mapper = unsafe { crate::PAGE_MAPPER.as_mut().unwrap() };
+    let frames = unsafe { crate::FRAME_ALLOCATOR.as_mut().unwrap() };
+    let pages = [CODE, STACK].map(|addr| Page::containing_address(VirtAddr::new(addr)));
+    for page in pages {
+        user::map_user_page(mapper, frames, page, PageTableFlags::WRITABLE).unwrap();
+    }
+    let code = unsafe {
+        core::ptr::copy_nonoverlapping(program.as_ptr(), CODE as *mut u8, program.len());
+        user::run(VirtAddr::new(CODE), VirtAddr::new(STACK + 4096))
+    };
+    for page in pages {
+        mapper.unmap(page).unwrap().1.flush();
+    }
+    code
+}
+
+pub fn user_mode_roundtrip() {
+    // mov edi, 42; mov eax, 60 (exit); int 0x80; jmp $
+    const PROGRAM: &[u8] = &[0xbf, 42, 0, 0, 0, 0xb8, 60, 0, 0, 0, 0xcd, 0x80, 0xeb, 0xfe];
+    assert_eq!(run_user_program(PROGRAM), 42);
+}
+
+// lea rsi, [rip + msg]; mov edx, 3; mov edi, 1; mov eax, 1 (write); syscall
+// mov edi, eax; mov eax, 60 (exit); syscall
+// msg: "ok\n"
+const WRITE_OK: &[u8] = &[
+    0x48, 0x8d, 0x35, 26, 0, 0, 0, 0xba, 3, 0, 0, 0, 0xbf, 1, 0, 0, 0, 0xb8, 1, 0, 0, 0, 0x0f,
+    0x05, 0x89, 0xc7, 0xb8, 60, 0, 0, 0, 0x0f, 0x05, b'o', b'k', b'\n',
+];
+
+pub fn syscall_write_and_exit() {
+    assert_eq!(run_user_program(WRITE_OK), 3);
+}
+
+/// Wraps `code` in a minimal static ELF64 executable with a single RX segment at 0x400000.
+pub fn build_elf(code: &[u8]) -> Vec<u8> {
+    const BASE: u64 = 0x40_0000;
+    const HEADERS: u64 = 64 + 56;
+
+    let size = HEADERS + code.len() as u64;
+    let mut elf = Vec::new();
+    elf.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+    elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    elf.extend_from_slice(&0x3eu16.to_le_bytes()); // x86_64
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&(BASE + HEADERS).to_le_bytes()); // entry
+    elf.extend_from_slice(&64u64.to_le_bytes()); // phoff
+    elf.extend_from_slice(&0u64.to_le_bytes()); // shoff
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    for half in [64u16, 56, 1, 64, 0, 0] {
+        elf.extend_from_slice(&half.to_le_bytes());
+    }
+    elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    elf.extend_from_slice(&5u32.to_le_bytes()); // R + X
+    for word in [0, BASE, BASE, size, size, 0x1000] {
+        elf.extend_from_slice(&word.to_le_bytes());
+    }
+    elf.extend_from_slice(code);
+    elf
+}
+
+pub fn elf_loader_stack() {
+    // mov rdi, [rsp] (argc); mov eax, 60 (exit); syscall
+    const CODE: &[u8] = &[0x48, 0x8b, 0x3c, 0x24, 0xb8, 60, 0, 0, 0, 0x0f, 0x05];
+    let elf = build_elf(CODE);
+    let code = crate::elf::run(&elf, &["prog", "a", "b"], &["HOME=/"]).unwrap();
+    assert_eq!(code, 3);
+}
+
+pub fn elf_entry_checks() {
+    use crate::elf::{Elf, ElfError};
+
+    const CODE: &[u8] = &[0x0f, 0x05];
+    let with_header = |offset: usize, value: &[u8]| {
+        let mut elf = build_elf(CODE);
+        elf[offset..offset + value.len()].copy_from_slice(value);
+        elf
+    };
+    assert!(Elf::parse(&build_elf(CODE)).is_ok());
+    // before the segment, past its end, and in the kernel half
+    for entry in [0x3f_ffff, 0x40_0000 + 64 + 56 + 2, u64::MAX] {
+        let elf = with_header(24, &entry.to_le_bytes());
+        assert_eq!(Elf::parse(&elf).err(), Some(ElfError::BadEntry));
+    }
+    // in a segment that can't be executed
+    let elf = with_header(64 + 4, &4u32.to_le_bytes());
+    assert_eq!(Elf::parse(&elf).err(), Some(ElfError::BadEntry));
+}
+
+pub fn kernel_threads() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::task::{self, WaitQueue};
+
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+    static QUEUE: WaitQueue = WaitQueue::new();
+    for _ in 0..3 {
+        task::spawn(|| {
+            DONE.fetch_add(1, Ordering::Relaxed);
+            QUEUE.notify_all();
+        });
+    }
+    QUEUE.wait_until(|| (DONE.load(Ordering::Relaxed) == 3).then_some(()));
+}
+
+pub fn process_exit_code() {
+    // mov edi, 5; mov eax, 60 (exit); syscall
+    const CODE: &[u8] = &[0xbf, 5, 0, 0, 0, 0xb8, 60, 0, 0, 0, 0x0f, 0x05];
+    let pid = crate::process::spawn(&build_elf(CODE), &["five"], &[]).unwrap();
+    assert_eq!(
+        crate::process::wait(Some(pid)),
+        Ok((pid, ExitStatus::Exited(5)))
+    );
+    assert!(crate::process::get(pid).is_none());
+}
+
+pub fn process_getpid() {
+    // mov eax, 39 (getpid); syscall; mov edi, eax; mov eax, 60 (exit); syscall
+    const CODE: &[u8] = &[
+        0xb8, 39, 0, 0, 0, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 60, 0, 0, 0, 0x0f, 0x05,
+    ];
+    let pid = crate::process::spawn(&build_elf(CODE), &["getpid"], &[]).unwrap();
+    assert_eq!(
+        crate::process::wait(None),
+        Ok((pid, ExitStatus::Exited(pid as i32)))
+    );
+}
+
+pub fn signal_handler() {
+    // r12 = mmap(0, 4096, PROT_READ | PROT_WRITE)
+    // rt_sigaction(SIGUSR1, {handler, SA_RESTORER, restorer, 0}, 0, 8)
+    // kill(getpid(), SIGUSR1); exit([r12])
+    // handler: mov [r12], edi; ret
+    // restorer: mov eax, 15 (rt_sigreturn); syscall
+    const CODE: &[u8] = &[
+        0xb8, 9, 0, 0, 0, 0x31, 0xff, 0xbe, 0, 0x10, 0, 0, 0xba, 3, 0, 0, 0, 0x0f, 0x05, 0x49,
+        0x89, 0xc4, 0x48, 0x8d, 0x05, 0x47, 0, 0, 0, 0x48, 0x8d, 0x0d, 0x45, 0, 0, 0, 0x6a, 0,
+        0x51, 0x68, 0, 0, 0, 0x04, 0x50, 0xb8, 13, 0, 0, 0, 0xbf, 10, 0, 0, 0, 0x48, 0x89, 0xe6,
+        0x31, 0xd2, 0x41, 0xba, 8, 0, 0, 0, 0x0f, 0x05, 0xb8, 39, 0, 0, 0, 0x0f, 0x05, 0x89, 0xc7,
+        0xbe, 10, 0, 0, 0, 0xb8, 62, 0, 0, 0, 0x0f, 0x05, 0x41, 0x8b, 0x3c, 0x24, 0xb8, 60, 0, 0,
+        0, 0x0f, 0x05, 0x41, 0x89, 0x3c, 0x24, 0xc3, 0xb8, 15, 0, 0, 0, 0x0f, 0x05,
+    ];
+    let pid = crate::process::spawn(&build_elf(CODE), &["handler"], &[]).unwrap();
+    let status = ExitStatus::Exited(crate::signal::SIGUSR1 as i32);
+    assert_eq!(crate::process::wait(Some(pid)), Ok((pid, status)));
+}
+
+pub fn signal_on_fault() {
+    // mov qword ptr [0], 0
+    const CODE: &[u8] = &[0x48, 0xc7, 0x04, 0x25, 0, 0, 0, 0, 0, 0, 0, 0];
+    let pid = crate::process::spawn(&build_elf(CODE), &["segv"], &[]).unwrap();
+    let status = ExitStatus::Signaled {
+        signal: crate::signal::SIGSEGV,
+        core: true,
+    };
+    assert_eq!(crate::process::wait(Some(pid)), Ok((pid, status)));
+    assert_eq!(status.wait_status(), 0x8b);
+}
+
+pub fn fd_dup2() {
+    use crate::{fd::FdTable, syscall::Errno};
+
+    let mut files = FdTable::with_console();
+    assert_eq!(files.dup2(1, 7), Ok(7));
+    assert!(files.get(7).is_ok());
+    assert_eq!(files.dup(0), Ok(3));
+    assert_eq!(files.close(1), Ok(()));
+    assert_eq!(files.dup2(1, 2).err(), Some(Errno::BadFd));
+    assert_eq!(files.dup(7), Ok(1));
+}
+
+pub fn pipe_between_processes() {
+    let (reader, writer) = crate::pipe::pipe();
+    let mut files = crate::fd::FdTable::with_console();
+    files.set(1, writer).unwrap();
+    let pid =
+        crate::process::spawn_with_files(&build_elf(WRITE_OK), &["write"], &[], files).unwrap();
+    let mut buf = [0; 8];
+    assert_eq!(reader.read(&mut buf), Ok(3));
+    assert_eq!(&buf[..3], b"ok\n");
+    assert_eq!(
+        crate::process::wait(Some(pid)),
+        Ok((pid, ExitStatus::Exited(3)))
+    );
+    // the only write end was closed with the child
+    assert_eq!(reader.read(&mut buf), Ok(0));
+}
+
+pub fn channel_between_threads() {
+    use crate::channel::{self, ChannelError};
+
+    let (sender, receiver) = channel::channel::<u32>(2);
+    assert_eq!(receiver.try_receive(), Err(ChannelError::Empty));
+    assert_eq!(receiver.receive_timeout(1), Err(ChannelError::Timeout));
+
+    let producer = sender.clone();
+    crate::task::spawn(move || {
+        for i in 0..10 {
+            producer.send(i).unwrap();
+        }
+    });
+    for i in 0..10 {
+        assert_eq!(receiver.receive(), Ok(i));
+    }
+
+    sender.try_send(10).unwrap();
+    sender.try_send(11).unwrap();
+    assert_eq!(sender.try_send(12).unwrap_err().error, ChannelError::Full);
+    drop(sender);
+    // buffered messages outlive the senders
+    assert_eq!(receiver.receive(), Ok(10));
+    assert_eq!(receiver.receive(), Ok(11));
+    assert_eq!(receiver.receive(), Err(ChannelError::Disconnected));
+}
+
+pub fn channel_syscalls() {
+    // sub rsp, 16; channel(4, rsp)
+    // channel_send([rsp], msg, 5, 0); channel_receive([rsp + 4], rsp + 8, 8, 0)
+    // movzx edi, byte ptr [rsp + 12]; mov eax, 60 (exit); syscall
+    // msg: "hello"
+    const CODE: &[u8] = &[
+        0x48, 0x83, 0xec, 0x10, 0xb8, 0xf4, 0x01, 0, 0, 0xbf, 4, 0, 0, 0, 0x48, 0x89, 0xe6, 0x0f,
+        0x05, 0x8b, 0x3c, 0x24, 0x48, 0x8d, 0x35, 0x33, 0, 0, 0, 0xba, 5, 0, 0, 0, 0x45, 0x31,
+        0xd2, 0xb8, 0xf5, 0x01, 0, 0, 0x0f, 0x05, 0x8b, 0x7c, 0x24, 4, 0x48, 0x8d, 0x74, 0x24, 8,
+        0xba, 8, 0, 0, 0, 0x45, 0x31, 0xd2, 0xb8, 0xf6, 0x01, 0, 0, 0x0f, 0x05, 0x0f, 0xb6, 0x7c,
+        0x24, 0x0c, 0xb8, 60, 0, 0, 0, 0x0f, 0x05, b'h', b'e', b'l', b'l', b'o',
+    ];
+    let pid = crate::process::spawn(&build_elf(CODE), &["channel"], &[]).unwrap();
+    let status = ExitStatus::Exited(b'o' as i32);
+    assert_eq!(crate::process::wait(Some(pid)), Ok((pid, status)));
+}
+
+pub fn shared_memory() {
+    use crate::{mem, shm};
+
+    // rax = shm_map("test", PROT_READ | PROT_WRITE); mov byte ptr [rax], 42; exit(0)
+    const CODE: &[u8] = &[
+        0x48, 0x8d, 0x3d, 0x1d, 0, 0, 0, 0xbe, 4, 0, 0, 0, 0xba, 3, 0, 0, 0, 0xb8, 0xf9, 0x01, 0,
+        0, 0x0f, 0x05, 0xc6, 0, 42, 0x31, 0xff, 0xb8, 60, 0, 0, 0, 0x0f, 0x05, b't', b'e', b's',
+        b't',
+    ];
+    let region = shm::create("test", 100).unwrap();
+    assert_eq!(region.size(), mem::PAGE_SIZE);
+    assert_eq!(
+        shm::create("test", 100).err(),
+        Some(shm::ShmError::AlreadyExists)
+    );
+
+    let pid = crate::process::spawn(&build_elf(CODE), &["shm"], &[]).unwrap();
+    assert_eq!(
+        crate::process::wait(Some(pid)),
+        Ok((pid, ExitStatus::Exited(0)))
+    );
+    let data = mem::phys_to_virt(region.frames()[0].start_address());
+    assert_eq!(unsafe { *data.as_ptr::<u8>() }, 42);
+
+    // the exited process dropped its mapping, so only the name keeps the region alive
+    shm::unlink("test").unwrap();
+    assert_eq!(alloc::sync::Arc::strong_count(&region), 1);
+
+    // reserving past the user address space fails, leaving the next reservation where it was
+    let process = crate::process::current();
+    let next = process.reserve_mmap(0).unwrap();
+    assert_eq!(process.reserve_mmap(u64::MAX), None);
+    assert_eq!(process.reserve_mmap(crate::user::USER_END), None);
+    assert_eq!(process.reserve_mmap(0), Some(next));
+}
+
+pub fn vfs_path_normalization() {
+    use crate::vfs::normalize;
+
+    assert_eq!(normalize("/"), "/");
+    assert_eq!(normalize("//usr///bin/"), "/usr/bin");
+    assert_eq!(normalize("/usr/./bin/../lib"), "/usr/lib");
+    assert_eq!(normalize("/../.."), "/");
+    assert_eq!(crate::vfs::absolute("etc/../tmp"), "/tmp");
+}
+
+pub fn user_strings_at_address_space_end() {
+    use crate::syscall::{user_cstr, Errno};
+
+    // strings at the very end of the address space must not wrap around
+    assert_eq!(user_cstr(u64::MAX), Err(Errno::Fault));
+    assert_eq!(user_cstr(u64::MAX - 4096), Err(Errno::Fault));
+}
+
+pub fn ramfs_files_and_directories() {
+    use crate::vfs::{self, FileType, FsError, SeekFrom};
+
+    vfs::mkdir("/ramfs-test", 0o755).unwrap();
+    vfs::write_file("/ramfs-test/hello", b"hello world").unwrap();
+    assert_eq!(
+        vfs::read_file("/ramfs-test/./hello").unwrap(),
+        b"hello world"
+    );
+
+    let file = vfs::open("/ramfs-test/hello", vfs::O_RDWR, 0).unwrap();
+    assert_eq!(file.seek(SeekFrom::End(-5)), Ok(6));
+    file.write(b"there!").unwrap();
+    assert_eq!(file.stat().size, 12);
+    drop(file);
+    assert_eq!(
+        vfs::read_file("/ramfs-test/hello").unwrap(),
+        b"hello there!"
+    );
+
+    vfs::chmod("/ramfs-test/hello", 0o600).unwrap();
+    let metadata = vfs::stat("/ramfs-test/hello").unwrap();
+    assert_eq!((metadata.kind, metadata.mode), (FileType::File, 0o600));
+    assert_eq!(vfs::stat("/ramfs-test").unwrap().nlink, 2);
+
+    vfs::chdir("/ramfs-test").unwrap();
+    vfs::mkdir("sub", 0o700).unwrap();
+    assert_eq!(crate::process::current().cwd(), "/ramfs-test");
+    assert_eq!(vfs::stat("../ramfs-test/sub").unwrap().mode, 0o700);
+    vfs::chdir("..").unwrap();
+
+    let names: Vec<_> = vfs::read_dir("/ramfs-test")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert_eq!(names, ["hello", "sub"]);
+    assert_eq!(vfs::rmdir("/ramfs-test").err(), Some(FsError::NotEmpty));
+    assert_eq!(
+        vfs::open("/ramfs-test/missing", vfs::O_RDONLY, 0).err(),
+        Some(FsError::NotFound)
+    );
+    assert_eq!(
+        vfs::open("/ramfs-test/hello/x", vfs::O_RDONLY, 0).err(),
+        Some(FsError::NotDirectory)
+    );
+    // a rename that fails leaves the file under its old name
+    let dir = vfs::lookup("/ramfs-test").unwrap();
+    let file = vfs::lookup("/ramfs-test/hello").unwrap();
+    assert_eq!(
+        dir.inode().rename("hello", file.inode(), "moved").err(),
+        Some(FsError::NotDirectory)
+    );
+    assert_eq!(
+        vfs::stat("/ramfs-test/hello").unwrap().ino,
+        file.inode().metadata().ino
+    );
+    vfs::unlink("/ramfs-test/hello").unwrap();
+    vfs::rmdir("/ramfs-test/sub").unwrap();
+    vfs::rmdir("/ramfs-test").unwrap();
+}
+
+pub fn ramfs_symlinks_and_mounts() {
+    use crate::{
+        ramfs::RamFs,
+        vfs::{self, FsError},
+    };
+
+    vfs::mkdir("/links", 0o755).unwrap();
+    vfs::write_file("/links/target", b"data").unwrap();
+    vfs::symlink("target", "/links/relative").unwrap();
+    vfs::symlink("/links", "/links/dir").unwrap();
+    vfs::symlink("loop", "/links/loop").unwrap();
+    assert_eq!(vfs::read_file("/links/dir/dir/relative").unwrap(), b"data");
+    assert_eq!(vfs::read_link("/links/relative").unwrap(), "target");
+    assert!(vfs::lstat("/links/relative").unwrap().kind == vfs::FileType::Symlink);
+    assert_eq!(vfs::stat("/links/loop").err(), Some(FsError::Loop));
+
+    // a mount hides the directory below it until unmounted
+    vfs::mkdir("/links/mnt", 0o755).unwrap();
+    vfs::write_file("/links/mnt/hidden", b"").unwrap();
+    vfs::mount("/links/dir/mnt", RamFs::new()).unwrap();
+    assert!(vfs::read_dir("/links/mnt").unwrap().is_empty());
+    vfs::write_file("/links/mnt/inner", b"mounted").unwrap();
+    assert_eq!(vfs::read_file("/links/mnt/../target").unwrap(), b"data");
+    assert_eq!(vfs::rmdir("/links/mnt").err(), Some(FsError::Busy));
+    assert_eq!(
+        vfs::unmount("/links/target").err(),
+        Some(FsError::InvalidPath)
+    );
+    vfs::unmount("/links/mnt").unwrap();
+    assert_eq!(vfs::stat("/links/mnt/inner").err(), Some(FsError::NotFound));
+    vfs::unlink("/links/mnt/hidden").unwrap();
+    vfs::rmdir("/links/mnt").unwrap();
+
+    for name in ["target", "relative", "dir", "loop"] {
+        vfs::unlink(&alloc::format!("/links/{name}")).unwrap();
+    }
+    vfs::rmdir("/links").unwrap();
+}
+
+/// An ustar header for `name`, as written by `build.rs`.
+fn tar_header(name: &str, kind: u8, mode: u32, size: usize, link: &str) -> [u8; 512] {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    let octal = alloc::format!("{mode:07o}\0{:07o}\0{:07o}\0{size:011o}\0", 0, 0);
+    header[100..100 + octal.len()].copy_from_slice(octal.as_bytes());
+    header[156] = kind;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..155].copy_from_slice(alloc::format!("{checksum:06o}\0").as_bytes());
+    header
+}
+
+pub fn initrd_unpack() {
+    use crate::{
+        initrd::{self, InitrdError},
+        vfs,
+    };
+
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&tar_header("bin/", b'5', 0o755, 0, ""));
+    archive.extend_from_slice(&tar_header("bin/hello", b'0', 0o750, 5, ""));
+    let mut content = [0u8; 512];
+    content[..5].copy_from_slice(b"hello");
+    archive.extend_from_slice(&content);
+    archive.extend_from_slice(&tar_header("hi", b'2', 0o777, 0, "bin/hello"));
+    archive.extend_from_slice(&[0; 1024]);
+
+    vfs::mkdir("/initrd-test", 0o755).unwrap();
+    assert_eq!(initrd::unpack(&archive, "/initrd-test"), Ok(3));
+    assert_eq!(vfs::read_file("/initrd-test/hi").unwrap(), b"hello");
+    assert_eq!(vfs::stat("/initrd-test/bin/hello").unwrap().mode, 0o750);
+    // unpacking again replaces the files
+    assert_eq!(initrd::unpack(&archive, "/initrd-test"), Ok(3));
+
+    archive[0] = b'x';
+    assert_eq!(
+        initrd::unpack(&archive, "/initrd-test"),
+        Err(InitrdError::BadChecksum)
+    );
+    assert_eq!(
+        initrd::unpack(&archive[512..1000], "/initrd-test"),
+        Err(InitrdError::Truncated)
+    );
+
+    vfs::unlink("/initrd-test/hi").unwrap();
+    vfs::unlink("/initrd-test/bin/hello").unwrap();
+    vfs::rmdir("/initrd-test/bin").unwrap();
+    vfs::rmdir("/initrd-test").unwrap();
+}
+
+pub fn ata_read_and_write() {
+    use crate::block::{self, BlockError, SECTOR_SIZE};
+
+    // the boot disk starts with the master boot record of the bootloader
+    let boot = block::get("hda").unwrap();
+    let mut sector = [0u8; SECTOR_SIZE];
+    boot.read_sectors(0, &mut sector).unwrap();
+    assert_eq!(sector[510..], [0x55, 0xaa]);
+    assert_eq!(
+        boot.read_sectors(boot.sector_count(), &mut sector),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        boot.read_sectors(0, &mut sector[..100]),
+        Err(BlockError::BadBuffer)
+    );
+
+    // the blank data disk, across a multiple sector command
+    let data = block::get("hdb").unwrap();
+    let lba = data.sector_count() - 3;
+    let mut saved = [0u8; SECTOR_SIZE * 3];
+    data.read_sectors(lba, &mut saved).unwrap();
+    let pattern: Vec<u8> = (0..SECTOR_SIZE * 3).map(|i| (i % 251) as u8).collect();
+    data.write_sectors(lba, &pattern).unwrap();
+    let mut read = [0u8; SECTOR_SIZE * 3];
+    data.read_sectors(lba, &mut read).unwrap();
+    assert_eq!(read[..], pattern[..]);
+    data.write_sectors(lba, &saved).unwrap();
+
+    // more sectors than a 28 bit command can count, well below its limit
+    const LONG: usize = 300;
+    let mut saved = alloc::vec![0u8; SECTOR_SIZE * LONG];
+    data.read_sectors(1, &mut saved).unwrap();
+    let pattern: Vec<u8> = (0..SECTOR_SIZE * LONG).map(|i| (i % 253) as u8).collect();
+    data.write_sectors(1, &pattern).unwrap();
+    let mut read = alloc::vec![0u8; SECTOR_SIZE * LONG];
+    data.read_sectors(1, &mut read).unwrap();
+    assert!(read == pattern);
+    data.write_sectors(1, &saved).unwrap();
+    data.flush().unwrap();
+}
+
+pub fn buffer_cache_write_back() {
+    use crate::{
+        block::{BlockDevice, SECTOR_SIZE},
+        cache::BufferCache,
+        ramdisk::RamDisk,
+    };
+    use alloc::sync::Arc;
+
+    let disk = Arc::new(RamDisk::new("ram0", 8));
+    let cache = BufferCache::new(disk.clone(), 2);
+
+    // unaligned writes are kept in the cache until synced
+    cache.write_at(SECTOR_SIZE as u64 - 2, b"abcd").unwrap();
+    assert!(disk.to_bytes().iter().all(|&b| b == 0));
+    let mut read = [0u8; 4];
+    cache.read_at(SECTOR_SIZE as u64 - 2, &mut read).unwrap();
+    assert_eq!(&read, b"abcd");
+    assert_eq!(cache.stats().dirty, 2);
+    cache.sync().unwrap();
+    assert_eq!(&disk.to_bytes()[SECTOR_SIZE - 2..SECTOR_SIZE + 2], b"abcd");
+
+    // the least recently used sector goes first, written back when dirty
+    cache.write_sectors(2, &[7; SECTOR_SIZE]).unwrap();
+    let mut sector = [0u8; SECTOR_SIZE];
+    cache.read_sectors(0, &mut sector).unwrap();
+    cache.read_sectors(3, &mut sector).unwrap();
+    assert_eq!(disk.to_bytes()[2 * SECTOR_SIZE], 7);
+    let stats = cache.stats();
+    assert_eq!((stats.cached, stats.dirty), (2, 0));
+    let misses = stats.misses;
+    cache.read_sectors(3, &mut sector).unwrap();
+    assert_eq!(cache.stats().misses, misses);
+
+    // dropping the cache writes back what it still holds
+    cache.write_sectors(5, &[9; SECTOR_SIZE]).unwrap();
+    drop(cache);
+    assert_eq!(disk.to_bytes()[5 * SECTOR_SIZE], 9);
+}
+
+pub fn mbr_partitions() {
+    use crate::{
+        block::{BlockDevice, SECTOR_SIZE},
+        partition::{self, PartitionKind},
+        ramdisk::RamDisk,
+    };
+
+    fn entry(sector: &mut [u8], index: usize, kind: u8, start: u32, sectors: u32) {
+        let entry = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    // a primary partition, then an extended one holding two logical partitions
+    let mut image = alloc::vec![0u8; 64 * SECTOR_SIZE];
+    fn sector(image: &mut [u8], lba: usize) -> &mut [u8] {
+        &mut image[lba * SECTOR_SIZE..(lba + 1) * SECTOR_SIZE]
+    }
+    let mbr = sector(&mut image, 0);
+    entry(mbr, 0, 0x83, 1, 15);
+    entry(mbr, 1, 0x05, 16, 48);
+    mbr[510..].copy_from_slice(&[0x55, 0xaa]);
+    let ebr = sector(&mut image, 16);
+    entry(ebr, 0, 0x0c, 1, 7);
+    entry(ebr, 1, 0x05, 8, 40);
+    ebr[510..].copy_from_slice(&[0x55, 0xaa]);
+    let ebr = sector(&mut image, 24);
+    entry(ebr, 0, 0x83, 2, 30);
+    ebr[510..].copy_from_slice(&[0x55, 0xaa]);
+
+    let disk = alloc::sync::Arc::new(RamDisk::from_bytes("mbr", image));
+    let table = partition::read_table(&*disk).unwrap();
+    let found: Vec<_> = table
+        .iter()
+        .map(|p| (p.number, p.start, p.sectors))
+        .collect();
+    assert_eq!(found, [(1, 1, 15), (5, 17, 7), (6, 26, 30)]);
+    assert_eq!(table[1].kind, PartitionKind::Mbr(0x0c));
+
+    let partitions = partition::scan(disk.clone()).unwrap();
+    assert_eq!(partitions[2].name(), "mbr6");
+    partitions[2].write_sectors(0, &[1; SECTOR_SIZE]).unwrap();
+    assert_eq!(disk.to_bytes()[26 * SECTOR_SIZE], 1);
+    assert!(crate::block::get("mbr5").is_some());
+    // the test disk doesn't stay in `/dev`
+    for partition in &partitions {
+        assert!(crate::block::unregister(partition.name()).is_some());
+    }
+    assert!(crate::block::get("mbr5").is_none());
+}
+
+pub fn gpt_partitions() {
+    use crate::{
+        block::SECTOR_SIZE,
+        partition::{self, crc32, Guid, PartitionError, PartitionKind},
+        ramdisk::RamDisk,
+    };
+
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(
+        alloc::format!("{}", Guid::BASIC_DATA),
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7"
+    );
+
+    // protective mbr, headers at 1 and 63, entries at 2 and 62
+    const SECTORS: usize = 64;
+    let mut image = alloc::vec![0u8; SECTORS * SECTOR_SIZE];
+    image[446 + 4] = 0xee;
+    image[510..512].copy_from_slice(&[0x55, 0xaa]);
+    let mut entries = [0u8; 512];
+    entries[..16].copy_from_slice(&Guid::LINUX_FS.0);
+    entries[16] = 0x42;
+    entries[32..40].copy_from_slice(&10u64.to_le_bytes());
+    entries[40..48].copy_from_slice(&19u64.to_le_bytes());
+    for (i, c) in "data".encode_utf16().enumerate() {
+        entries[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+    for (header_lba, entries_lba, other) in [(1u64, 2u64, 63u64), (63, 62, 1)] {
+        let mut header = [0u8; 512];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&header_lba.to_le_bytes());
+        header[32..40].copy_from_slice(&other.to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        let at = header_lba as usize * SECTOR_SIZE;
+        image[at..at + SECTOR_SIZE].copy_from_slice(&header);
+        let at = entries_lba as usize * SECTOR_SIZE;
+        image[at..at + SECTOR_SIZE].copy_from_slice(&entries);
+    }
+
+    let expected = PartitionKind::Gpt {
+        type_guid: Guid::LINUX_FS,
+        unique_guid: Guid([0x42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+        name: "data".into(),
+    };
+    let table = partition::read_table(&RamDisk::from_bytes("gpt", image.clone())).unwrap();
+    assert_eq!(
+        (table[0].number, table[0].start, table[0].sectors),
+        (1, 10, 10)
+    );
+    assert_eq!(table[0].kind, expected);
+
+    // a primary header with a bad entry array, but a good CRC, falls back to the backup
+    let with_primary_field = |offset: usize, value: &[u8]| {
+        let mut image = image.clone();
+        let header = &mut image[SECTOR_SIZE..2 * SECTOR_SIZE];
+        header[offset..offset + value.len()].copy_from_slice(value);
+        header[16..20].fill(0);
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        RamDisk::from_bytes("gpt", image)
+    };
+    // an array wrapping around, then one far bigger than the heap, never read in
+    let wrapping = with_primary_field(72, &(u64::MAX - 1).to_le_bytes());
+    assert_eq!(partition::read_table(&wrapping).unwrap()[0].start, 10);
+    let huge = with_primary_field(84, &(1u32 << 28).to_le_bytes());
+    assert_eq!(partition::read_table(&huge).unwrap()[0].start, 10);
+
+    // a damaged primary entry array falls back to the backup
+    image[2 * SECTOR_SIZE + 33] ^= 1;
+    let table = partition::read_table(&RamDisk::from_bytes("gpt", image.clone())).unwrap();
+    assert_eq!(table[0].start, 10);
+    image[62 * SECTOR_SIZE + 33] ^= 1;
+    assert_eq!(
+        partition::read_table(&RamDisk::from_bytes("gpt", image)),
+        Err(PartitionError::BadChecksum)
+    );
+}
+
+/// A blank FAT disk of `sectors` sectors with one sector per cluster, FAT32 without
+/// `root_entries`.
+fn fat_image(sectors: usize, root_entries: u16, fat_bits: usize) -> SparseDisk {
+    use crate::block::SECTOR_SIZE;
+
+    let fat32 = root_entries == 0;
+    let reserved = if fat32 { 32 } else { 1 };
+    let fat_size = ((sectors + 2) * fat_bits / 8).div_ceil(SECTOR_SIZE);
+    let root_sectors = (root_entries as usize * 32).div_ceil(SECTOR_SIZE);
+    // the data area starts out zeroed, so only what comes before it is built
+    let mut image = alloc::vec![0u8; (reserved + 2 * fat_size + root_sectors) * SECTOR_SIZE];
+    let boot = &mut image[..SECTOR_SIZE];
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&root_entries.to_le_bytes());
+    boot[21] = 0xf8;
+    boot[32..36].copy_from_slice(&(sectors as u32).to_le_bytes());
+    if fat32 {
+        boot[36..40].copy_from_slice(&(fat_size as u32).to_le_bytes());
+        boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+        boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+    } else {
+        boot[22..24].copy_from_slice(&(fat_size as u16).to_le_bytes());
+    }
+    boot[510..].copy_from_slice(&[0x55, 0xaa]);
+    if fat32 {
+        let info = &mut image[SECTOR_SIZE..2 * SECTOR_SIZE];
+        info[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+        info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+        info[488..496].fill(0xff);
+    }
+    // the media byte and end of chain marks in the two reserved entries, then the root
+    // directory cluster on FAT32
+    let head: &[u8] = match fat_bits {
+        12 => &[0xf8, 0xff, 0xff],
+        16 => &[0xf8, 0xff, 0xff, 0xff],
+        _ => &[
+            0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+        ],
+    };
+    for copy in 0..2 {
+        let at = (reserved + copy * fat_size) * SECTOR_SIZE;
+        image[at..at + head.len()].copy_from_slice(head);
+    }
+    SparseDisk::new("fat", sectors as u64, &image)
+}
+
+/// A disk storing only the sectors that aren't zeroed, for filesystems larger than the heap.
+struct SparseDisk {
+    name: &'static str,
+    sectors: u64,
+    data: spin::Mutex<alloc::collections::BTreeMap<u64, Box<[u8]>>>,
+}
+
+impl SparseDisk {
+    /// A disk of `sectors` sectors starting with `head`.
+    fn new(name: &'static str, sectors: u64, head: &[u8]) -> Self {
+        let disk = Self {
+            name,
+            sectors,
+            data: spin::Mutex::new(alloc::collections::BTreeMap::new()),
+        };
+        crate::block::BlockDevice::write_sectors(&disk, 0, head).unwrap();
+        disk
+    }
+}
+
+impl crate::block::BlockDevice for SparseDisk {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), crate::block::BlockError> {
+        self.check_range(lba, buf.len())?;
+        let data = self.data.lock();
+        for (sector, chunk) in (lba..).zip(buf.chunks_exact_mut(self.sector_size())) {
+            match data.get(&sector) {
+                Some(stored) => chunk.copy_from_slice(stored),
+                None => chunk.fill(0),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), crate::block::BlockError> {
+        self.check_range(lba, buf.len())?;
+        let mut data = self.data.lock();
+        for (sector, chunk) in (lba..).zip(buf.chunks_exact(self.sector_size())) {
+            if chunk.iter().all(|&b| b == 0) {
+                data.remove(&sector);
+            } else {
+                data.insert(sector, chunk.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn fat_long_names_and_clusters() {
+    use crate::{
+        block::{BlockDevice, SECTOR_SIZE},
+        fat::{FatFs, FatKind},
+        vfs,
+    };
+    use alloc::sync::Arc;
+
+    // no whole cluster in the data area, then a table too small for the clusters
+    let with_boot = |disk: SparseDisk, offset: usize, value: &[u8]| {
+        let mut sector = [0u8; SECTOR_SIZE];
+        disk.read_sectors(0, &mut sector).unwrap();
+        sector[offset..offset + value.len()].copy_from_slice(value);
+        disk.write_sectors(0, &sector).unwrap();
+        Arc::new(disk)
+    };
+    let no_cluster = with_boot(fat_image(64, 224, 12), 13, &[64]);
+    assert_eq!(FatFs::new(no_cluster).err(), Some(vfs::FsError::Corrupt));
+    let small_fat = with_boot(fat_image(2048, 224, 12), 22, &1u16.to_le_bytes());
+    assert_eq!(FatFs::new(small_fat).err(), Some(vfs::FsError::Corrupt));
+
+    fat_checks(Arc::new(fat_image(2048, 224, 12)), FatKind::Fat12);
+    fat_checks(Arc::new(fat_image(8192, 512, 16)), FatKind::Fat16);
+
+    // FAT32, with the FSInfo hint pointing at a free entry whose reserved top bits are set
+    let disk = Arc::new(fat_image(70000, 0, 32));
+    let mut sector = [0u8; SECTOR_SIZE];
+    disk.read_sectors(1, &mut sector).unwrap();
+    sector[488..492].copy_from_slice(&1234u32.to_le_bytes());
+    sector[492..496].copy_from_slice(&100u32.to_le_bytes());
+    disk.write_sectors(1, &sector).unwrap();
+    let fat_start = 32 * SECTOR_SIZE as u64;
+    let fat_size = (70002 * 4u64).div_ceil(SECTOR_SIZE as u64) * SECTOR_SIZE as u64;
+    for copy in 0..2 {
+        let at = fat_start + copy * fat_size + 101 * 4;
+        disk.write_at(at, &0xf000_0000u32.to_le_bytes()).unwrap();
+    }
+    let entry = |cluster: u64| {
+        let mut bytes = [0u8; 4];
+        disk.read_at(fat_start + cluster * 4, &mut bytes).unwrap();
+        u32::from_le_bytes(bytes)
+    };
+    fat_checks(disk.clone(), FatKind::Fat32);
+    // freeing the clusters kept the reserved bits
+    assert_eq!(entry(101), 0xf000_0000);
+
+    vfs::mkdir("/fat", 0o755).unwrap();
+    vfs::mount("/fat", FatFs::new(disk.clone()).unwrap()).unwrap();
+    vfs::write_file("/fat/two", &[7; 2 * SECTOR_SIZE]).unwrap();
+    // more entries than a cluster holds grow the root directory chain
+    for i in 0..20 {
+        vfs::write_file(&alloc::format!("/fat/f{i}"), b"").unwrap();
+    }
+    vfs::unmount("/fat").unwrap();
+    assert_eq!(entry(100), 101);
+    assert_eq!(entry(101), 0xffff_ffff);
+    let next_root = entry(2) & 0x0fff_ffff;
+    assert!((3..0x0fff_fff8).contains(&next_root));
+    disk.read_sectors(1, &mut sector).unwrap();
+    assert_eq!(&sector[488..492], &[0xff; 4]);
+
+    vfs::mount("/fat", FatFs::new(disk).unwrap()).unwrap();
+    assert_eq!(vfs::read_dir("/fat").unwrap().len(), 21);
+    assert_eq!(vfs::read_file("/fat/two").unwrap(), [7; 2 * SECTOR_SIZE]);
+    vfs::unmount("/fat").unwrap();
+    vfs::rmdir("/fat").unwrap();
+}
+
+/// Runs the same file operations on an empty FAT disk of the given `kind`.
+fn fat_checks(disk: alloc::sync::Arc<SparseDisk>, kind: crate::fat::FatKind) {
+    use crate::{
+        fat::FatFs,
+        vfs::{self, FileType, FsError},
+    };
+
+    let fs = FatFs::new(disk.clone()).unwrap();
+    assert_eq!(fs.kind(), kind);
+    vfs::mkdir("/fat", 0o755).unwrap();
+    vfs::mount("/fat", fs).unwrap();
+
+    // short names keep their case, others get long name entries
+    vfs::write_file("/fat/readme.txt", b"short").unwrap();
+    let long = "A rather long file name.text";
+    let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+    vfs::write_file(&alloc::format!("/fat/{long}"), &data).unwrap();
+    vfs::mkdir("/fat/Some Directory", 0o755).unwrap();
+    vfs::write_file("/fat/some directory/inner", b"inner").unwrap();
+    let mut names: Vec<_> = vfs::read_dir("/fat")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    names.sort();
+    assert_eq!(names, [long, "Some Directory", "readme.txt"]);
+    assert_eq!(vfs::read_file("/fat/README.TXT").unwrap(), b"short");
+    assert_eq!(
+        vfs::stat("/fat/Some Directory").unwrap().kind,
+        FileType::Directory
+    );
+
+    // writes past the end fill the gap with zeroes, truncation frees clusters
+    let file = vfs::open("/fat/readme.txt", vfs::O_RDWR, 0).unwrap();
+    file.seek(vfs::SeekFrom::Start(1500)).unwrap();
+    file.write(b"end").unwrap();
+    let content = vfs::read_file("/fat/readme.txt").unwrap();
+    assert_eq!(content.len(), 1503);
+    assert!(content[5..1500].iter().all(|&b| b == 0));
+    file.inode().truncate(2).unwrap();
+    assert_eq!(vfs::read_file("/fat/readme.txt").unwrap(), b"sh");
+
+    assert_eq!(
+        vfs::rmdir("/fat/Some Directory").err(),
+        Some(FsError::NotEmpty)
+    );
+    vfs::unlink("/fat/some directory/inner").unwrap();
+    vfs::rmdir("/fat/Some Directory").unwrap();
+    vfs::unmount("/fat").unwrap();
+
+    // everything is on the disk after unmounting
+    vfs::mount("/fat", FatFs::new(disk).unwrap()).unwrap();
+    assert_eq!(
+        vfs::read_file(&alloc::format!("/fat/{long}")).unwrap(),
+        data
+    );
+    assert_eq!(vfs::read_dir("/fat").unwrap().len(), 2);
+    vfs::unlink(&alloc::format!("/fat/{long}")).unwrap();
+    vfs::unlink("/fat/readme.txt").unwrap();
+    assert!(vfs::read_dir("/fat").unwrap().is_empty());
+    vfs::unmount("/fat").unwrap();
+    vfs::rmdir("/fat").unwrap();
+}
+
+/// A blank ext2 image of `blocks` blocks of 1 KiB in a single group, as `mke2fs` would make
+/// it without `lost+found`.
+fn ext2_image(blocks: usize) -> Vec<u8> {
+    const INODES: usize = 128;
+    // boot block, superblock, descriptors, bitmaps, inode table then the root directory
+    const ROOT_BLOCK: usize = 5 + INODES * 128 / 1024;
+
+    let mut image = alloc::vec![0u8; blocks * 1024];
+    let mut put = |at: usize, bytes: &[u8]| image[at..at + bytes.len()].copy_from_slice(bytes);
+    let free_blocks = (blocks - 1 - ROOT_BLOCK) as u32;
+    let sb = 1024;
+    put(sb, &(INODES as u32).to_le_bytes());
+    put(sb + 4, &(blocks as u32).to_le_bytes());
+    put(sb + 12, &free_blocks.to_le_bytes());
+    put(sb + 16, &(INODES as u32 - 10).to_le_bytes());
+    put(sb + 20, &1u32.to_le_bytes());
+    put(sb + 32, &8192u32.to_le_bytes());
+    put(sb + 36, &8192u32.to_le_bytes());
+    put(sb + 40, &(INODES as u32).to_le_bytes());
+    put(sb + 48, &1_700_000_000u32.to_le_bytes());
+    put(sb + 56, &0xef53u16.to_le_bytes());
+    put(sb + 58, &1u16.to_le_bytes());
+    put(sb + 60, &1u16.to_le_bytes());
+    put(sb + 76, &1u32.to_le_bytes());
+    put(sb + 84, &11u32.to_le_bytes());
+    put(sb + 88, &128u16.to_le_bytes());
+    put(sb + 96, &2u32.to_le_bytes());
+    put(sb + 100, &3u32.to_le_bytes());
+
+    let gd = 2 * 1024;
+    put(gd, &3u32.to_le_bytes());
+    put(gd + 4, &4u32.to_le_bytes());
+    put(gd + 8, &5u32.to_le_bytes());
+    put(gd + 12, &(free_blocks as u16).to_le_bytes());
+    put(gd + 14, &(INODES as u16 - 10).to_le_bytes());
+    put(gd + 16, &1u16.to_le_bytes());
+
+    // bits past the end of the group are set, like the used blocks and reserved inodes
+    let mut set_bits = |block: usize, bits: core::ops::Range<usize>| {
+        for bit in bits {
+            image[block * 1024 + bit / 8] |= 1 << (bit % 8);
+        }
+    };
+    set_bits(3, 0..ROOT_BLOCK);
+    set_bits(3, blocks - 1..8192);
+    set_bits(4, 0..10);
+    set_bits(4, INODES..8192);
+
+    let mut put = |at: usize, bytes: &[u8]| image[at..at + bytes.len()].copy_from_slice(bytes);
+    let root = 5 * 1024 + 128;
+    put(root, &0o40755u16.to_le_bytes());
+    put(root + 4, &1024u32.to_le_bytes());
+    put(root + 26, &2u16.to_le_bytes());
+    put(root + 28, &2u32.to_le_bytes());
+    put(root + 40, &(ROOT_BLOCK as u32).to_le_bytes());
+    let dir = ROOT_BLOCK * 1024;
+    put(dir, &[2, 0, 0, 0, 12, 0, 1, 2, b'.', 0, 0, 0]);
+    put(dir + 12, &[2, 0, 0, 0, 0xf4, 0x03, 2, 2, b'.', b'.']);
+    image
+}
+
+pub fn ext2_files_and_links() {
+    use crate::{
+        ext2::Ext2Fs,
+        ramdisk::RamDisk,
+        vfs::{self, FileType, FsError},
+    };
+    use alloc::sync::Arc;
+
+    // a first data block past the end is rejected rather than wrapping the group count around
+    let mut bad = ext2_image(64);
+    bad[1024 + 20..1024 + 24].copy_from_slice(&64u32.to_le_bytes());
+    let bad = Arc::new(RamDisk::from_bytes("ext2", bad));
+    assert_eq!(Ext2Fs::new(bad).err(), Some(FsError::Corrupt));
+    // so are groups with more blocks or inodes than their one-block bitmaps hold
+    for offset in [32, 40] {
+        let mut bad = ext2_image(64);
+        bad[1024 + offset..1024 + offset + 4].copy_from_slice(&8193u32.to_le_bytes());
+        let bad = Arc::new(RamDisk::from_bytes("ext2", bad));
+        assert_eq!(Ext2Fs::new(bad).err(), Some(FsError::Corrupt));
+    }
+    // and free counts disagreeing with the groups' are reported instead of wrapping around
+    let mut bad = ext2_image(64);
+    bad[1024 + 12..1024 + 16].fill(0);
+    let bad = Ext2Fs::new(Arc::new(RamDisk::from_bytes("ext2", bad))).unwrap();
+    vfs::mkdir("/ext-bad", 0o755).unwrap();
+    vfs::mount("/ext-bad", bad).unwrap();
+    assert_eq!(
+        vfs::write_file("/ext-bad/file", b"data").err(),
+        Some(FsError::Corrupt)
+    );
+    vfs::unmount("/ext-bad").unwrap();
+
+    let disk = Arc::new(RamDisk::from_bytes("ext2", ext2_image(1024)));
+    let fs = Ext2Fs::new(disk.clone()).unwrap();
+    let (free_blocks, free_inodes) = (fs.free_blocks(), fs.free_inodes());
+    vfs::mkdir("/ext", 0o755).unwrap();
+    vfs::mount("/ext", fs.clone()).unwrap();
+
+    // large enough to go through doubly indirect blocks
+    let data: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+    vfs::mkdir("/ext/dir", 0o750).unwrap();
+    vfs::write_file("/ext/dir/big", &data).unwrap();
+    vfs::symlink("dir/big", "/ext/short").unwrap();
+    let long_target = alloc::format!("/ext/dir/{}/../big", "x".repeat(80));
+    vfs::symlink(&long_target, "/ext/long").unwrap();
+    assert_eq!(vfs::read_link("/ext/long").unwrap(), long_target);
+    assert_eq!(vfs::read_file("/ext/short").unwrap().len(), data.len());
+    vfs::chmod("/ext/dir/big", 0o600).unwrap();
+    let stat = vfs::stat("/ext/dir").unwrap();
+    assert_eq!(
+        (stat.kind, stat.mode, stat.nlink),
+        (FileType::Directory, 0o750, 2)
+    );
+    assert_eq!(vfs::stat("/ext").unwrap().nlink, 3);
+
+    // holes read as zeroes and shrinking gives blocks back
+    let file = vfs::open("/ext/dir/big", vfs::O_RDWR, 0).unwrap();
+    file.inode().truncate(10).unwrap();
+    file.seek(vfs::SeekFrom::Start(5000)).unwrap();
+    file.write(b"tail").unwrap();
+    let content = vfs::read_file("/ext/dir/big").unwrap();
+    assert_eq!(&content[..10], &data[..10]);
+    assert!(content[10..5000].iter().all(|&b| b == 0));
+    assert_eq!(vfs::stat("/ext/dir/big").unwrap().mode, 0o600);
+
+    // an unlinked file stays readable while open
+    vfs::unlink("/ext/dir/big").unwrap();
+    assert_eq!(vfs::stat("/ext/dir/big").err(), Some(FsError::NotFound));
+    let mut buf = [0u8; 4];
+    file.seek(vfs::SeekFrom::Start(5000)).unwrap();
+    file.read(&mut buf).unwrap();
+    assert_eq!(&buf, b"tail");
+    drop(file);
+    assert_eq!(vfs::read_file("/ext/short").err(), Some(FsError::NotFound));
+
+    // and one still open when unmounting is freed once closed
+    vfs::write_file("/ext/open", b"kept").unwrap();
+    let file = vfs::open("/ext/open", vfs::O_RDONLY, 0).unwrap();
+    vfs::unlink("/ext/open").unwrap();
+    vfs::unmount("/ext").unwrap();
+    drop(fs);
+    assert_eq!(file.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"kept");
+    drop(file);
+
+    let fs = Ext2Fs::new(disk).unwrap();
+    vfs::mount("/ext", fs.clone()).unwrap();
+    assert_eq!(vfs::read_link("/ext/long").unwrap(), long_target);
+    vfs::unlink("/ext/long").unwrap();
+    vfs::unlink("/ext/short").unwrap();
+    vfs::rmdir("/ext/dir").unwrap();
+    assert!(vfs::read_dir("/ext").unwrap().is_empty());
+    assert_eq!(
+        (fs.free_blocks(), fs.free_inodes()),
+        (free_blocks, free_inodes)
+    );
+    vfs::unmount("/ext").unwrap();
+    vfs::rmdir("/ext").unwrap();
+}
+
+pub fn ext2_rename() {
+    use crate::{
+        ext2::Ext2Fs,
+        ramdisk::RamDisk,
+        ramfs::RamFs,
+        vfs::{self, FsError},
+    };
+    use alloc::sync::Arc;
+
+    let disk = Arc::new(RamDisk::from_bytes("ext2", ext2_image(256)));
+    vfs::mkdir("/ren", 0o755).unwrap();
+    vfs::mount("/ren", Ext2Fs::new(disk).unwrap()).unwrap();
+    vfs::mkdir("/ren/a", 0o755).unwrap();
+    vfs::mkdir("/ren/b", 0o755).unwrap();
+    vfs::write_file("/ren/a/file", b"one").unwrap();
+    vfs::write_file("/ren/b/other", b"two").unwrap();
+
+    // replacing a file, then moving a directory below another
+    vfs::rename("/ren/a/file", "/ren/b/other").unwrap();
+    assert_eq!(vfs::read_file("/ren/b/other").unwrap(), b"one");
+    assert_eq!(vfs::stat("/ren/a/file").err(), Some(FsError::NotFound));
+    vfs::rename("/ren/a", "/ren/b/a").unwrap();
+    assert_eq!(vfs::stat("/ren/b").unwrap().nlink, 3);
+    assert_eq!(vfs::stat("/ren").unwrap().nlink, 3);
+    vfs::chdir("/ren/b/a").unwrap();
+    assert_eq!(vfs::read_file("../other").unwrap(), b"one");
+    vfs::chdir("/").unwrap();
+
+    assert_eq!(
+        vfs::rename("/ren/b", "/ren/b/a/c").err(),
+        Some(FsError::InvalidPath)
+    );
+    assert_eq!(
+        vfs::rename("/ren/b/other", "/ren/b/a").err(),
+        Some(FsError::IsDirectory)
+    );
+    vfs::mkdir("/ren/empty", 0o755).unwrap();
+    assert_eq!(
+        vfs::rename("/ren/empty", "/ren/b").err(),
+        Some(FsError::NotEmpty)
+    );
+    vfs::rename("/ren/b/a", "/ren/empty").unwrap();
+
+    // moving across filesystems is up to user space
+    vfs::mkdir("/ren-ram", 0o755).unwrap();
+    vfs::mount("/ren-ram", RamFs::new()).unwrap();
+    assert_eq!(
+        vfs::rename("/ren/b/other", "/ren-ram/other").err(),
+        Some(FsError::CrossDevice)
+    );
+    vfs::unmount("/ren-ram").unwrap();
+    vfs::rmdir("/ren-ram").unwrap();
+
+    let mut names: Vec<_> = vfs::read_dir("/ren")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    names.sort();
+    assert_eq!(names, ["b", "empty"]);
+    vfs::unmount("/ren").unwrap();
+    vfs::rmdir("/ren").unwrap();
+}
+
+pub fn devfs_nodes() {
+    use crate::{
+        block::{self, SECTOR_SIZE},
+        ramdisk::RamDisk,
+        vfs::{self, FileType, FsError, O_RDONLY, O_RDWR},
+    };
+    use alloc::sync::Arc;
+
+    let names: Vec<_> = vfs::read_dir("/dev")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    for name in ["null", "zero", "random", "ttyS0", "fb0", "keyboard"] {
+        assert!(names.iter().any(|n| n == name), "/dev/{name} is missing");
+    }
+    assert_eq!(vfs::stat("/dev/null").unwrap().kind, FileType::CharDevice);
+    assert!(vfs::stat("/dev/fb0").unwrap().size > 0);
+
+    vfs::write_file("/dev/null", b"gone").unwrap();
+    assert_eq!(vfs::read_file("/dev/null").unwrap(), b"");
+    let zero = vfs::open("/dev/zero", O_RDONLY, 0).unwrap();
+    let mut buf = [1u8; 16];
+    assert_eq!(zero.read(&mut buf).unwrap(), 16);
+    assert_eq!(buf, [0; 16]);
+    let random = vfs::open("/dev/random", O_RDONLY, 0).unwrap();
+    let mut other = [0u8; 16];
+    random.read(&mut buf).unwrap();
+    random.read(&mut other).unwrap();
+    assert_ne!(buf, other);
+
+    // block devices show up as soon as they are registered
+    let disk = Arc::new(RamDisk::new("ramdev", 2));
+    block::register(disk.clone());
+    assert_eq!(
+        vfs::stat("/dev/ramdev").unwrap().kind,
+        FileType::BlockDevice
+    );
+    let file = vfs::open("/dev/ramdev", O_RDWR, 0).unwrap();
+    file.seek(vfs::SeekFrom::Start(SECTOR_SIZE as u64 - 2))
+        .unwrap();
+    file.write(b"abcd").unwrap();
+    assert_eq!(&disk.to_bytes()[SECTOR_SIZE - 2..SECTOR_SIZE + 2], b"abcd");
+    file.seek(vfs::SeekFrom::End(-1)).unwrap();
+    assert_eq!(file.write(b"xy").unwrap(), 1);
+    assert_eq!(file.write(b"z").err(), Some(FsError::NoSpace));
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+
+    assert_eq!(
+        vfs::write_file("/dev/new", b"").err(),
+        Some(FsError::ReadOnly)
+    );
+}
+
+pub fn procfs_files() {
+    use crate::{process, vfs};
+    use alloc::string::String;
+
+    let read = |path: &str| String::from_utf8(vfs::read_file(path).unwrap()).unwrap();
+    let value = |text: &str, key: &str| -> u64 {
+        let line = text.lines().find(|line| line.starts_with(key)).unwrap();
+        line[key.len() + 1..].trim().parse().unwrap()
+    };
+
+    assert!(value(&read("/proc/uptime"), "ticks:") > 0);
+    let meminfo = read("/proc/meminfo");
+    assert!(value(&meminfo, "frames_used:") <= value(&meminfo, "frames_total:"));
+    assert!(value(&meminfo, "heap_used:") > 0);
+    let timer = read("/proc/interrupts")
+        .lines()
+        .find(|line| line.contains("timer"))
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap());
+    assert!(timer.unwrap() > 0);
+    assert!(read("/proc/memmap").contains("usable"));
+    assert!(read("/proc/cpuinfo").starts_with("vendor: "));
+
+    // every process has a directory, the reader's one also reached through `self`
+    let pid = process::current().pid();
+    let status = read("/proc/self/status");
+    assert_eq!(value(&status, "pid:"), pid);
+    assert_eq!(read(&alloc::format!("/proc/{pid}/status")), status);
+    assert!(vfs::read_dir("/proc")
+        .unwrap()
+        .iter()
+        .any(|entry| entry.name == "1"));
+    assert!(vfs::stat("/proc/999999").is_err());
+}
+
+pub fn shell_line_editor() {
+    use crate::shell::{self, LineEditor};
+    use alloc::string::String;
+
+    let mut editor = LineEditor::new(shell::complete);
+    let mut out = String::new();
+    let mut feed = |editor: &mut LineEditor, input: &str| {
+        let mut line = None;
+        for byte in input.bytes() {
+            line = line.or(editor.feed(byte, &mut out));
+        }
+        line
+    };
+
+    // arrows move through the line, backspace and delete remove around the cursor
+    assert_eq!(feed(&mut editor, "abd\x1b[Dc"), None);
+    assert_eq!(editor.cursor(), 3);
+    assert_eq!(
+        feed(&mut editor, "\x1b[H\x1b[3~\x1b[F\x08\r").as_deref(),
+        Some("bc")
+    );
+    feed(&mut editor, "echo two\n");
+    assert_eq!(
+        feed(&mut editor, "x\x1b[A\x1b[A\x1b[B\n").as_deref(),
+        Some("echo two")
+    );
+    assert_eq!(editor.history(), ["bc", "echo two"]);
+    assert_eq!(feed(&mut editor, "junk\x03").as_deref(), Some(""));
+
+    // a single command completes with a space, paths up to their slash
+    assert_eq!(feed(&mut editor, "ech\thi\n").as_deref(), Some("echo hi"));
+    assert_eq!(
+        feed(&mut editor, "ls /pro\tse\t\n").as_deref(),
+        Some("ls /proc/self ")
+    );
+    // listing the choices shows the prompt again, the cursor going back after it
+    feed(&mut editor, "ls /");
+    let mut listed = String::new();
+    editor.feed(b'\t', &mut listed);
+    assert!(listed.ends_with("\n\x1b7\x1b8ls /\x1b8ls /"));
+    assert_eq!(editor.cursor(), 4);
+    feed(&mut editor, "\x03");
+
+    // the cursor is placed from where the prompt ended, whatever was written since
+    let mut shown = String::new();
+    editor.start("$ ", &mut shown);
+    for byte in b"ab\x1b[D" {
+        editor.feed(*byte, &mut shown);
+    }
+    assert!(shown.starts_with("$ \x1b7") && shown.ends_with("\x1b8a"));
+    editor.feed(0x03, &mut shown);
+
+    // on the screen, it stays on the line as it wraps and scrolls
+    let mut writer = test_writer();
+    let (columns, rows) = writer.size();
+    writer.write_str(&"\n".repeat(rows)).unwrap();
+    editor.start("$ ", &mut writer);
+    for _ in 0..columns {
+        editor.feed(b'x', &mut writer);
+    }
+    writer.write_str("\x1b[Hlog").unwrap();
+    for byte in b"\x1b[D\x1b[D\x1b[D" {
+        editor.feed(*byte, &mut writer);
+    }
+    assert_eq!(writer.cursor(), (columns - 1, rows - 2));
+    assert_eq!(writer.cell(0, rows - 2).ch, '$');
+    assert_eq!(writer.cell(1, rows - 1).ch, 'x');
+    editor.feed(0x03, &mut writer);
+}
+
+/// A console of its own on a small framebuffer, to check what it shows.
+fn test_writer() -> FrameBufferWriter {
+    let (width, height) = (320, 100);
+    let info = FrameBufferInfo {
+        byte_len: width * height * 4,
+        width,
+        height,
+        pixel_format: PixelFormat::Rgb,
+        bytes_per_pixel: 4,
+        stride: width,
+    };
+    FrameBufferWriter::detached(info)
+}
+
+/// The text of a row of `writer`, without the blanks after it.
+fn screen_row(writer: &FrameBufferWriter, row: usize) -> alloc::string::String {
+    let (columns, _) = writer.size();
+    let text: alloc::string::String = (0..columns)
+        .map(|column| writer.cell(column, row).ch)
+        .collect();
+    text.trim_end().into()
+}
+
+pub fn monitor_escape_sequences() {
+    use crate::monitor::{RgbColor, Style, BOLD, REVERSE, UNDERLINE};
+
+    let mut writer = test_writer();
+    let (columns, rows) = writer.size();
+    let (red, green) = (RgbColor::new(205, 0, 0), RgbColor::new(0, 205, 0));
+    let style = |writer: &FrameBufferWriter, column| writer.cell(column, 0).style;
+
+    // attributes, the 16 colors, the 256 color palette and truecolor
+    writer
+        .write_str("\x1b[1;4;7;31ma\x1b[22;24;27;39;42mb")
+        .unwrap();
+    writer
+        .write_str("\x1b[38;5;196;48;5;244mc\x1b[38;2;1;2;3;48;2;300;0;9md")
+        .unwrap();
+    writer.write_str("\x1b[mE\x1b[31;38;5mF").unwrap();
+    let styles = [
+        (red, Style::DEFAULT.bg, BOLD | UNDERLINE | REVERSE),
+        (Style::DEFAULT.fg, green, 0),
+        (RgbColor::new(255, 0, 0), RgbColor::new(128, 128, 128), 0),
+        (RgbColor::new(1, 2, 3), RgbColor::new(255, 0, 9), 0),
+        (Style::DEFAULT.fg, Style::DEFAULT.bg, 0),
+        (red, Style::DEFAULT.bg, 0),
+    ];
+    for (column, (fg, bg, attrs)) in styles.into_iter().enumerate() {
+        assert_eq!(style(&writer, column), Style { fg, bg, attrs });
+    }
+    assert_eq!(screen_row(&writer, 0), "abcdEF");
+
+    // missing parameters take their default, and too big ones stop at the edge
+    writer.write_str("\x1b[0m\x1b[;5H").unwrap();
+    assert_eq!(writer.cursor(), (4, 0));
+    writer.write_str("\x1b[99999;99999H").unwrap();
+    assert_eq!(writer.cursor(), (columns - 1, rows - 1));
+    writer
+        .write_str("\x1b[2;3H\x1b[A\x1b[2C\x1b[B\x1b[D")
+        .unwrap();
+    assert_eq!(writer.cursor(), (3, 1));
+    writer.write_str("\x1b[E\x1b[10G").unwrap();
+    assert_eq!(writer.cursor(), (9, 2));
+    writer.write_str("\x1b[2d").unwrap();
+    assert_eq!(writer.cursor(), (9, 1));
+
+    // the position and style are saved and restored
+    writer
+        .write_str("\x1b[2;1H\x1b[32m\x1b7\x1b[3;3H\x1b[0m\x1b8x")
+        .unwrap();
+    assert_eq!(writer.cell(0, 1).ch, 'x');
+    assert_eq!(writer.cell(0, 1).style.fg, green);
+    writer
+        .write_str("\x1b[0m\x1b[s\x1b[H\x1b[31m\x1b[uy")
+        .unwrap();
+    assert_eq!(writer.cell(1, 1).ch, 'y');
+    assert_eq!(writer.cell(1, 1).style, Style::DEFAULT);
+
+    // parameters past those kept are dropped, and unknown sequences skipped
+    write!(writer, "\x1b[H\x1b[{}1mZ\x1b[5zY", "0;".repeat(20)).unwrap();
+    assert_eq!(writer.cell(0, 0).ch, 'Z');
+    assert_eq!(writer.cell(0, 0).style, Style::DEFAULT);
+    assert_eq!(writer.cell(1, 0).ch, 'Y');
+}
+
+pub fn monitor_cells() {
+    use crate::monitor::{RgbColor, CELL_HEIGHT};
+
+    let mut writer = test_writer();
+    let (columns, rows) = writer.size();
+    let blue = RgbColor::new(0, 0, 200);
+
+    // only the cells changed are drawn again
+    writer.write_str("abcdef").unwrap();
+    assert_eq!(writer.dirty(0), 0..6);
+    writer.flush();
+    assert_eq!(writer.dirty(0), 0..0);
+    writer.write_str("\x1b[3D\x1b[P").unwrap();
+    assert_eq!(screen_row(&writer, 0), "abcef");
+    assert_eq!(writer.dirty(0), 3..columns);
+    assert_eq!(writer.dirty(1), 0..0);
+    writer.write_str("\x1b[2@").unwrap();
+    assert_eq!(screen_row(&writer, 0), "abc  ef");
+    writer.write_str("\x1b[1;2HX\x1b[K").unwrap();
+    assert_eq!(screen_row(&writer, 0), "aX");
+    writer.write_str("\x1b[G\x1b[X").unwrap();
+    assert_eq!(screen_row(&writer, 0), " X");
+    writer.flush();
+
+    // erasing keeps the background color
+    writer
+        .write_str("\x1b[48;2;0;0;200m\x1b[1K\x1b[0m")
+        .unwrap();
+    assert_eq!(writer.cell(0, 0).style.bg, blue);
+    assert_eq!(writer.cell(1, 0).ch, 'X');
+    writer.write_str("\x1b[H\x1b[2J").unwrap();
+    assert_eq!(writer.dirty(0), 0..2);
+    assert_eq!(writer.dirty(1), 0..0);
+    writer.flush();
+    for row in 0..rows {
+        write!(writer, "\x1b[{};1H{row}", row + 1).unwrap();
+    }
+    writer.write_str("\x1b[3;5H\x1b[J").unwrap();
+    assert_eq!(screen_row(&writer, 2), "2");
+    assert!((3..rows).all(|row| screen_row(&writer, row).is_empty()));
+    writer.write_str("\x1b[1J").unwrap();
+    assert!((0..rows).all(|row| screen_row(&writer, row).is_empty()));
+
+    // inserted and deleted lines move those below
+    for row in 0..rows {
+        write!(writer, "\x1b[{};1H{row}", row + 1).unwrap();
+    }
+    writer.flush();
+    writer.write_str("\x1b[2;3H\x1b[44m\x1b[2L\x1b[0m").unwrap();
+    assert_eq!(screen_row(&writer, 0), "0");
+    assert_eq!(screen_row(&writer, 3), "1");
+    assert_ne!(writer.cell(0, 1).style.bg, RgbColor::new(0, 0, 0));
+    assert_eq!(writer.dirty(0), 0..0);
+    assert_eq!(writer.dirty(1), 0..columns);
+    writer.write_str("\x1b[M").unwrap();
+    assert_eq!(screen_row(&writer, 2), "1");
+    assert_eq!(screen_row(&writer, rows - 1), "");
+
+    // lines scrolled in are drawn in the background color
+    writer.write_str("\x1b[48;2;0;0;200m").unwrap();
+    writer.write_str(&"\n".repeat(rows)).unwrap();
+    writer.write_str("\x1b[0m").unwrap();
+    writer.flush();
+    assert_eq!(writer.cell(columns - 1, rows - 1).style.bg, blue);
+    assert_eq!(
+        writer.cell_pixel(columns - 1, rows - 1, 0, CELL_HEIGHT - 1),
+        blue
+    );
+}
+
+pub fn monitor_scrollback() {
+    use crate::monitor::Cell;
+    use alloc::string::String;
+
+    let mut writer = test_writer();
+    let (columns, rows) = writer.size();
+    writer.write_colored_str("\x1b[1;31mred\x1b[0m plain \x1b[48;5;21mbg\x1b[38;2;1;2;3m rgb  ");
+    writer.write_colored_str("\x1b[0m");
+    let first: Vec<Cell> = (0..columns).map(|column| writer.cell(column, 0)).collect();
+    writer.write_colored_str("\r\nsecond");
+    writer.write_colored_str("\n".repeat(rows));
+
+    // lines come back from the scrollback as they were, without the blanks after them
+    let line = writer.scrollback_line(2).unwrap();
+    assert_eq!(line.len(), "red plain bg rgb  ".len());
+    assert_eq!(line[..], first[..line.len()]);
+    assert!(first[line.len()..].iter().all(|cell| *cell == Cell::BLANK));
+    let second: String = writer
+        .scrollback_line(1)
+        .unwrap()
+        .iter()
+        .map(|cell| cell.ch)
+        .collect();
+    assert_eq!(second, "second");
+    assert!(writer.scrollback_line(3).is_none());
+
+    // until erased along with the screen
+    writer.write_colored_str("\x1b[3J");
+    assert!(writer.scrollback_line(1).is_none());
+}
+
+pub fn monitor_cursor() {
+    use crate::monitor::{RgbColor, CELL_HEIGHT, CELL_WIDTH};
+
+    let (white, black) = (RgbColor::new(255, 255, 255), RgbColor::new(0, 0, 0));
+    let mut writer = test_writer();
+    writer.set_cursor(3, 2);
+    assert_eq!(writer.cursor(), (3, 2));
+    // a block cursor shows its cell in reverse
+    assert_eq!(writer.cell_pixel(3, 2, 0, 0), white);
+    assert_eq!(
+        writer.cell_pixel(3, 2, CELL_WIDTH - 1, CELL_HEIGHT - 1),
+        white
+    );
+    assert_eq!(writer.cell_pixel(2, 2, 0, 0), black);
+
+    // blinking hides it, then shows it again
+    writer.blink();
+    assert_eq!(writer.cell_pixel(3, 2, 0, 0), black);
+    writer.blink();
+    assert_eq!(writer.cell_pixel(3, 2, 0, 0), white);
+
+    // a steady underline, which blinking leaves shown
+    writer.write_colored_str("\x1b[4 q");
+    assert_eq!(writer.cell_pixel(3, 2, 0, 0), black);
+    assert_eq!(
+        writer.cell_pixel(3, 2, CELL_WIDTH - 1, CELL_HEIGHT - 1),
+        white
+    );
+    writer.blink();
+    assert_eq!(writer.cell_pixel(3, 2, 0, CELL_HEIGHT - 1), white);
+
+    // a blinking bar
+    writer.write_colored_str("\x1b[5 q");
+    assert_eq!(writer.cell_pixel(3, 2, 0, 0), white);
+    assert_eq!(
+        writer.cell_pixel(3, 2, CELL_WIDTH - 1, CELL_HEIGHT - 1),
+        black
+    );
+    writer.blink();
+    assert_eq!(writer.cell_pixel(3, 2, 0, 0), black);
+
+    // moving the cursor draws the cell it leaves again
+    writer.set_cursor(0, 0);
+    assert_eq!(writer.cell_pixel(0, 0, 0, 0), white);
+    writer.write_colored_str("\x1b[?25l");
+    assert_eq!(writer.cell_pixel(0, 0, 0, 0), black);
+    writer.write_colored_str("\x1b[?25h\x1b[2 q");
+    assert_eq!(writer.cell_pixel(0, 0, CELL_WIDTH - 1, 0), white);
+    assert!(
+        (1..CELL_HEIGHT).all(|y| (0..CELL_WIDTH).all(|x| writer.cell_pixel(3, 2, x, y) == black))
+    );
+}
+
+/// Runs `line` with the input of `io` and its output going to a file, returning its exit status
+/// and what it wrote.
+fn run_shell(io: &crate::shell::Io, line: &str) -> (i32, alloc::string::String) {
+    use crate::{
+        fd::FileRef,
+        shell::{self, Io},
+        vfs::{self, O_RDWR},
+    };
+
+    vfs::write_file("/shell-out", b"").unwrap();
+    let out: FileRef = vfs::open("/shell-out", O_RDWR, 0).unwrap();
+    let io = Io {
+        stdout: out.clone(),
+        stderr: out,
+        ..io.clone()
+    };
+    let status = shell::run_line(line, &io);
+    let out = vfs::read_file("/shell-out").unwrap();
+    vfs::unlink("/shell-out").unwrap();
+    (status, alloc::string::String::from_utf8(out).unwrap())
+}
+
+pub fn shell_commands() {
+    use crate::{
+        shell::{self, Io},
+        vfs,
+    };
+
+    let io = Io::console();
+    let run = |line: &str| run_shell(&io, line);
+
+    assert_eq!(run("  echo  hello   world "), (0, "hello world\n".into()));
+    assert_eq!(run(""), (0, "".into()));
+    let (status, out) = run("frobnicate now");
+    assert_eq!(status, shell::NOT_FOUND);
+    assert!(out.starts_with("frobnicate"));
+
+    vfs::mkdir("/shell-dir", 0o755).unwrap();
+    vfs::mkdir("/shell-dir/sub", 0o755).unwrap();
+    vfs::write_file("/shell-dir/file", b"content").unwrap();
+    assert_eq!(run("ls /shell-dir"), (0, "file\nsub/\n".into()));
+    assert_eq!(run("cat /shell-dir/file"), (0, "content".into()));
+    assert_eq!(run("cat /shell-dir/missing").0, 1);
+    assert_eq!(run("cd /shell-dir/sub").0, 0);
+    assert_eq!(run("pwd"), (0, "/shell-dir/sub\n".into()));
+    assert_eq!(run("cd").0, 0);
+    assert!(run("help").1.starts_with("cat"));
+
+    vfs::unlink("/shell-dir/file").unwrap();
+    vfs::rmdir("/shell-dir/sub").unwrap();
+    vfs::rmdir("/shell-dir").unwrap();
+}
+
+pub fn script_parse() {
+    use crate::script::{self, Connector, ParseError, Part, RedirectKind, Word};
+
+    let literal = |text: &str| Word(alloc::vec![Part::Literal(text.into())]);
+
+    let list = script::parse("a 'b c'\"$X\"d ${Y}2 # comment\n\n x=1 b").unwrap();
+    assert_eq!(list.len(), 2);
+    let command = &list[0].first.0[0];
+    assert_eq!(command.words[0], literal("a"));
+    assert_eq!(
+        command.words[1].0,
+        [
+            Part::Literal("b c".into()),
+            Part::Variable("X".into()),
+            Part::Literal("d".into())
+        ]
+    );
+    assert_eq!(
+        command.words[2].0,
+        [Part::Variable("Y".into()), Part::Literal("2".into())]
+    );
+    let command = &list[1].first.0[0];
+    assert_eq!(command.assignments, [("x".into(), literal("1"))]);
+    assert_eq!(command.words, [literal("b")]);
+
+    let list = script::parse("a | b 2>err >> out && c ||\n d < in; e").unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].first.0.len(), 2);
+    let redirects = &list[0].first.0[1].redirects;
+    assert_eq!(
+        (redirects[0].fd, redirects[0].kind),
+        (2, RedirectKind::Write)
+    );
+    assert_eq!(redirects[0].target, literal("err"));
+    assert_eq!(
+        (redirects[1].fd, redirects[1].kind),
+        (1, RedirectKind::Append)
+    );
+    let connectors: Vec<_> = list[0]
+        .rest
+        .iter()
+        .map(|(connector, _)| *connector)
+        .collect();
+    assert_eq!(connectors, [Connector::And, Connector::Or]);
+    assert_eq!(
+        list[0].rest[1].1 .0[0].redirects[0].kind,
+        RedirectKind::Read
+    );
+
+    assert_eq!(
+        script::parse("echo 'open"),
+        Err(ParseError::UnterminatedQuote)
+    );
+    assert_eq!(
+        script::parse("echo ${X"),
+        Err(ParseError::UnterminatedVariable)
+    );
+    assert_eq!(script::parse("| ls"), Err(ParseError::MissingCommand));
+    assert_eq!(script::parse("ls &&"), Err(ParseError::MissingCommand));
+    assert_eq!(script::parse("ls >"), Err(ParseError::MissingTarget));
+    assert_eq!(script::parse("ls &"), Err(ParseError::Background));
+}
+
+pub fn shell_scripts() {
+    use crate::{
+        shell::{self, Io},
+        vfs,
+    };
+
+    let io = Io::console();
+    let run = |line: &str| run_shell(&io, line);
+
+    assert_eq!(
+        run("GREETING='hello  there'; echo \"$GREETING\"!").1,
+        "hello  there!\n"
+    );
+    assert_eq!(io.var("GREETING").as_deref(), Some("hello  there"));
+    // assignments before a command only last as long as it
+    assert!(run("GREETING=hi env").1.contains("GREETING=hi\n"));
+    assert_eq!(io.var("GREETING").as_deref(), Some("hello  there"));
+    run("GREETING=a GREETING=b true");
+    assert_eq!(io.var("GREETING").as_deref(), Some("hello  there"));
+    // other sessions don't see them, nor does this one those of earlier pipeline stages
+    assert_eq!(run_shell(&Io::console(), "echo [$GREETING]").1, "[]\n");
+    assert_eq!(run("STAGE=first | true; echo [$STAGE]").1, "[]\n");
+    assert_eq!(run("true | STAGE=last; echo [$STAGE]").1, "[last]\n");
+    assert_eq!(run("unset GREETING; echo [$GREETING]").1, "[]\n");
+    assert_eq!(run("false; echo $?").1, "1\n");
+    assert_eq!(run("echo a\\ b 'c d' \"e\\\"f\"").1, "a b c d e\"f\n");
+
+    assert_eq!(run("true && echo yes || echo no").1, "yes\n");
+    assert_eq!(run("false && echo yes || echo no").1, "no\n");
+    assert_eq!(run("false || false").0, 1);
+
+    assert_eq!(run("echo piped | cat | cat"), (0, "piped\n".into()));
+    assert_eq!(run("echo one > /shell-file; echo two >> /shell-file").1, "");
+    assert_eq!(run("cat < /shell-file").1, "one\ntwo\n");
+    assert_eq!(run("cat /shell-missing 2> /shell-file").1, "");
+    assert!(vfs::read_file("/shell-file").unwrap().starts_with(b"cat"));
+    assert_eq!(run("cat < /shell-missing").0, 1);
+
+    vfs::write_file(
+        "/shell-script",
+        b"# greets its arguments\necho $# $0\nname=$1\necho \"$name\" &&\n  echo ${2}\nfalse\n",
+    )
+    .unwrap();
+    assert_eq!(
+        run("sh /shell-script first second"),
+        (1, "2 /shell-script\nfirst\nsecond\n".into())
+    );
+    assert_eq!(run("/shell-script only").1, "1 /shell-script\nonly\n\n");
+    assert_eq!(io.var("1"), None);
+
+    // a script running itself gives up instead of running out of stack
+    vfs::write_file("/shell-loop", b"/shell-loop\n").unwrap();
+    let (status, out) = run("/shell-loop");
+    assert_eq!(status, 1);
+    assert!(out.ends_with("nested too deeply\n"));
+    vfs::unlink("/shell-loop").unwrap();
+
+    assert_eq!(run("echo 'open").0, shell::SYNTAX_ERROR);
+    assert_eq!(run("echo a |").0, shell::SYNTAX_ERROR);
+
+    vfs::unlink("/shell-file").unwrap();
+    vfs::unlink("/shell-script").unwrap();
+}
+
+pub fn shell_programs() {
+    use crate::{
+        shell::{self, Io},
+        vfs,
+    };
+
+    let io = Io::console();
+    let run = |line: &str| run_shell(&io, line);
+
+    vfs::write_file("/shell-program", &build_elf(WRITE_OK)).unwrap();
+    assert_eq!(run("/shell-program; echo $?"), (0, "ok\n3\n".into()));
+    assert_eq!(run("/shell-program | cat"), (0, "ok\n".into()));
+    assert_eq!(run("/shell-program > /shell-file").1, "");
+    assert_eq!(vfs::read_file("/shell-file").unwrap(), b"ok\n");
+
+    vfs::write_file("/shell-program", b"\x7fELF").unwrap();
+    let (status, out) = run("/shell-program");
+    assert_eq!(status, shell::NOT_EXECUTABLE);
+    assert!(out.starts_with("sh: /shell-program:"));
+
+    vfs::unlink("/shell-file").unwrap();
+    vfs::unlink("/shell-program").unwrap();
+}
@@ -0,0 +1,162 @@
+use core::{arch::global_asm, sync::atomic::Ordering};
+
+use alloc::vec;
+
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use crate::{gdt, task};
+
+/// Level 4 entries below this address are reserved to user programs. The bootloader is told to
+/// place every kernel mapping above it.
+pub const USER_END: u64 = 0x0000_4000_0000_0000;
+
+extern "C" {
+    fn user_enter_asm(entry: u64, stack: u64, cs: u64, ss: u64) -> !;
+    fn user_run_asm(entry: u64, stack: u64, cs: u64, ss: u64, saved_rsp: *mut usize) -> u64;
+    fn user_return_asm(saved_rsp: usize, code: u64) -> !;
+}
+
+global_asm!(
+    ".global user_enter_asm",
+    "user_enter_asm:",
+    "push rcx",     // ss
+    "push rsi",     // rsp
+    "push 0x202",   // rflags, with interrupts enabled
+    "push rdx",     // cs
+    "push rdi",     // rip
+    "xor eax, eax", // don't leak kernel values to user mode
+    "xor ebx, ebx",
+    "xor ecx, ecx",
+    "xor edx, edx",
+    "xor esi, esi",
+    "xor edi, edi",
+    "xor ebp, ebp",
+    "xor r8d, r8d",
+    "xor r9d, r9d",
+    "xor r10d, r10d",
+    "xor r11d, r11d",
+    "xor r12d, r12d",
+    "xor r13d, r13d",
+    "xor r14d, r14d",
+    "xor r15d, r15d",
+    "swapgs", // the kernel GS base waits in KERNEL_GS_BASE until the next trap
+    "iretq",
+    "",
+    ".global user_run_asm",
+    "user_run_asm:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [r8], rsp",
+    "jmp user_enter_asm",
+    "",
+    ".global user_return_asm",
+    "user_return_asm:",
+    "mov rsp, rdi",
+    "mov rax, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+);
+
+fn user_selectors() -> (u64, u64) {
+    let selectors = gdt::selectors();
+    (
+        selectors.user_code().0 as u64,
+        selectors.user_data().0 as u64,
+    )
+}
+
+/// Jumps to `entry` in ring 3, running on the user stack `stack`.
+pub unsafe fn enter(entry: VirtAddr, stack: VirtAddr) -> ! {
+    let (cs, ss) = user_selectors();
+    user_enter_asm(entry.as_u64(), stack.as_u64(), cs, ss)
+}
+
+/// Runs the code at `entry` in ring 3 until it exits, returning its exit code.
+///
+/// Both `entry` and `stack` must already be mapped as user accessible.
+pub unsafe fn run(entry: VirtAddr, stack: VirtAddr) -> u64 {
+    let (cs, ss) = user_selectors();
+    let thread = task::current();
+    let were_enabled = interrupts::are_enabled();
+
+    // the frames of this function stay live on the thread stack, so traps from the program get
+    // a stack of their own
+    let trap_stack = vec![0u8; task::KERNEL_STACK_SIZE].into_boxed_slice();
+    let previous_stack = thread.kernel_stack_top();
+    task::set_kernel_stack(VirtAddr::from_ptr(trap_stack.as_ptr_range().end).align_down(16u64));
+
+    let saved = thread.user_return.as_ptr();
+    let code = user_run_asm(entry.as_u64(), stack.as_u64(), cs, ss, saved);
+    thread.user_return.store(0, Ordering::Relaxed);
+    task::set_kernel_stack(previous_stack);
+    drop(trap_stack);
+    // we come back from an interrupt gate, which disabled interrupts
+    if were_enabled {
+        interrupts::enable();
+    }
+    code
+}
+
+/// Whether the running thread is inside a [`run`] call.
+pub fn is_running() -> bool {
+    task::current().user_return.load(Ordering::Relaxed) != 0
+}
+
+/// Abandons the current user program and returns `code` from the pending [`run`].
+pub unsafe fn return_to_kernel(code: u64) -> ! {
+    let rsp = task::current().user_return.load(Ordering::Relaxed);
+    assert_ne!(rsp, 0, "no user program to return from");
+    user_return_asm(rsp, code)
+}
+
+pub fn map_user_page(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    page: Page,
+    flags: PageTableFlags,
+) -> Result<PhysFrame, MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    if let Err(err) = map_user_frame(mapper, frame_allocator, page, frame, flags) {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        return Err(err);
+    }
+    Ok(frame)
+}
+
+/// Maps `page` to an existing `frame` for ring 3.
+pub fn map_user_frame(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    assert!(page.start_address().as_u64() < USER_END);
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let parent_flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    unsafe {
+        mapper
+            .map_to_with_table_flags(page, frame, flags, parent_flags, frame_allocator)?
+            .flush()
+    }
+    Ok(())
+}
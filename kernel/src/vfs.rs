@@ -0,0 +1,746 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::any::Any;
+
+use crate::{fd::FileLike, info, okay, process, ramfs::RamFs, syscall::Errno};
+
+pub const PATH_MAX: usize = 4096;
+pub const NAME_MAX: usize = 255;
+/// Symbolic links followed while resolving a single path.
+pub const MAX_SYMLINKS: usize = 8;
+
+pub const O_RDONLY: u32 = 0o0;
+pub const O_WRONLY: u32 = 0o1;
+pub const O_RDWR: u32 = 0o2;
+pub const O_ACCMODE: u32 = 0o3;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+pub const O_DIRECTORY: u32 = 0o200000;
+pub const O_NOFOLLOW: u32 = 0o400000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotDirectory,
+    IsDirectory,
+    AlreadyExists,
+    NotEmpty,
+    InvalidPath,
+    NameTooLong,
+    ReadOnly,
+    /// The inode doesn't support the operation.
+    Unsupported,
+    NoSpace,
+    Io,
+    /// Too many symbolic links, likely a loop.
+    Loop,
+    Busy,
+    /// The file wasn't opened for this kind of access.
+    BadAccess,
+    /// The operation would span two filesystems.
+    CrossDevice,
+    /// A signal arrived while waiting for a device.
+    Interrupted,
+    /// The on-disk structures of the filesystem make no sense.
+    Corrupt,
+}
+
+impl From<FsError> for Errno {
+    fn from(value: FsError) -> Self {
+        match value {
+            FsError::NotFound => Errno::NoEnt,
+            FsError::NotDirectory => Errno::NotDir,
+            FsError::IsDirectory => Errno::IsDir,
+            FsError::AlreadyExists => Errno::Exist,
+            FsError::NotEmpty => Errno::NotEmpty,
+            FsError::InvalidPath | FsError::Unsupported => Errno::Inval,
+            FsError::NameTooLong => Errno::NameTooLong,
+            FsError::ReadOnly => Errno::RoFs,
+            FsError::NoSpace => Errno::NoSpc,
+            FsError::Io | FsError::Corrupt => Errno::Io,
+            FsError::Loop => Errno::Loop,
+            FsError::Busy => Errno::Busy,
+            FsError::BadAccess => Errno::BadFd,
+            FsError::CrossDevice => Errno::XDev,
+            FsError::Interrupted => Errno::Intr,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Pipe,
+}
+
+impl FileType {
+    /// The `S_IFMT` bits of `st_mode`.
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            FileType::Pipe => 0o010000,
+            FileType::CharDevice => 0o020000,
+            FileType::Directory => 0o040000,
+            FileType::BlockDevice => 0o060000,
+            FileType::File => 0o100000,
+            FileType::Symlink => 0o120000,
+        }
+    }
+
+    /// The `d_type` of directory entries.
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            FileType::Pipe => 1,
+            FileType::CharDevice => 2,
+            FileType::Directory => 4,
+            FileType::BlockDevice => 6,
+            FileType::File => 8,
+            FileType::Symlink => 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub ino: u64,
+    pub kind: FileType,
+    pub size: u64,
+    /// Permission bits, as in `0o755`.
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Seconds since boot, as there is no real time clock yet.
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Directory
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+pub type InodeRef = Arc<dyn Inode>;
+
+/// A file, directory or link of some filesystem. Operations an inode doesn't support fail with
+/// [`FsError::Unsupported`], or [`FsError::NotDirectory`] for directory ones.
+pub trait Inode: Any + Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(self.not_file())
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(self.not_file())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(self.not_file())
+    }
+
+    /// Finds the entry `name` of a directory, never `.` or `..`.
+    fn lookup(&self, _name: &str) -> Result<InodeRef, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Creates an empty file or directory `name` in a directory.
+    fn create(&self, _name: &str, _kind: FileType, _mode: u16) -> Result<InodeRef, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<InodeRef, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Removes the entry `name`, which must be an empty directory if it is one.
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Moves the entry `old_name` to `new_name` in `new_dir`, replacing what is there unless it
+    /// is a directory that isn't empty. Fails with [`FsError::CrossDevice`] when `new_dir` is
+    /// of another filesystem.
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> Result<(), FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        Err(FsError::InvalidPath)
+    }
+
+    fn set_mode(&self, _mode: u16) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn not_file(&self) -> FsError {
+        if self.metadata().is_dir() {
+            FsError::IsDirectory
+        } else {
+            FsError::Unsupported
+        }
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &str;
+    fn root(&self) -> InodeRef;
+
+    /// Writes back whatever is only in memory, for filesystems backed by a device.
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+/// An inode reached through a path, remembering how to go back up for `..`.
+pub struct Dentry {
+    name: String,
+    path: String,
+    inode: InodeRef,
+    parent: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+    fn child(parent: &Arc<Dentry>, name: &str, inode: InodeRef) -> Arc<Dentry> {
+        let path = parent.child_path(name);
+        // a mounted filesystem hides the directory it is mounted on
+        let inode = mounted_root(&path).unwrap_or(inode);
+        Arc::new(Dentry {
+            name: name.to_string(),
+            path,
+            inode,
+            parent: Some(parent.clone()),
+        })
+    }
+
+    fn child_path(&self, name: &str) -> String {
+        match self.path.as_str() {
+            "/" => format!("/{name}"),
+            path => format!("{path}/{name}"),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The absolute path without symbolic links, `.` or `..`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn inode(&self) -> &InodeRef {
+        &self.inode
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref()
+    }
+}
+
+/// Filesystems by the absolute path they are mounted on.
+static MOUNTS: spin::Mutex<BTreeMap<String, Arc<dyn FileSystem>>> =
+    spin::Mutex::new(BTreeMap::new());
+
+/// Mounts an empty ramfs as the root filesystem.
+pub fn init() {
+    info!("mounting root filesystem");
+    mount("/", RamFs::new()).expect("root already mounted");
+    okay!("mounted root filesystem");
+}
+
+fn mounted_root(path: &str) -> Option<InodeRef> {
+    MOUNTS.lock().get(path).map(|fs| fs.root())
+}
+
+/// Mounts `fs` on the directory `path`. The first mount must be on `/`.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = if MOUNTS.lock().is_empty() {
+        if path != "/" {
+            return Err(FsError::NotFound);
+        }
+        path.to_string()
+    } else {
+        let dentry = lookup(path)?;
+        if !dentry.inode.metadata().is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        dentry.path.clone()
+    };
+    let mut mounts = MOUNTS.lock();
+    if mounts.contains_key(&path) {
+        return Err(FsError::Busy);
+    }
+    info!("mounted {} on {path}", fs.name());
+    mounts.insert(path, fs);
+    Ok(())
+}
+
+/// Mounts `fs` on `path`, creating the directory with `mode` first if it doesn't exist yet.
+pub fn mount_at(path: &str, mode: u16, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    match mkdir(path, mode) {
+        Ok(()) | Err(FsError::AlreadyExists) => mount(path, fs),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn unmount(path: &str) -> Result<(), FsError> {
+    let path = lookup(path)?.path.clone();
+    let mut mounts = MOUNTS.lock();
+    // other mounts below this one would become unreachable
+    let prefix = if path == "/" {
+        path.clone()
+    } else {
+        format!("{path}/")
+    };
+    if mounts.keys().any(|p| p.starts_with(&prefix) && *p != path) {
+        return Err(FsError::Busy);
+    }
+    let fs = mounts.remove(&path).ok_or(FsError::InvalidPath)?;
+    drop(mounts);
+    fs.sync()
+}
+
+/// Syncs every mounted filesystem.
+pub fn sync() -> Result<(), FsError> {
+    let filesystems: Vec<_> = MOUNTS.lock().values().cloned().collect();
+    for fs in filesystems {
+        fs.sync()?;
+    }
+    Ok(())
+}
+
+/// Mount points and the name of the filesystem mounted on each.
+pub fn mounts() -> Vec<(String, String)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|(path, fs)| (path.clone(), fs.name().to_string()))
+        .collect()
+}
+
+pub fn root() -> Result<Arc<Dentry>, FsError> {
+    let inode = mounted_root("/").ok_or(FsError::NotFound)?;
+    Ok(Arc::new(Dentry {
+        name: String::new(),
+        path: "/".to_string(),
+        inode,
+        parent: None,
+    }))
+}
+
+/// Joins `path` to the working directory of the running process unless it is absolute. The
+/// result is only lexically cleaned up, see [`normalize`].
+pub fn absolute(path: &str) -> String {
+    if path.starts_with('/') {
+        normalize(path)
+    } else {
+        normalize(&format!("{}/{path}", process::current().cwd()))
+    }
+}
+
+/// Removes `.`, `..` and repeated slashes from an absolute path, without looking at the
+/// filesystem. `..` of the root is the root.
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let mut normalized = String::new();
+    for part in parts {
+        normalized.push('/');
+        normalized.push_str(part);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+fn start_of(path: &str) -> Result<Arc<Dentry>, FsError> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    if path.len() > PATH_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    if path.starts_with('/') {
+        return root();
+    }
+    // the working directory only holds resolved paths, so this doesn't recurse
+    walk(root()?, &process::current().cwd(), true, 0)
+}
+
+/// Resolves `path` from `start`, following symbolic links except in the last component unless
+/// `follow` is set. `depth` counts the links followed so far.
+fn walk(
+    start: Arc<Dentry>,
+    path: &str,
+    follow: bool,
+    depth: usize,
+) -> Result<Arc<Dentry>, FsError> {
+    let mut current = if path.starts_with('/') {
+        root()?
+    } else {
+        start
+    };
+    let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+    while let Some(name) = components.next() {
+        if !current.inode.metadata().is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        match name {
+            "." => continue,
+            ".." => {
+                current = current.parent.clone().unwrap_or(current);
+                continue;
+            }
+            _ => {}
+        }
+        if name.len() > NAME_MAX {
+            return Err(FsError::NameTooLong);
+        }
+        let child = Dentry::child(&current, name, current.inode.lookup(name)?);
+        let last = components.peek().is_none();
+        if child.inode.metadata().kind == FileType::Symlink && (!last || follow) {
+            if depth >= MAX_SYMLINKS {
+                return Err(FsError::Loop);
+            }
+            let target = child.inode.read_link()?;
+            current = walk(current, &target, true, depth + 1)?;
+        } else {
+            current = child;
+        }
+    }
+    Ok(current)
+}
+
+/// Resolves `path`, following every symbolic link.
+pub fn lookup(path: &str) -> Result<Arc<Dentry>, FsError> {
+    walk(start_of(path)?, path, true, 0)
+}
+
+/// Resolves `path` without following a symbolic link in the last component.
+pub fn lookup_link(path: &str) -> Result<Arc<Dentry>, FsError> {
+    walk(start_of(path)?, path, false, 0)
+}
+
+/// Resolves the directory holding the last component of `path`, returning it with that name.
+fn lookup_parent(path: &str) -> Result<(Arc<Dentry>, String), FsError> {
+    let trimmed = path.trim_end_matches('/');
+    let (dir, name) = match trimmed.rfind('/') {
+        Some(0) => ("/", &trimmed[1..]),
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None => (".", trimmed),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidPath);
+    }
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    let parent = lookup(dir)?;
+    if !parent.inode.metadata().is_dir() {
+        return Err(FsError::NotDirectory);
+    }
+    Ok((parent, name.to_string()))
+}
+
+pub fn stat(path: &str) -> Result<Metadata, FsError> {
+    Ok(lookup(path)?.inode.metadata())
+}
+
+pub fn lstat(path: &str) -> Result<Metadata, FsError> {
+    Ok(lookup_link(path)?.inode.metadata())
+}
+
+pub fn mkdir(path: &str, mode: u16) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    parent.inode.create(&name, FileType::Directory, mode)?;
+    Ok(())
+}
+
+/// Removes the file or link `path`.
+pub fn unlink(path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    let dentry = Dentry::child(&parent, &name, parent.inode.lookup(&name)?);
+    if dentry.inode.metadata().is_dir() {
+        return Err(FsError::IsDirectory);
+    }
+    parent.inode.unlink(&name)
+}
+
+/// Removes the empty directory `path`.
+pub fn rmdir(path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    let dentry = Dentry::child(&parent, &name, parent.inode.lookup(&name)?);
+    if !dentry.inode.metadata().is_dir() {
+        return Err(FsError::NotDirectory);
+    }
+    if MOUNTS.lock().contains_key(&dentry.path) {
+        return Err(FsError::Busy);
+    }
+    parent.inode.unlink(&name)
+}
+
+/// Moves `old` to `new`, within a single filesystem.
+pub fn rename(old: &str, new: &str) -> Result<(), FsError> {
+    let (old_parent, old_name) = lookup_parent(old)?;
+    let (new_parent, new_name) = lookup_parent(new)?;
+    let moved = old_parent.child_path(&old_name);
+    let target = new_parent.child_path(&new_name);
+    {
+        let mounts = MOUNTS.lock();
+        if mounts.contains_key(&moved) || mounts.contains_key(&target) {
+            return Err(FsError::Busy);
+        }
+        // the inodes of mount points are those of the mounted filesystem
+        let mount_of = |path: &str| {
+            mounts
+                .keys()
+                .filter(|p| *p == "/" || path == *p || path.starts_with(&format!("{p}/")))
+                .max_by_key(|p| p.len())
+                .cloned()
+        };
+        if mount_of(&moved) != mount_of(&target) {
+            return Err(FsError::CrossDevice);
+        }
+    }
+    // a directory can't go below itself
+    if target.starts_with(&format!("{moved}/")) {
+        return Err(FsError::InvalidPath);
+    }
+    old_parent
+        .inode
+        .rename(&old_name, &new_parent.inode, &new_name)
+}
+
+/// Changes the permission bits of `path`.
+pub fn chmod(path: &str, mode: u16) -> Result<(), FsError> {
+    lookup(path)?.inode.set_mode(mode)
+}
+
+pub fn symlink(target: &str, path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    parent.inode.symlink(&name, target)?;
+    Ok(())
+}
+
+pub fn read_link(path: &str) -> Result<String, FsError> {
+    lookup_link(path)?.inode.read_link()
+}
+
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    lookup(path)?.inode.readdir()
+}
+
+/// Reads the whole file `path`.
+pub fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
+    let file = open(path, O_RDONLY, 0)?;
+    let mut data = Vec::new();
+    let mut buf = [0; 512];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            return Ok(data);
+        }
+        data.extend_from_slice(&buf[..read]);
+    }
+}
+
+/// Replaces the content of `path` with `data`, creating it if needed.
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), FsError> {
+    let file = open(path, O_WRONLY | O_CREAT | O_TRUNC, 0o644)?;
+    let mut done = 0;
+    while done < data.len() {
+        done += file.write(&data[done..])?;
+    }
+    Ok(())
+}
+
+/// Changes the working directory of the running process.
+pub fn chdir(path: &str) -> Result<(), FsError> {
+    let dentry = lookup(path)?;
+    if !dentry.inode.metadata().is_dir() {
+        return Err(FsError::NotDirectory);
+    }
+    process::current().set_cwd(dentry.path.clone());
+    Ok(())
+}
+
+pub fn open(path: &str, flags: u32, mode: u16) -> Result<Arc<File>, FsError> {
+    let found = if flags & O_NOFOLLOW != 0 {
+        lookup_link(path)
+    } else {
+        lookup(path)
+    };
+    let dentry = match found {
+        Ok(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(FsError::AlreadyExists),
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if flags & O_CREAT != 0 => {
+            let (parent, name) = lookup_parent(path)?;
+            let inode = parent.inode.create(&name, FileType::File, mode)?;
+            Dentry::child(&parent, &name, inode)
+        }
+        Err(err) => return Err(err),
+    };
+
+    let metadata = dentry.inode.metadata();
+    let access = flags & O_ACCMODE;
+    if metadata.kind == FileType::Symlink {
+        return Err(FsError::Loop);
+    }
+    if metadata.is_dir() && access != O_RDONLY {
+        return Err(FsError::IsDirectory);
+    }
+    if !metadata.is_dir() && flags & O_DIRECTORY != 0 {
+        return Err(FsError::NotDirectory);
+    }
+    if flags & O_TRUNC != 0 && access != O_RDONLY && metadata.kind == FileType::File {
+        dentry.inode.truncate(0)?;
+    }
+    Ok(Arc::new(File {
+        dentry,
+        flags,
+        offset: spin::Mutex::new(0),
+    }))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An open file, with its own offset shared by every descriptor duplicated from it.
+pub struct File {
+    dentry: Arc<Dentry>,
+    flags: u32,
+    /// Byte offset, or index of the next entry for directories.
+    offset: spin::Mutex<u64>,
+}
+
+impl File {
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    pub fn inode(&self) -> &InodeRef {
+        &self.dentry.inode
+    }
+
+    fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    fn writable(&self) -> bool {
+        matches!(self.flags & O_ACCMODE, O_WRONLY | O_RDWR)
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.readable() {
+            return Err(FsError::BadAccess);
+        }
+        let mut offset = self.offset.lock();
+        let read = self.inode().read_at(*offset, buf)?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.writable() {
+            return Err(FsError::BadAccess);
+        }
+        let mut offset = self.offset.lock();
+        if self.flags & O_APPEND != 0 {
+            *offset = self.inode().metadata().size;
+        }
+        let written = self.inode().write_at(*offset, buf)?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> Result<u64, FsError> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(to) => Some(to),
+            SeekFrom::Current(by) => offset.checked_add_signed(by),
+            SeekFrom::End(by) => self.inode().metadata().size.checked_add_signed(by),
+        };
+        *offset = new.ok_or(FsError::InvalidPath)?;
+        Ok(*offset)
+    }
+
+    pub fn stat(&self) -> Metadata {
+        self.inode().metadata()
+    }
+
+    /// Passes the directory entries from the current offset to `f` with their index, advancing
+    /// past every entry it accepts. `f` returns `false` to stop, which is then returned as `true`.
+    pub fn read_entries(
+        &self,
+        mut f: impl FnMut(usize, &DirEntry) -> bool,
+    ) -> Result<bool, FsError> {
+        let entries = self.inode().readdir()?;
+        let mut offset = self.offset.lock();
+        for (index, entry) in entries.iter().enumerate().skip(*offset as usize) {
+            if !f(index, entry) {
+                return Ok(true);
+            }
+            *offset += 1;
+        }
+        Ok(false)
+    }
+}
+
+impl FileLike for File {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(File::read(self, buf)?)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(File::write(self, buf)?)
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Result<u64, Errno> {
+        Ok(File::seek(self, pos)?)
+    }
+
+    fn stat(&self) -> Result<Metadata, Errno> {
+        Ok(File::stat(self))
+    }
+
+    fn as_vfs_file(&self) -> Option<&File> {
+        Some(self)
+    }
+}
@@ -3,11 +3,14 @@
 
 fn main() {
     let bios_path = env!("BIOS_PATH");
+    let data_path = env!("DATA_PATH");
 
     let mut cmd = std::process::Command::new("qemu-system-x86_64");
 
     cmd.arg("-drive")
-        .arg(format!("format=raw,file={bios_path}"))
+        .arg(format!("format=raw,file={bios_path},index=0"))
+        .arg("-drive")
+        .arg(format!("format=raw,file={data_path},index=1"))
         .arg("-display")
         .arg("gtk,zoom-to-fit=on");
     let mut child = cmd.spawn().unwrap();
@@ -17,11 +20,14 @@ fn main() {
 #[cfg(test)]
 fn main_test(_: &[&()]) {
     let bios_path = env!("BIOS_PATH");
+    let data_path = env!("DATA_PATH");
 
     let mut cmd = std::process::Command::new("qemu-system-x86_64");
 
     cmd.arg("-drive")
-        .arg(format!("format=raw,file={bios_path}"))
+        .arg(format!("format=raw,file={bios_path},index=0"))
+        .arg("-drive")
+        .arg(format!("format=raw,file={data_path},index=1"))
         .arg("-display")
         .arg("gtk,zoom-to-fit=on");
     let mut child = cmd.spawn().unwrap();